@@ -0,0 +1,19 @@
+//! Start an instance with the asynchronous API and query it with `psql`.
+//!
+//! ```text
+//! cargo run --example async --features tokio-process
+//! ```
+
+use tmp_postgrust::new_default_process_async;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let process = new_default_process_async().await?;
+    println!("started instance: {}", process.connection_string);
+
+    let output = process
+        .run_pg_tool("psql", ["-XAtc", "SELECT current_database();"])
+        .await?;
+    println!("connected to database {}", output.stdout.trim());
+    Ok(())
+}
@@ -0,0 +1,32 @@
+//! Start an instance and use the `tokio-postgres` helpers of the guard.
+//!
+//! ```text
+//! cargo run --example client --features tokio-process,client
+//! ```
+
+use tmp_postgrust::new_default_process_async;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let process = new_default_process_async().await?;
+
+    let client = process.client().await?;
+    client
+        .batch_execute("CREATE TABLE items (id int PRIMARY KEY, name text);")
+        .await?;
+
+    let inserted = process
+        .with_rollback(|scope| async move {
+            scope
+                .execute("INSERT INTO items VALUES (1, 'rolled back')", &[])
+                .await
+        })
+        .await??;
+    let count: i64 = process.query_scalar("SELECT count(*) FROM items").await?;
+    println!("inserted {inserted} row, {count} left after the rollback");
+
+    process
+        .assert_query_eq("SELECT count(*) FROM items", &[&["0"]])
+        .await;
+    Ok(())
+}
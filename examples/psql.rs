@@ -0,0 +1,17 @@
+//! Start an instance and query it with `psql`, checking that the postgresql binaries are
+//! found on this machine.
+//!
+//! ```text
+//! cargo run --example psql
+//! ```
+
+use tmp_postgrust::new_default_process;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let process = new_default_process()?;
+    println!("started instance: {}", process.connection_string);
+
+    let output = process.run_pg_tool("psql", ["-XAtc", "SELECT version();"])?;
+    println!("{}", output.stdout.trim());
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use crate::sql::{quote_literal, split_records, unaligned_query_args};
+
+/// Lists the server processes besides the session running the query.
+const ACTIVITY_QUERY: &str = "
+SELECT pid, COALESCE(usename, ''), COALESCE(datname, ''), application_name,
+       COALESCE(backend_type, ''), COALESCE(state, ''), COALESCE(query, ''),
+       COALESCE(EXTRACT(EPOCH FROM now() - state_change)::text, '')
+FROM pg_stat_activity
+WHERE pid <> pg_backend_pid()
+ORDER BY pid;
+";
+
+/// Check for client connections still open when an instance is stopped, set with
+/// [`with_connection_leak_check`](crate::builder::TmpPostgrustFactoryBuilder::with_connection_leak_check).
+///
+/// Tests that forget to close their pools tend to flake on shutdown, the check points at
+/// them instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConnectionLeakCheck {
+    /// Do not check.
+    #[default]
+    Off,
+    /// Log a warning listing the open connections.
+    Warn,
+    /// Panic listing the open connections once the instance stopped, unless the thread is
+    /// already panicking.
+    Strict,
+}
+
+/// How long terminated sessions get to disconnect.
+pub(crate) const TERMINATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `backend_type` of processes serving client connections.
+const CLIENT_BACKEND: &str = "client backend";
+
+/// Server process listed in `pg_stat_activity`, as returned by
+/// [`activity`](crate::synchronous::ProcessGuard::activity).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backend {
+    /// Process id, as passed to
+    /// [`terminate_backend`](crate::synchronous::ProcessGuard::terminate_backend).
+    pub pid: u32,
+    /// Role of the session, empty for most background processes.
+    pub user: String,
+    /// Database of the session, empty for most background processes.
+    pub database: String,
+    /// `application_name` set by the client.
+    pub application_name: String,
+    /// Type of the process, e.g. `client backend` or `autovacuum launcher`.
+    pub backend_type: String,
+    /// State of the session such as `active`, `idle` or `idle in transaction`, empty for
+    /// background processes.
+    pub state: String,
+    /// Last query of the session.
+    pub query: String,
+    /// How long the session has been in its current state.
+    pub state_duration: Option<Duration>,
+}
+
+impl Backend {
+    /// Whether the process serves a client connection.
+    #[must_use]
+    pub fn is_client(&self) -> bool {
+        self.backend_type == CLIENT_BACKEND
+    }
+
+    /// Whether the session holds a transaction open without running a query, e.g. because a
+    /// test forgot to commit.
+    #[must_use]
+    pub fn is_idle_in_transaction(&self) -> bool {
+        self.state.starts_with("idle in transaction")
+    }
+}
+
+/// Arguments for `psql` to print the activity in a parseable form.
+pub(crate) fn activity_query_args() -> [&'static str; 9] {
+    unaligned_query_args(ACTIVITY_QUERY)
+}
+
+/// Parse the output of the activity query.
+pub(crate) fn parse_activity(output: &str) -> Vec<Backend> {
+    split_records(output)
+        .filter_map(|fields| {
+            let field = |index: usize| fields.get(index).map(|field| (*field).to_string());
+            Some(Backend {
+                pid: fields.first()?.parse().ok()?,
+                user: field(1)?,
+                database: field(2)?,
+                application_name: field(3)?,
+                backend_type: field(4)?,
+                state: field(5)?,
+                query: field(6)?,
+                state_duration: fields
+                    .get(7)
+                    .and_then(|seconds| seconds.parse::<f64>().ok())
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds.max(0.0)).ok()),
+            })
+        })
+        .collect()
+}
+
+/// Query terminating the process `pid`, printing whether it was signalled.
+pub(crate) fn terminate_query(pid: u32) -> String {
+    "SELECT pg_terminate_backend(".to_string() + &pid.to_string() + ")"
+}
+
+/// Query terminating every other session on `dbname`, printing how many were signalled.
+pub(crate) fn terminate_database_query(dbname: &str) -> String {
+    "SELECT count(*) FILTER (WHERE pg_terminate_backend(pid)) FROM pg_stat_activity \
+     WHERE pid <> pg_backend_pid() AND datname = "
+        .to_string()
+        + &quote_literal(dbname)
+}
+
+/// Condition that no other session is connected to `dbname`.
+pub(crate) fn no_sessions_predicate(dbname: &str) -> String {
+    "NOT EXISTS (SELECT FROM pg_stat_activity WHERE pid <> pg_backend_pid() AND datname = "
+        .to_string()
+        + &quote_literal(dbname)
+        + ")"
+}
+
+/// Describe the client connections in `activity` left open to the instance labelled `label`,
+/// or `None` when there are none.
+pub(crate) fn describe_leaks(label: &str, activity: &[Backend]) -> Option<String> {
+    let leaked: Vec<&Backend> = activity
+        .iter()
+        .filter(|backend| backend.is_client())
+        .collect();
+    if leaked.is_empty() {
+        return None;
+    }
+    let mut message = "instance ".to_string() + label + " was stopped with ";
+    message.push_str(&leaked.len().to_string());
+    message.push_str(" client connections still open:");
+    for backend in leaked {
+        message.push_str(" pid ");
+        message.push_str(&backend.pid.to_string());
+        message.push_str(" (");
+        message.push_str(&backend.user);
+        message.push_str(", application ");
+        message.push_str(&quote_literal(&backend.application_name));
+        message.push_str(", ");
+        message.push_str(&backend.state);
+        message.push(')');
+    }
+    Some(message)
+}
@@ -0,0 +1,237 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use tempdir::TempDir;
+use tracing::{debug, error, info};
+
+use crate::auth::AuthContext;
+use crate::builder::Verbosity;
+use crate::errors::{ProcessCapture, TmpPostgrustError, TmpPostgrustResult};
+use crate::search::find_postgresql_command;
+use crate::synchronous::exec_process;
+
+/// Configuration files copied from the data directory.
+const CONFIG_FILES: [&str; 2] = ["postgresql.conf", "pg_hba.conf"];
+
+/// Statements that took the most time in total, when `pg_stat_statements` is available.
+const TOP_STATEMENTS_SQL: &str = "SELECT calls, total_exec_time, rows, query \
+                                  FROM pg_stat_statements \
+                                  ORDER BY total_exec_time DESC LIMIT 100";
+
+/// Gather what helps debugging a failed test into `dir`, creating it if needed: the `log`
+/// directory of the server, which exists with the
+/// [CSV log](crate::TmpPostgrustFactoryBuilder::with_csv_log), `postgresql.conf`,
+/// `pg_hba.conf`, the most expensive statements of `pg_stat_statements` as
+/// `pg_stat_statements.csv` if the extension is installed and a `pg_dump --schema-only` of
+/// `dbname` as `schema.sql`. Returns the paths written.
+pub(crate) fn collect(
+    superuser: &AuthContext,
+    dbname: &str,
+    data_directory: &Path,
+    dir: &Path,
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).map_err(TmpPostgrustError::CollectArtifactsFailed)?;
+    let mut written = Vec::new();
+
+    let log_dir = data_directory.join("log");
+    if log_dir.is_dir() {
+        let target = dir.join("log");
+        std::fs::create_dir_all(&target).map_err(TmpPostgrustError::CollectArtifactsFailed)?;
+        for entry in
+            std::fs::read_dir(&log_dir).map_err(TmpPostgrustError::CollectArtifactsFailed)?
+        {
+            let entry = entry.map_err(TmpPostgrustError::CollectArtifactsFailed)?;
+            written.push(copy_file(&entry.path(), &target)?);
+        }
+    }
+    for name in CONFIG_FILES {
+        written.push(copy_file(&data_directory.join(name), dir)?);
+    }
+
+    let psql_args = ["-X", "--csv", "-c", TOP_STATEMENTS_SQL];
+    match run_tool(superuser, dbname, "psql", &psql_args, verbosity) {
+        Ok(output) => written.push(write_file(dir, "pg_stat_statements.csv", &output.stdout)?),
+        Err(err) => debug!("skipping pg_stat_statements: {}", err),
+    }
+    let schema = run_tool(superuser, dbname, "pg_dump", &["--schema-only"], verbosity)?;
+    written.push(write_file(dir, "schema.sql", &schema.stdout)?);
+    Ok(written)
+}
+
+fn copy_file(source: &Path, dir: &Path) -> TmpPostgrustResult<PathBuf> {
+    let target = dir.join(source.file_name().unwrap_or_default());
+    std::fs::copy(source, &target).map_err(TmpPostgrustError::CollectArtifactsFailed)?;
+    Ok(target)
+}
+
+fn write_file(dir: &Path, name: &str, contents: &str) -> TmpPostgrustResult<PathBuf> {
+    let target = dir.join(name);
+    std::fs::write(&target, contents).map_err(TmpPostgrustError::CollectArtifactsFailed)?;
+    Ok(target)
+}
+
+fn run_tool(
+    auth: &AuthContext,
+    dbname: &str,
+    tool: &str,
+    args: &[&str],
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<ProcessCapture> {
+    let tool_path = find_postgresql_command("bin", tool)
+        .map_err(|()| TmpPostgrustError::FindBinaryFailed(tool.to_string()))?;
+    exec_process(
+        Command::new(tool_path)
+            .args(args.iter().map(OsStr::new))
+            .envs(auth.libpq_envs(dbname)),
+        verbosity,
+        TmpPostgrustError::PgToolFailed,
+    )
+}
+
+/// Artifacts collected from an instance whose guard was dropped while the thread panicked,
+/// handed to every [`ArtifactSink`] of the factory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactBundle {
+    label: String,
+    port: u32,
+    dir: PathBuf,
+    files: Vec<PathBuf>,
+}
+
+impl ArtifactBundle {
+    /// Label of the instance.
+    #[must_use]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Port the instance listened on.
+    #[must_use]
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    /// Name unique among the running instances, made of the label, with characters other than
+    /// ASCII letters, digits, `-` and `_` replaced by `_`, and the port, e.g. `my_test-5433`.
+    #[must_use]
+    pub fn name(&self) -> String {
+        let label: String = self
+            .label
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{}-{}", label, self.port)
+    }
+
+    /// Temporary directory containing the artifacts, which is removed once every sink ran.
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Paths of the collected files inside [`dir`](Self::dir).
+    #[must_use]
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+/// Destination of the artifacts of failed tests, registered with
+/// [`with_artifact_sink`](crate::TmpPostgrustFactoryBuilder::with_artifact_sink), e.g. to
+/// upload them to an object store so database test diagnostics of every CI pipeline end up in
+/// one place.
+pub trait ArtifactSink: fmt::Debug + Send + Sync {
+    /// Store the artifacts of `bundle`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are only logged, as the sink runs while the guard is dropped.
+    fn store(&self, bundle: &ArtifactBundle) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Sink copying every bundle into a directory named after [`ArtifactBundle::name`] inside
+/// its directory, e.g. one that CI uploads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilesystemSink {
+    dir: PathBuf,
+}
+
+impl FilesystemSink {
+    /// Copy bundles into `dir`, which is created when the first bundle is stored.
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FilesystemSink { dir: dir.into() }
+    }
+}
+
+impl ArtifactSink for FilesystemSink {
+    fn store(&self, bundle: &ArtifactBundle) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let target = self.dir.join(bundle.name());
+        for file in bundle.files() {
+            let destination = target.join(file.strip_prefix(bundle.dir())?);
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(file, destination)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collect the artifacts of the instance `label` and hand them to `sinks` if the thread is
+/// panicking, logging failures as the guard is being dropped.
+pub(crate) fn collect_on_panic(
+    sinks: &[Arc<dyn ArtifactSink>],
+    superuser: &AuthContext,
+    dbname: &str,
+    data_directory: &Path,
+    label: &str,
+    verbosity: Verbosity,
+) {
+    if sinks.is_empty() || !std::thread::panicking() {
+        return;
+    }
+    let staging = match TempDir::new("tmp-postgrust-artifacts") {
+        Ok(staging) => staging,
+        Err(err) => {
+            error!(
+                "failed to create directory for artifacts of {}: {}",
+                label, err
+            );
+            return;
+        }
+    };
+    let files = match collect(superuser, dbname, data_directory, staging.path(), verbosity) {
+        Ok(files) => files,
+        Err(err) => {
+            error!("failed to collect artifacts of instance {}: {}", label, err);
+            return;
+        }
+    };
+    let bundle = ArtifactBundle {
+        label: label.to_string(),
+        port: superuser.port,
+        dir: staging.path().to_path_buf(),
+        files,
+    };
+    for sink in sinks {
+        match sink.store(&bundle) {
+            Ok(()) => info!("stored artifacts of instance {} in {:?}", label, sink),
+            Err(err) => error!(
+                "failed to store artifacts of instance {} in {:?}: {}",
+                label, sink, err
+            ),
+        }
+    }
+}
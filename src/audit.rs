@@ -0,0 +1,122 @@
+use std::path::Path;
+
+/// CSV log of an instance, relative to its data directory.
+pub(crate) const CSV_LOG_FILE: &str = "log/postgresql.csv";
+
+/// Prefix pgaudit gives the messages it logs.
+const AUDIT_PREFIX: &str = "AUDIT: ";
+
+/// Settings logging to [`CSV_LOG_FILE`] through the logging collector.
+pub(crate) const CSV_LOG_CONFIG: &str = "logging_collector = on
+log_destination = 'csvlog'
+log_directory = 'log'
+log_filename = 'postgresql.log'
+";
+
+/// Column of the CSV log holding the role of the session.
+pub(crate) const USER_COLUMN: usize = 1;
+/// Column of the CSV log holding the database of the session.
+pub(crate) const DATABASE_COLUMN: usize = 2;
+/// Column of the CSV log holding the message.
+pub(crate) const MESSAGE_COLUMN: usize = 13;
+/// Column of the CSV log holding the detail of the message.
+pub(crate) const DETAIL_COLUMN: usize = 14;
+
+/// Statement logged by pgaudit, as listed by
+/// [`audit_events`](crate::synchronous::ProcessGuard::audit_events).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// Role of the session that ran the statement.
+    pub user: String,
+    /// Database the statement ran in.
+    pub database: String,
+    /// `SESSION` or `OBJECT` audit logging.
+    pub audit_type: String,
+    /// Identifier of the statement, unique within the session.
+    pub statement_id: u64,
+    /// Identifier of the sub-statement, e.g. in a function called by the statement.
+    pub substatement_id: u64,
+    /// Class of the statement such as `READ`, `WRITE` or `DDL`.
+    pub class: String,
+    /// Command such as `SELECT` or `ALTER TABLE`.
+    pub command: String,
+    /// Type of the object the statement accessed, e.g. `TABLE`, empty when not logged.
+    pub object_type: String,
+    /// Schema qualified name of the accessed object, empty when not logged.
+    pub object_name: String,
+    /// Text of the statement.
+    pub statement: String,
+    /// Parameters of the statement, `<not logged>` unless `pgaudit.log_parameter` is on.
+    pub parameter: String,
+}
+
+impl AuditEvent {
+    fn parse(user: &str, database: &str, message: &str) -> Option<Self> {
+        let record = parse_csv(message.strip_prefix(AUDIT_PREFIX)?).pop()?;
+        let mut fields = record.into_iter();
+        Some(AuditEvent {
+            user: user.to_string(),
+            database: database.to_string(),
+            audit_type: fields.next()?,
+            statement_id: fields.next()?.parse().ok()?,
+            substatement_id: fields.next()?.parse().ok()?,
+            class: fields.next()?,
+            command: fields.next()?,
+            object_type: fields.next()?,
+            object_name: fields.next()?,
+            statement: fields.next()?,
+            parameter: fields.next().unwrap_or_default(),
+        })
+    }
+}
+
+/// Audit events in the CSV log of the instance in `data_directory`, in the order they were
+/// logged.
+pub(crate) fn read_events(data_directory: &Path) -> std::io::Result<Vec<AuditEvent>> {
+    let log = std::fs::read_to_string(data_directory.join(CSV_LOG_FILE))?;
+    Ok(parse_events(&log))
+}
+
+/// Parse the audit events out of a CSV log, skipping every other message.
+pub(crate) fn parse_events(log: &str) -> Vec<AuditEvent> {
+    parse_csv(log)
+        .iter()
+        .filter_map(|record| {
+            AuditEvent::parse(
+                record.get(USER_COLUMN)?,
+                record.get(DATABASE_COLUMN)?,
+                record.get(MESSAGE_COLUMN)?,
+            )
+        })
+        .collect()
+}
+
+/// Split CSV into records of fields, allowing quoted fields with separators, doubled quotes
+/// and line breaks.
+pub(crate) fn parse_csv(input: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => record.push(std::mem::take(&mut field)),
+            '\n' if !quoted => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
@@ -0,0 +1,131 @@
+use std::collections::hash_map::RandomState;
+use std::ffi::OsString;
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Name of the bootstrap superuser created by `initdb`.
+pub(crate) const SUPERUSER: &str = "postgres";
+
+/// Random password of 32 hexadecimal digits for a role, read from the random number generator
+/// of the operating system where `/dev/urandom` exists.
+pub(crate) fn generate_password() -> String {
+    let mut bytes = [0_u8; 16];
+    let read = File::open("/dev/urandom").and_then(|mut file| file.read_exact(&mut bytes));
+    if read.is_err() {
+        // Hashers of the standard library are seeded randomly.
+        for chunk in bytes.chunks_mut(8) {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u128(
+                std::time::UNIX_EPOCH
+                    .elapsed()
+                    .unwrap_or_default()
+                    .as_nanos(),
+            );
+            chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+    }
+    let mut password = String::with_capacity(32);
+    for byte in bytes {
+        let _ = write!(password, "{byte:02x}");
+    }
+    password
+}
+
+/// Connection target and credentials used when running postgresql client tools
+/// (`createdb`, `createuser`, ...) against a temporary instance.
+#[derive(Clone)]
+pub(crate) struct AuthContext {
+    /// Directory containing the unix socket, or a hostname.
+    pub(crate) host: PathBuf,
+    /// Port the server is listening on.
+    pub(crate) port: u32,
+    /// Role to authenticate as.
+    pub(crate) user: String,
+    /// Password for the role, passed to tools via `PGPASSWORD`.
+    pub(crate) password: Option<String>,
+    /// Password of the bootstrap superuser, when the cluster requires one.
+    pub(crate) superuser_password: Option<String>,
+}
+
+/// Never prints the passwords, as the context is recorded in logs and tracing spans.
+impl fmt::Debug for AuthContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = |password: &Option<String>| password.as_ref().map(|_| "<redacted>");
+        f.debug_struct("AuthContext")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("password", &redacted(&self.password))
+            .field("superuser_password", &redacted(&self.superuser_password))
+            .finish()
+    }
+}
+
+impl AuthContext {
+    /// Authenticate as the bootstrap superuser over the unix socket, with `password` if the
+    /// cluster requires one.
+    pub(crate) fn superuser(socket_dir: &Path, port: u32, password: Option<String>) -> Self {
+        AuthContext {
+            host: socket_dir.to_path_buf(),
+            port,
+            user: SUPERUSER.to_string(),
+            password: password.clone(),
+            superuser_password: password,
+        }
+    }
+
+    /// Authenticate as the bootstrap superuser on the same server.
+    pub(crate) fn as_superuser(&self) -> Self {
+        AuthContext {
+            user: SUPERUSER.to_string(),
+            password: self.superuser_password.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Arguments selecting host, port and user. Tools are never allowed to prompt
+    /// for a password as there is nobody to answer the prompt.
+    pub(crate) fn args(&self) -> Vec<OsString> {
+        vec![
+            "-h".into(),
+            self.host.clone().into(),
+            "-p".into(),
+            self.port.to_string().into(),
+            "-U".into(),
+            self.user.clone().into(),
+            "--no-password".into(),
+        ]
+    }
+
+    /// Environment variables pointing libpq based tools at `dbname` on the instance.
+    pub(crate) fn libpq_envs(&self, dbname: &str) -> Vec<(&'static str, String)> {
+        let mut envs = vec![
+            ("PGHOST", self.host.to_string_lossy().into_owned()),
+            ("PGPORT", self.port.to_string()),
+            ("PGUSER", self.user.clone()),
+            ("PGDATABASE", dbname.to_string()),
+        ];
+        envs.extend(self.envs());
+        envs
+    }
+
+    /// Format [`libpq_envs`](Self::libpq_envs) as shell `export` statements, one per line.
+    pub(crate) fn shell_exports(&self, dbname: &str) -> String {
+        self.libpq_envs(dbname)
+            .into_iter()
+            .map(|(key, value)| format!("export {}='{}'\n", key, value.replace('\'', "'\\''")))
+            .collect::<Vec<_>>()
+            .concat()
+    }
+
+    /// Environment variables carrying credentials that should not appear in arguments.
+    pub(crate) fn envs(&self) -> Vec<(&'static str, String)> {
+        self.password
+            .iter()
+            .map(|password| ("PGPASSWORD", password.clone()))
+            .collect()
+    }
+}
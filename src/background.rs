@@ -0,0 +1,108 @@
+use crate::sql::{split_records, unaligned_query_args};
+
+/// Reports the background writer counters followed by vacuum and analyze activity of every
+/// user table, padding rows to the same columns. Only counters present in all supported
+/// postgresql versions are selected.
+const ACTIVITY_QUERY: &str = "
+SELECT 'bgwriter', buffers_clean::text, maxwritten_clean::text, buffers_alloc::text,
+       NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL
+FROM pg_stat_bgwriter
+UNION ALL
+SELECT 'table', schemaname || '.' || relname, n_live_tup::text, n_dead_tup::text,
+       vacuum_count::text, autovacuum_count::text, analyze_count::text,
+       autoanalyze_count::text, last_vacuum::text, last_autovacuum::text,
+       last_analyze::text, last_autoanalyze::text
+FROM pg_stat_all_tables
+WHERE schemaname NOT IN ('pg_catalog', 'information_schema')
+  AND schemaname NOT LIKE 'pg_toast%'
+ORDER BY 1, 2;
+";
+
+/// Background writer and vacuum activity of an instance, as reported by the statistics views.
+///
+/// Statistics are collected asynchronously, so changes made by a session can take until the
+/// session ends to be visible.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackgroundActivity {
+    /// Buffers written by the background writer.
+    pub buffers_clean: i64,
+    /// Times the background writer stopped because it wrote too many buffers.
+    pub maxwritten_clean: i64,
+    /// Buffers allocated.
+    pub buffers_alloc: i64,
+    /// Activity of every user table, ordered by name.
+    pub tables: Vec<TableActivity>,
+}
+
+/// Vacuum and analyze activity of a table from `pg_stat_all_tables`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableActivity {
+    /// Schema qualified name of the table.
+    pub name: String,
+    /// Estimated number of live rows.
+    pub live_tuples: i64,
+    /// Estimated number of dead rows.
+    pub dead_tuples: i64,
+    /// Times the table was vacuumed manually.
+    pub vacuum_count: i64,
+    /// Times the table was vacuumed by autovacuum.
+    pub autovacuum_count: i64,
+    /// Times the table was analyzed manually.
+    pub analyze_count: i64,
+    /// Times the table was analyzed by autovacuum.
+    pub autoanalyze_count: i64,
+    /// Time of the last manual vacuum.
+    pub last_vacuum: Option<String>,
+    /// Time of the last vacuum by autovacuum.
+    pub last_autovacuum: Option<String>,
+    /// Time of the last manual analyze.
+    pub last_analyze: Option<String>,
+    /// Time of the last analyze by autovacuum.
+    pub last_autoanalyze: Option<String>,
+}
+
+/// Arguments for `psql` to print the activity query in a parseable form.
+pub(crate) fn activity_query_args() -> [&'static str; 9] {
+    unaligned_query_args(ACTIVITY_QUERY)
+}
+
+/// Parse the output of the activity query.
+pub(crate) fn parse_activity(output: &str) -> BackgroundActivity {
+    let mut activity = BackgroundActivity::default();
+    for fields in split_records(output) {
+        let count = |index: usize| {
+            fields
+                .get(index)
+                .and_then(|count| count.parse().ok())
+                .unwrap_or_default()
+        };
+        let time = |index: usize| {
+            fields
+                .get(index)
+                .filter(|time| !time.is_empty())
+                .map(|time| (*time).to_string())
+        };
+        match fields.first().copied() {
+            Some("bgwriter") => {
+                activity.buffers_clean = count(1);
+                activity.maxwritten_clean = count(2);
+                activity.buffers_alloc = count(3);
+            }
+            Some("table") => activity.tables.push(TableActivity {
+                name: fields.get(1).copied().unwrap_or_default().to_string(),
+                live_tuples: count(2),
+                dead_tuples: count(3),
+                vacuum_count: count(4),
+                autovacuum_count: count(5),
+                analyze_count: count(6),
+                autoanalyze_count: count(7),
+                last_vacuum: time(8),
+                last_autovacuum: time(9),
+                last_analyze: time(10),
+                last_autoanalyze: time(11),
+            }),
+            _ => {}
+        }
+    }
+    activity
+}
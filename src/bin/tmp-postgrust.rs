@@ -0,0 +1,116 @@
+//! Command line interface of `tmp-postgrust`.
+//!
+//! ```text
+//! tmp-postgrust broker <socket> [--pool-size <n>] [--lease-ttl <seconds>] [--cache-dir <dir>]
+//! tmp-postgrust lease <socket> <command> [<args>...]
+//! ```
+//!
+//! `broker` lends instances on the unix socket until it is terminated. `lease` borrows an
+//! instance from a broker and runs the command with `DATABASE_URL` set to its connection
+//! string, returning the instance once the command exits.
+//!
+//! The broker lends instances over a unix socket, so it is only available on unix.
+
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::process::exit;
+#[cfg(unix)]
+use std::process::Command;
+#[cfg(unix)]
+use std::sync::mpsc::channel;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+#[cfg(unix)]
+use tmp_postgrust::broker::{Broker, BrokerLease};
+#[cfg(unix)]
+use tmp_postgrust::TmpPostgrustFactory;
+
+#[cfg(unix)]
+const USAGE: &str = "usage:
+    tmp-postgrust broker <socket> [--pool-size <n>] [--lease-ttl <seconds>] [--cache-dir <dir>]
+    tmp-postgrust lease <socket> <command> [<args>...]";
+
+#[cfg(unix)]
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    exit(2)
+}
+
+#[cfg(unix)]
+fn broker(mut args: impl Iterator<Item = String>) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = args.next().unwrap_or_else(|| usage());
+    let mut builder = TmpPostgrustFactory::builder();
+    let mut pool_size = None;
+    let mut lease_ttl = None;
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match flag.as_str() {
+            "--pool-size" => pool_size = Some(value.parse()?),
+            "--lease-ttl" => lease_ttl = Some(Duration::from_secs(value.parse()?)),
+            "--cache-dir" => builder = builder.with_cache_dir(value),
+            _ => usage(),
+        }
+    }
+    let (progress, steps) = channel();
+    let reporter = thread::spawn(move || {
+        for step in steps {
+            eprintln!("{step}");
+        }
+    });
+    let factory = builder.with_progress(progress).build();
+    // The sender is dropped with the builder, which ends the reporter.
+    let _ = reporter.join();
+    let mut broker = Broker::new(factory?, socket);
+    if let Some(pool_size) = pool_size {
+        broker = broker.with_pool_size(pool_size);
+    }
+    if let Some(lease_ttl) = lease_ttl {
+        broker = broker.with_lease_ttl(lease_ttl);
+    }
+    broker.serve()?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn lease(mut args: impl Iterator<Item = String>) -> Result<i32, Box<dyn std::error::Error>> {
+    let socket = PathBuf::from(args.next().unwrap_or_else(|| usage()));
+    let program = args.next().unwrap_or_else(|| usage());
+    let lease = BrokerLease::acquire(&socket, &program)?;
+    let status = Command::new(&program)
+        .args(args)
+        .env("DATABASE_URL", lease.connection_string())
+        .status()?;
+    lease.release()?;
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(unix)]
+fn main() {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let result = match args.next().as_deref() {
+        Some("broker") => broker(args).map(|()| 0),
+        Some("lease") => lease(args),
+        _ => usage(),
+    };
+    match result {
+        Ok(code) => exit(code),
+        Err(err) => {
+            eprintln!("tmp-postgrust: {err}");
+            exit(1);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("tmp-postgrust: the broker requires unix sockets");
+    exit(1);
+}
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, instrument, warn};
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::synchronous::ProcessGuard;
+use crate::TmpPostgrustFactory;
+
+/// Label of the instances started by a broker.
+const BROKER_LABEL: &str = "broker";
+
+/// How long the provisioner waits before retrying after failing to start an instance.
+const PROVISION_RETRY: Duration = Duration::from_secs(1);
+
+/// Long-running server that keeps instances ready and lends them to other processes over a
+/// unix socket, e.g. to the test binaries of every crate in a workspace running `cargo test`
+/// concurrently.
+///
+/// A lease lasts as long as the connection it was requested on. When the borrower releases it
+/// or its process exits the server is stopped and a fresh instance takes its place.
+///
+/// The protocol is line based: a client sends `LEASE<TAB>holder`, naming itself for the logs,
+/// and receives `OK<TAB>connection string` or `ERR<TAB>message`. Sending `RELEASE` stops the
+/// instance, which the broker confirms with `RELEASED`. A lease held longer than the
+/// [lease TTL](Self::with_lease_ttl) is ended by the broker with `EXPIRED`.
+#[derive(Debug)]
+pub struct Broker {
+    factory: TmpPostgrustFactory,
+    socket_path: PathBuf,
+    pool_size: usize,
+    lease_ttl: Option<Duration>,
+}
+
+impl Broker {
+    /// Create a broker lending instances of `factory` on the unix socket at `socket_path`.
+    #[must_use]
+    pub fn new(factory: TmpPostgrustFactory, socket_path: impl Into<PathBuf>) -> Self {
+        Broker {
+            factory,
+            socket_path: socket_path.into(),
+            pool_size: 2,
+            lease_ttl: None,
+        }
+    }
+
+    /// Number of instances kept ready to be leased, 2 by default.
+    #[must_use]
+    pub fn with_pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size;
+        self
+    }
+
+    /// Stop instances leased for longer than `lease_ttl` with a warning naming their holder,
+    /// so holders that hang or forget to release do not slowly leak instances in long CI
+    /// sessions. Leases never expire by default.
+    #[must_use]
+    pub fn with_lease_ttl(mut self, lease_ttl: Duration) -> Self {
+        self.lease_ttl = Some(lease_ttl);
+        self
+    }
+
+    /// Start serving in background threads until the returned handle is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`BrokerFailed`](TmpPostgrustError::BrokerFailed) if the socket cannot be bound.
+    #[instrument(skip(self), fields(socket_path = %self.socket_path.display()))]
+    pub fn spawn(self) -> TmpPostgrustResult<BrokerHandle> {
+        // A socket left behind by a broker that did not shut down cleanly.
+        if UnixStream::connect(&self.socket_path).is_err() {
+            let _ = std::fs::remove_file(&self.socket_path);
+        }
+        let listener =
+            UnixListener::bind(&self.socket_path).map_err(TmpPostgrustError::BrokerFailed)?;
+        info!("lending instances on {}", self.socket_path.display());
+
+        let state = Arc::new(BrokerState {
+            factory: self.factory,
+            pool: Mutex::default(),
+            changed: Condvar::new(),
+            pool_size: self.pool_size,
+            lease_ttl: self.lease_ttl,
+            shutdown: AtomicBool::new(false),
+        });
+        let provisioner = {
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || state.provision())
+        };
+        let acceptor = {
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || state.accept(&listener))
+        };
+        Ok(BrokerHandle {
+            state,
+            socket_path: self.socket_path,
+            threads: vec![provisioner, acceptor],
+        })
+    }
+
+    /// Serve until the process is terminated.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`spawn`](Self::spawn).
+    pub fn serve(self) -> TmpPostgrustResult<()> {
+        let mut handle = self.spawn()?;
+        for thread in handle.threads.drain(..) {
+            let _ = thread.join();
+        }
+        Ok(())
+    }
+}
+
+struct BrokerState {
+    factory: TmpPostgrustFactory,
+    pool: Mutex<VecDeque<ProcessGuard>>,
+    changed: Condvar,
+    pool_size: usize,
+    lease_ttl: Option<Duration>,
+    shutdown: AtomicBool,
+}
+
+impl BrokerState {
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Keep the pool filled with ready instances.
+    fn provision(&self) {
+        loop {
+            {
+                let mut pool = self.pool.lock().unwrap();
+                while pool.len() >= self.pool_size && !self.is_shutdown() {
+                    pool = self.changed.wait(pool).unwrap();
+                }
+            }
+            if self.is_shutdown() {
+                return;
+            }
+            match self.factory.new_labeled_instance(BROKER_LABEL) {
+                Ok(instance) => {
+                    self.pool.lock().unwrap().push_back(instance);
+                    self.changed.notify_all();
+                }
+                Err(err) => {
+                    error!("failed to provision instance: {}", err);
+                    std::thread::sleep(PROVISION_RETRY);
+                }
+            }
+        }
+    }
+
+    fn accept(self: &Arc<Self>, listener: &UnixListener) {
+        for stream in listener.incoming() {
+            if self.is_shutdown() {
+                return;
+            }
+            match stream {
+                Ok(stream) => {
+                    let state = Arc::clone(self);
+                    std::thread::spawn(move || {
+                        if let Err(err) = state.serve_client(stream) {
+                            warn!("lease connection failed: {}", err);
+                        }
+                    });
+                }
+                Err(err) => error!("failed to accept connection: {}", err),
+            }
+        }
+    }
+
+    /// Take a ready instance out of the pool, waiting for one to be provisioned.
+    fn take_instance(&self) -> Option<ProcessGuard> {
+        let mut pool = self.pool.lock().unwrap();
+        loop {
+            if self.is_shutdown() {
+                return None;
+            }
+            if let Some(instance) = pool.pop_front() {
+                self.changed.notify_all();
+                return Some(instance);
+            }
+            pool = self.changed.wait(pool).unwrap();
+        }
+    }
+
+    fn serve_client(&self, stream: UnixStream) -> std::io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut lines = BufReader::new(stream).lines();
+        let Some(request) = lines.next().transpose()? else {
+            return Ok(());
+        };
+        let Some(holder) = request.strip_prefix("LEASE\t") else {
+            return writeln!(writer, "ERR\tunknown request {request:?}");
+        };
+        let Some(instance) = self.take_instance() else {
+            return writeln!(writer, "ERR\tbroker is shutting down");
+        };
+        info!("leased instance to {}", holder);
+        let leased = Instant::now();
+        writeln!(writer, "OK\t{}", instance.connection_string)?;
+
+        // The lease ends with a release, when the holder disconnects or when it expires.
+        let end = loop {
+            if let Some(lease_ttl) = self.lease_ttl {
+                let remaining = lease_ttl.saturating_sub(leased.elapsed());
+                if remaining.is_zero() {
+                    break LeaseEnd::Expired;
+                }
+                // Shared with the reading half of the stream.
+                writer.set_read_timeout(Some(remaining))?;
+            }
+            match lines.next() {
+                Some(Ok(line)) if line == "RELEASE" => break LeaseEnd::Released,
+                Some(Ok(_)) => {}
+                Some(Err(err))
+                    if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Some(Err(_)) | None => break LeaseEnd::Disconnected,
+            }
+        };
+        drop(instance);
+        match end {
+            LeaseEnd::Released => {
+                info!("reclaimed instance leased to {}", holder);
+                writeln!(writer, "RELEASED")
+            }
+            LeaseEnd::Disconnected => {
+                info!("reclaimed instance of disconnected holder {}", holder);
+                Ok(())
+            }
+            LeaseEnd::Expired => {
+                warn!(
+                    "lease of {} expired after {:?}, stopped its instance",
+                    holder,
+                    leased.elapsed()
+                );
+                writeln!(writer, "EXPIRED")
+            }
+        }
+    }
+}
+
+/// How a lease ended.
+enum LeaseEnd {
+    Released,
+    Disconnected,
+    Expired,
+}
+
+/// Running [`Broker`], which stops serving and stops its ready instances when dropped.
+pub struct BrokerHandle {
+    state: Arc<BrokerState>,
+    socket_path: PathBuf,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Drop for BrokerHandle {
+    fn drop(&mut self) {
+        self.state.shutdown.store(true, Ordering::SeqCst);
+        self.state.changed.notify_all();
+        // Wake up the acceptor blocked waiting for a connection.
+        let _ = UnixStream::connect(&self.socket_path);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        self.state.pool.lock().unwrap().clear();
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+/// Instance borrowed from a [`Broker`], returned to it when dropped.
+#[derive(Debug)]
+pub struct BrokerLease {
+    stream: UnixStream,
+    connection_string: String,
+}
+
+impl BrokerLease {
+    /// Borrow an instance from the broker listening on `socket_path`, waiting until one is
+    /// ready. `holder` identifies the borrower in the logs of the broker.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`BrokerFailed`](TmpPostgrustError::BrokerFailed) if the broker cannot be reached
+    /// and with [`LeaseRefused`](TmpPostgrustError::LeaseRefused) if it refuses to lend an
+    /// instance.
+    #[instrument]
+    pub fn acquire(socket_path: &Path, holder: &str) -> TmpPostgrustResult<BrokerLease> {
+        let mut stream =
+            UnixStream::connect(socket_path).map_err(TmpPostgrustError::BrokerFailed)?;
+        writeln!(stream, "LEASE\t{}", holder.replace(['\t', '\n'], " "))
+            .map_err(TmpPostgrustError::BrokerFailed)?;
+        let mut response = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut response)
+            .map_err(TmpPostgrustError::BrokerFailed)?;
+        match response.trim_end().split_once('\t') {
+            Some(("OK", connection_string)) => Ok(BrokerLease {
+                stream,
+                connection_string: connection_string.to_string(),
+            }),
+            Some(("ERR", message)) => Err(TmpPostgrustError::LeaseRefused(message.to_string())),
+            _ => Err(TmpPostgrustError::LeaseRefused(format!(
+                "unexpected response {response:?}"
+            ))),
+        }
+    }
+
+    /// Connection string of the borrowed instance.
+    #[must_use]
+    pub fn connection_string(&self) -> &str {
+        &self.connection_string
+    }
+
+    /// Return the instance and wait until the broker stopped it.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`LeaseExpired`](TmpPostgrustError::LeaseExpired) if the broker already
+    /// stopped the instance because the lease expired.
+    pub fn release(mut self) -> TmpPostgrustResult<()> {
+        // Writing fails if the broker already closed the connection of an expired lease.
+        let sent = writeln!(self.stream, "RELEASE");
+        let mut response = String::new();
+        BufReader::new(&self.stream)
+            .read_line(&mut response)
+            .map_err(TmpPostgrustError::BrokerFailed)?;
+        match response.trim_end() {
+            "RELEASED" => Ok(()),
+            "EXPIRED" => Err(TmpPostgrustError::LeaseExpired),
+            "" => Err(TmpPostgrustError::BrokerFailed(sent.err().unwrap_or_else(
+                || std::io::Error::from(ErrorKind::UnexpectedEof),
+            ))),
+            _ => Err(TmpPostgrustError::LeaseRefused(format!(
+                "unexpected response {response:?}"
+            ))),
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::audit::{self, DATABASE_COLUMN, DETAIL_COLUMN, MESSAGE_COLUMN, USER_COLUMN};
+use crate::auth::{AuthContext, SUPERUSER};
+use crate::builder::Verbosity;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::record::{QueryFingerprints, RecordedStatement};
+use crate::synchronous::exec_psql;
+use crate::wait::Backoff;
+
+/// How long to wait for the statements sent during a budget to appear in the log.
+const LOG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Column of the CSV log holding the process id of the session.
+const PROCESS_ID_COLUMN: usize = 3;
+
+/// Start of the markers delimiting budgets in the log.
+const MARKER_PREFIX: &str = "tmp-postgrust budget ";
+
+/// Numbers the markers delimiting budgets in the log.
+static NEXT_MARKER: AtomicU64 = AtomicU64::new(0);
+
+/// Limits on the queries a part of a test may send, e.g. a single request handled by the
+/// application, started with `start_budget` on a guard. Limits left `None` are not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// Largest number of statements that may be sent.
+    pub max_queries: Option<usize>,
+    /// Longest the server may spend running the statements in total.
+    pub max_total_time: Option<Duration>,
+}
+
+impl Budget {
+    /// Descriptions of the limits `usage` exceeds.
+    fn violations(&self, usage: &BudgetUsage) -> Vec<String> {
+        let mut violations = Vec::new();
+        if let Some(max_queries) = self.max_queries.filter(|&max| usage.queries > max) {
+            violations.push(format!(
+                "{} queries, at most {} allowed",
+                usage.queries, max_queries
+            ));
+        }
+        if let Some(max_total_time) = self.max_total_time.filter(|&max| usage.total_time > max) {
+            violations.push(format!(
+                "{:?} spent running queries, at most {:?} allowed",
+                usage.total_time, max_total_time
+            ));
+        }
+        violations
+    }
+}
+
+/// Statements sent while a budget was running.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BudgetUsage {
+    /// Number of statements sent.
+    pub queries: usize,
+    /// Time the server spent running the statements, including parsing and binding prepared
+    /// statements.
+    pub total_time: Duration,
+    /// Distinct statements with the number of times they were sent.
+    pub fingerprints: QueryFingerprints,
+}
+
+/// Running [`Budget`], counting the statements sent to an instance by every session of the
+/// database user since it was started, except those of the guard checking the budget.
+///
+/// Built on [statement recording](crate::TmpPostgrustFactoryBuilder::with_statement_recording),
+/// which the factory of the instance needs to be built with. The budget is delimited by
+/// marker statements in the log, so statements the logging collector has not written yet are
+/// waited for rather than missed.
+#[derive(Debug)]
+pub struct BudgetScope {
+    budget: Budget,
+    auth: AuthContext,
+    dbname: String,
+    data_directory: PathBuf,
+    verbosity: Verbosity,
+    start_marker: String,
+}
+
+impl BudgetScope {
+    pub(crate) fn start(
+        budget: Budget,
+        auth: AuthContext,
+        dbname: &str,
+        data_directory: PathBuf,
+        verbosity: Verbosity,
+    ) -> TmpPostgrustResult<Self> {
+        let scope = BudgetScope {
+            budget,
+            auth,
+            dbname: dbname.to_string(),
+            data_directory,
+            verbosity,
+            start_marker: next_marker(),
+        };
+        scope.log_marker(&scope.start_marker)?;
+        Ok(scope)
+    }
+
+    /// The limits checked by this scope.
+    #[must_use]
+    pub fn budget(&self) -> Budget {
+        self.budget
+    }
+
+    /// Statements sent since the budget was started.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReadCsvLogFailed`](TmpPostgrustError::ReadCsvLogFailed) if the server log
+    /// cannot be read, or with [`StatementLogTimeout`](TmpPostgrustError::StatementLogTimeout) if
+    /// the statements do not show up in it in time.
+    pub fn usage(&self) -> TmpPostgrustResult<BudgetUsage> {
+        let end_marker = next_marker();
+        self.log_marker(&end_marker)?;
+        let started = Instant::now();
+        let mut backoff = Backoff::new();
+        loop {
+            let log = std::fs::read_to_string(self.data_directory.join(audit::CSV_LOG_FILE));
+            match log {
+                Ok(log) => {
+                    if let Some(usage) = parse_usage(&log, &self.start_marker, &end_marker) {
+                        return Ok(usage);
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(TmpPostgrustError::ReadCsvLogFailed(err)),
+            }
+            let remaining = LOG_TIMEOUT.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(TmpPostgrustError::StatementLogTimeout(LOG_TIMEOUT));
+            }
+            std::thread::sleep(backoff.next_delay(remaining));
+        }
+    }
+
+    /// Check the statements sent since the budget was started against its limits.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`BudgetExceeded`](TmpPostgrustError::BudgetExceeded) when any limit is
+    /// exceeded, or like [`usage`](Self::usage).
+    pub fn check(&self) -> TmpPostgrustResult<BudgetUsage> {
+        let usage = self.usage()?;
+        let violations = self.budget.violations(&usage);
+        if violations.is_empty() {
+            Ok(usage)
+        } else {
+            Err(TmpPostgrustError::BudgetExceeded(BudgetExceeded {
+                violations,
+                usage,
+            }))
+        }
+    }
+
+    /// Panic listing the statements sent since the budget was started if they exceed any of
+    /// its limits.
+    ///
+    /// # Panics
+    /// When the budget is exceeded or the statements cannot be read from the log.
+    pub fn assert(&self) {
+        if let Err(err) = self.check() {
+            panic!("{}", err);
+        }
+    }
+
+    fn log_marker(&self, marker: &str) -> TmpPostgrustResult<()> {
+        exec_psql(
+            &self.auth,
+            &self.dbname,
+            &marker_statement(marker),
+            self.verbosity,
+        )
+    }
+}
+
+/// Limits exceeded by the statements sent during a budget.
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    /// Descriptions of the exceeded limits.
+    pub violations: Vec<String>,
+    /// Statements sent during the budget.
+    pub usage: BudgetUsage,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "query budget exceeded: {}", self.violations.join(", "))?;
+        write!(f, "{}", self.usage.fingerprints)
+    }
+}
+
+/// Name of a marker unique to this process.
+fn next_marker() -> String {
+    let marker = NEXT_MARKER.fetch_add(1, Ordering::Relaxed);
+    format!("{}{}-{}", MARKER_PREFIX, std::process::id(), marker)
+}
+
+fn marker_statement(marker: &str) -> String {
+    format!("SELECT '{marker}'")
+}
+
+/// Marker logged by `record`, if it is the statement of one.
+fn logged_marker(record: &[String]) -> Option<&str> {
+    let marker = record
+        .get(MESSAGE_COLUMN)?
+        .strip_prefix("statement: SELECT '")?
+        .strip_suffix('\'')?;
+    marker.starts_with(MARKER_PREFIX).then_some(marker)
+}
+
+/// Parse a message such as `duration: 0.123 ms`.
+fn parse_duration(message: &str) -> Option<Duration> {
+    let (millis, _) = message.strip_prefix("duration: ")?.split_once(" ms")?;
+    Some(Duration::from_secs_f64(
+        millis.parse::<f64>().ok()? / 1000.0,
+    ))
+}
+
+/// Usage between the statements logging `start_marker` and `end_marker`, or `None` until both
+/// have been logged. Messages of the sessions logging markers are left out, including those of
+/// earlier checks of the same budget.
+fn parse_usage(log: &str, start_marker: &str, end_marker: &str) -> Option<BudgetUsage> {
+    let records = audit::parse_csv(log);
+    let start = records
+        .iter()
+        .position(|record| logged_marker(record) == Some(start_marker))?;
+    let end = start
+        + records[start..]
+            .iter()
+            .position(|record| logged_marker(record) == Some(end_marker))?;
+    let records = &records[start..=end];
+    let marker_pids: HashSet<_> = records
+        .iter()
+        .filter(|record| logged_marker(record).is_some())
+        .filter_map(|record| record.get(PROCESS_ID_COLUMN))
+        .collect();
+
+    let mut statements = Vec::new();
+    let mut total_time = Duration::ZERO;
+    for record in records {
+        let user = record.get(USER_COLUMN).map_or("", String::as_str);
+        let pid = record.get(PROCESS_ID_COLUMN);
+        if user == SUPERUSER || pid.is_none_or(|pid| marker_pids.contains(pid)) {
+            continue;
+        }
+        let message = record.get(MESSAGE_COLUMN).map_or("", String::as_str);
+        if let Some(duration) = parse_duration(message) {
+            total_time += duration;
+        } else if let Some(statement) = RecordedStatement::parse(
+            user,
+            record.get(DATABASE_COLUMN).map_or("", String::as_str),
+            message,
+            record.get(DETAIL_COLUMN).map_or("", String::as_str),
+        ) {
+            statements.push(statement);
+        }
+    }
+    Some(BudgetUsage {
+        queries: statements.len(),
+        total_time,
+        fingerprints: QueryFingerprints::from_statements(&statements),
+    })
+}
@@ -0,0 +1,854 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempdir::TempDir;
+use tracing::{info, instrument};
+
+use crate::activity::ConnectionLeakCheck;
+use crate::artifacts::{ArtifactSink, FilesystemSink};
+use crate::copy::detect_copy_strategy;
+use crate::dirs::InstanceDir;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::events::{BuildStep, LifecycleEvent};
+use crate::grants::Grant;
+use crate::instance::InstanceOptions;
+#[cfg(feature = "tokio-process")]
+use crate::lazy::LazyFactory;
+use crate::limiter::InstanceLimitBehavior;
+use crate::manifest::BinaryManifest;
+use crate::platform::Platform;
+use crate::preset::Preset;
+use crate::search;
+use crate::throttle::IoThrottle;
+use crate::workers::BackgroundWorkerExtension;
+use crate::workspace::Workspace;
+use crate::{CacheDir, TmpPostgrustFactory};
+
+/// How much of the output of postgresql and its tools is forwarded to `tracing`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Forward nothing.
+    Quiet,
+    /// Log the commands that are run, but not their output.
+    Commands,
+    /// Log commands and every line of their output at debug level.
+    #[default]
+    Verbose,
+}
+
+/// Implementation of the dynamic shared memory segments used by parallel queries, the
+/// `dynamic_shared_memory_type` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynamicSharedMemoryType {
+    /// POSIX shared memory in `/dev/shm`, the default on Linux.
+    Posix,
+    /// System V shared memory, limited by the `kernel.shm*` settings.
+    SysV,
+    /// Files in the data directory mapped into memory, which works wherever the data
+    /// directory is writable.
+    Mmap,
+}
+
+impl DynamicSharedMemoryType {
+    pub(crate) fn setting(self) -> &'static str {
+        match self {
+            DynamicSharedMemoryType::Posix => "posix",
+            DynamicSharedMemoryType::SysV => "sysv",
+            DynamicSharedMemoryType::Mmap => "mmap",
+        }
+    }
+}
+
+/// Template database `createdb` copies the database of every instance from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DatabaseTemplate {
+    /// `template1`, inheriting every object added to it in the cached cluster.
+    #[default]
+    Template1,
+    /// `template0`, the pristine database as created by `initdb`.
+    Template0,
+}
+
+impl DatabaseTemplate {
+    /// Name of the template database.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            DatabaseTemplate::Template1 => "template1",
+            DatabaseTemplate::Template0 => "template0",
+        }
+    }
+}
+
+/// Authentication method `initdb` writes into `pg_hba.conf` of the cached cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// Allow every connection without a password, the default of `tmp-postgrust`.
+    Trust,
+    /// Allow local connections for the operating system user of the same name.
+    Peer,
+    /// Require a password sent in clear text.
+    Password,
+    /// Require an MD5 hashed password.
+    Md5,
+    /// Require a password through SCRAM-SHA-256, the default of production servers since
+    /// postgresql 14.
+    ScramSha256,
+}
+
+impl AuthMethod {
+    /// Name of the method in `pg_hba.conf`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            AuthMethod::Trust => "trust",
+            AuthMethod::Peer => "peer",
+            AuthMethod::Password => "password",
+            AuthMethod::Md5 => "md5",
+            AuthMethod::ScramSha256 => "scram-sha-256",
+        }
+    }
+
+    /// Whether clients have to send a password.
+    pub(crate) fn requires_password(self) -> bool {
+        matches!(
+            self,
+            AuthMethod::Password | AuthMethod::Md5 | AuthMethod::ScramSha256
+        )
+    }
+}
+
+/// Builder for a [`TmpPostgrustFactory`] with non-default settings.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Default)]
+pub struct TmpPostgrustFactoryBuilder {
+    pub(crate) cache_dir: Option<PathBuf>,
+    pub(crate) verbosity: Verbosity,
+    pub(crate) copy_excludes: Vec<OsString>,
+    pub(crate) core_dumps: bool,
+    pub(crate) fake_time: bool,
+    pub(crate) tablespaces: Vec<(String, PathBuf)>,
+    pub(crate) ddl_audit: bool,
+    pub(crate) sequence_start: Option<i64>,
+    pub(crate) required_manifest: Option<BinaryManifest>,
+    pub(crate) dynamic_shared_memory_type: Option<DynamicSharedMemoryType>,
+    pub(crate) shared_buffers_mb: Option<u32>,
+    pub(crate) workspace_dir: Option<PathBuf>,
+    pub(crate) workspace_instance_limit: Option<usize>,
+    pub(crate) instance_limit_behavior: InstanceLimitBehavior,
+    pub(crate) conf_fragments: Vec<PathBuf>,
+    pub(crate) conf_settings: Vec<(String, String)>,
+    pub(crate) instance_options: InstanceOptions,
+    pub(crate) grants: Vec<Grant>,
+    pub(crate) validate_settings: bool,
+    pub(crate) max_concurrent_instances: Option<usize>,
+    pub(crate) max_connections: Option<u32>,
+    pub(crate) socket_hardening: bool,
+    pub(crate) tcp: bool,
+    pub(crate) ready_timeout: Option<Duration>,
+    pub(crate) background_workers: Vec<BackgroundWorkerExtension>,
+    pub(crate) max_worker_processes: Option<u32>,
+    pub(crate) csv_log: bool,
+    pub(crate) statement_recording: bool,
+    pub(crate) connection_leak_check: ConnectionLeakCheck,
+    pub(crate) socket_link: bool,
+    pub(crate) database_template: DatabaseTemplate,
+    pub(crate) shared_server: bool,
+    pub(crate) data_checksums: bool,
+    pub(crate) auth_local: Option<AuthMethod>,
+    pub(crate) auth_host: Option<AuthMethod>,
+    pub(crate) pwfile: Option<PathBuf>,
+    pub(crate) artifact_sinks: Vec<Arc<dyn ArtifactSink>>,
+    pub(crate) progress: Option<Sender<BuildStep>>,
+    pub(crate) io_throttle: Option<IoThrottle>,
+    #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+    pub(crate) data_directory_size: Option<u64>,
+}
+
+impl TmpPostgrustFactoryBuilder {
+    /// Keep the initialized database cluster in `cache_dir`, reusing it if a previous run
+    /// already initialized it.
+    #[must_use]
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Control how much postgresql, `initdb` and `createdb` output is logged, independently
+    /// of the global tracing filter.
+    #[must_use]
+    pub fn with_verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Skip the top level entry `name` of the cached cluster when copying it into new
+    /// instances, e.g. a large log directory of a persistent cache. `postmaster.pid`,
+    /// `postmaster.opts`, `current_logfiles` and `log` are always skipped.
+    #[must_use]
+    pub fn with_copy_exclude(mut self, name: impl Into<OsString>) -> Self {
+        self.copy_excludes.push(name.into());
+        self
+    }
+
+    /// Allow servers to write core dumps and stop them instead of restarting after a crash,
+    /// keeping the data directory of a crashed server so crashes caused by extensions under
+    /// test can be investigated. Where the core file ends up depends on the
+    /// `kernel.core_pattern` setting of the system.
+    #[must_use]
+    pub fn with_core_dumps(mut self, core_dumps: bool) -> Self {
+        self.core_dumps = core_dumps;
+        self
+    }
+
+    /// Install `app_now()` in the database of every instance. It returns `now()` until a fake
+    /// time is set with `set_fake_time` on the guard, so temporal logic that calls `app_now()`
+    /// instead of `now()` can be tested deterministically.
+    #[must_use]
+    pub fn with_fake_time(mut self, fake_time: bool) -> Self {
+        self.fake_time = fake_time;
+        self
+    }
+
+    /// Create the tablespace `name` in every instance, located in `subdir` of a directory
+    /// inside the data directory of the instance, so applications that pin tables to
+    /// tablespaces can be tested. `subdir` has to be a relative path. The database user owns
+    /// the tablespace.
+    #[must_use]
+    pub fn with_tablespace(mut self, name: impl Into<String>, subdir: impl Into<PathBuf>) -> Self {
+        self.tablespaces.push((name.into(), subdir.into()));
+        self
+    }
+
+    /// Record every DDL command run in the database of an instance with an event trigger, so
+    /// migration tests can assert which DDL ran using `ddl_history` on the guard.
+    #[must_use]
+    pub fn with_ddl_audit(mut self, ddl_audit: bool) -> Self {
+        self.ddl_audit = ddl_audit;
+        self
+    }
+
+    /// Make every sequence created in an instance, including those of `serial` and identity
+    /// columns, start at `start`, and [`reset_sequences`] restart them there. Ids then
+    /// no longer depend on the order tables were created or rows inserted in other runs, so
+    /// dumped data can be compared against golden files.
+    ///
+    /// [`reset_sequences`]: crate::synchronous::ProcessGuard::reset_sequences
+    #[must_use]
+    pub fn with_sequence_start(mut self, start: i64) -> Self {
+        self.sequence_start = Some(start);
+        self
+    }
+
+    /// Use `dynamic_shared_memory_type` for every instance. By default `mmap` is used when
+    /// `/dev/shm` is too small and the server default otherwise.
+    #[must_use]
+    pub fn with_dynamic_shared_memory_type(
+        mut self,
+        dynamic_shared_memory_type: DynamicSharedMemoryType,
+    ) -> Self {
+        self.dynamic_shared_memory_type = Some(dynamic_shared_memory_type);
+        self
+    }
+
+    /// Size `shared_buffers` of every instance in megabytes instead of the default 12MB, which
+    /// is kept small so many instances fit into the shared memory of the system.
+    #[must_use]
+    pub fn with_shared_buffers(mut self, megabytes: u32) -> Self {
+        self.shared_buffers_mb = Some(megabytes);
+        self
+    }
+
+    /// Append the raw `postgresql.conf` fragment at `path` verbatim after the generated
+    /// settings of every instance, so configuration snippets of production can be reused in
+    /// tests. Fragments are read when the factory is built and appended in the order they were
+    /// added. As the last assignment of a setting wins, a warning is logged for every setting
+    /// that overrides a generated setting or one of an earlier fragment.
+    #[must_use]
+    pub fn with_conf_fragment(mut self, path: impl Into<PathBuf>) -> Self {
+        self.conf_fragments.push(path.into());
+        self
+    }
+
+    /// Set `name` to `value` in the `postgresql.conf` of every instance, replacing the generated
+    /// value if there is one, e.g. `with_conf_setting("max_connections", "50")`. Fragments are
+    /// appended after these settings. Building the factory fails with
+    /// [`DuplicateConfSetting`](TmpPostgrustError::DuplicateConfSetting) when a setting is
+    /// added more than once.
+    #[must_use]
+    pub fn with_conf_setting(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.conf_settings.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set up the database and role of instances started without explicit options according to
+    /// `options`, e.g. with an application role that is not a superuser so tests catch missing
+    /// grants.
+    #[must_use]
+    pub fn with_instance_options(mut self, options: InstanceOptions) -> Self {
+        self.instance_options = options;
+        self
+    }
+
+    /// Apply `grant` when setting up every instance, e.g.
+    /// `with_grants(Grant::on_future_tables_in_schema("public").to("reader").privileges(&[Select]))`,
+    /// so permissions are declared alongside the factory instead of in ad-hoc SQL. Building
+    /// the factory fails with [`InvalidGrant`](TmpPostgrustError::InvalidGrant) when the grant
+    /// names privileges the object does not have.
+    #[must_use]
+    pub fn with_grants(mut self, grant: Grant) -> Self {
+        self.grants.push(grant);
+        self
+    }
+
+    /// Check the configuration of instances with `postgres -C` when the factory is built,
+    /// failing with [`InvalidSettings`](TmpPostgrustError::InvalidSettings) listing every
+    /// unknown setting and invalid value, e.g. of a
+    /// [configuration fragment](Self::with_conf_fragment). Without the check an invalid
+    /// setting only shows up as a server that refuses to start.
+    #[must_use]
+    pub fn with_settings_validation(mut self, validate_settings: bool) -> Self {
+        self.validate_settings = validate_settings;
+        self
+    }
+
+    /// Wait up to `timeout` for a starting instance to accept connections, as reported by
+    /// `pg_isready`, and for its background workers, instead of 10 seconds, e.g. on slow CI
+    /// machines or with large `shared_buffers`.
+    #[must_use]
+    pub fn with_ready_timeout(mut self, timeout: Duration) -> Self {
+        self.ready_timeout = Some(timeout);
+        self
+    }
+
+    /// Make instances listen on `127.0.0.1` besides their unix socket, each on a port that was
+    /// free when the instance started, for tools that cannot use unix sockets such as JDBC
+    /// drivers or GUI clients. Connect with `tcp_connection_string` on the guard. Cannot be
+    /// combined with [`with_socket_hardening`](Self::with_socket_hardening).
+    #[must_use]
+    pub fn with_tcp(mut self, tcp: bool) -> Self {
+        self.tcp = tcp;
+        self
+    }
+
+    /// Guarantee that nothing else on the machine can connect to instances, for tests handling
+    /// sensitive fixtures. Besides TCP being disabled, the unix socket is only accessible to
+    /// the operating system user running the tests, `unix_socket_permissions = 0700`, and
+    /// `pg_hba.conf` only allows that user to connect with peer authentication as the
+    /// superuser or the database user. Starting an instance fails if its socket turns out to
+    /// be accessible to others.
+    #[must_use]
+    pub fn with_socket_hardening(mut self, socket_hardening: bool) -> Self {
+        self.socket_hardening = socket_hardening;
+        self
+    }
+
+    /// Create the database of every instance from `template`, e.g.
+    /// [`Template0`](DatabaseTemplate::Template0) to leave out objects added to `template1`
+    /// of a cached cluster. `template1` by default.
+    #[must_use]
+    pub fn with_database_template(mut self, template: DatabaseTemplate) -> Self {
+        self.database_template = template;
+        self
+    }
+
+    /// Run one long-lived server for the factory and have `new_instance` and its variants
+    /// clone a database of their own on it with `CREATE DATABASE ... TEMPLATE`, which takes
+    /// milliseconds instead of starting a server. Dropping the guard drops the database.
+    ///
+    /// The server is started with the first instance and counts against the limit of running
+    /// instances until the factory and every guard are dropped. Settings that belong to the
+    /// server, such as the clock of fake time, are shared by all of its instances, which
+    /// cannot be detached or have their checksums verified. Named instances, instances created
+    /// with options of their own or from the cache of another factory, and instances prepared
+    /// with `prepare_instance` to edit their configuration still get a server of their own.
+    #[must_use]
+    pub fn with_shared_server(mut self, shared_server: bool) -> Self {
+        self.shared_server = shared_server;
+        self
+    }
+
+    /// Initialize the cached cluster with `initdb --data-checksums`, so corrupted pages are
+    /// detected by the server and by
+    /// [`verify_checksums`](crate::synchronous::ProcessGuard::verify_checksums). A cache
+    /// directory that is already initialized keeps the setting it was initialized with.
+    #[must_use]
+    pub fn with_data_checksums(mut self, data_checksums: bool) -> Self {
+        self.data_checksums = data_checksums;
+        self
+    }
+
+    /// Initialize the cached cluster with `initdb --auth-local=<local> --auth-host=<host>`
+    /// instead of `trust`, e.g. [`ScramSha256`](AuthMethod::ScramSha256) as in production, so
+    /// client code handling authentication is tested faithfully. Methods requiring a password
+    /// need a [password file](Self::with_pwfile). A cache directory that is already
+    /// initialized keeps the methods it was initialized with.
+    #[must_use]
+    pub fn with_initdb_auth(mut self, local: AuthMethod, host: AuthMethod) -> Self {
+        self.auth_local = Some(local);
+        self.auth_host = Some(host);
+        self
+    }
+
+    /// Set the password of the superuser to the first line of the file at `path` with
+    /// `initdb --pwfile`. The role of the database of every instance gets a random password
+    /// of its own unless [`InstanceOptions::with_password`] sets one, and connection details of
+    /// instances include it.
+    #[must_use]
+    pub fn with_pwfile(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pwfile = Some(path.into());
+        self
+    }
+
+    /// Place the data directory of every instance on its own ext4 filesystem of `size` bytes,
+    /// backed by an image file mounted through a loop device, so running out of disk space,
+    /// e.g. failing commits and WAL writes, can be reproduced deterministically. The cached
+    /// cluster takes about 40MB of it.
+    ///
+    /// Mounting requires root, so `mount` and `umount` are run with `sudo -n`, failing without
+    /// passwordless `sudo` as available on most CI runners.
+    #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+    #[must_use]
+    pub fn with_data_directory_size(mut self, size: u64) -> Self {
+        self.data_directory_size = Some(size);
+        self
+    }
+
+    /// Limit the disk I/O of the server of every instance with `throttle`, to simulate a slow
+    /// database.
+    #[must_use]
+    pub fn with_io_throttle(mut self, throttle: IoThrottle) -> Self {
+        self.io_throttle = Some(throttle);
+        self
+    }
+
+    /// Advertise a short symlink such as `/tmp/tmp-postgrust/1234-5432` to the socket
+    /// directory in the connection details of instances instead of the directory itself, for
+    /// tools limiting the length of connection strings and for typing `psql` commands.
+    #[must_use]
+    pub fn with_socket_link(mut self, socket_link: bool) -> Self {
+        self.socket_link = socket_link;
+        self
+    }
+
+    /// Collect the [artifacts](crate::synchronous::ProcessGuard::collect_artifacts) of every
+    /// instance whose guard is dropped while the thread panics, e.g. because an assertion of
+    /// the test failed, into a directory named after its label and port inside `dir`, which
+    /// CI can upload. Shorthand for a [`FilesystemSink`].
+    #[must_use]
+    pub fn with_artifacts_on_panic(self, dir: impl Into<PathBuf>) -> Self {
+        self.with_artifact_sink(FilesystemSink::new(dir))
+    }
+
+    /// Hand the [artifacts](crate::synchronous::ProcessGuard::collect_artifacts) of every
+    /// instance whose guard is dropped while the thread panics to `sink`, e.g. one uploading
+    /// them to the storage of the CI pipeline. Sinks run in the order they were added.
+    #[must_use]
+    pub fn with_artifact_sink(mut self, sink: impl ArtifactSink + 'static) -> Self {
+        self.artifact_sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Check whether client connections are still open when an instance is stopped or dropped,
+    /// warning about them or panicking in [strict](ConnectionLeakCheck::Strict) mode. Off by
+    /// default.
+    #[must_use]
+    pub fn with_connection_leak_check(mut self, check: ConnectionLeakCheck) -> Self {
+        self.connection_leak_check = check;
+        self
+    }
+
+    /// Log to `log/postgresql.csv` in the data directory through the logging collector, which
+    /// [`audit_events`](crate::synchronous::ProcessGuard::audit_events) reads. The output of
+    /// the server no longer appears on the stderr of its process once it started.
+    #[must_use]
+    pub fn with_csv_log(mut self, csv_log: bool) -> Self {
+        self.csv_log = csv_log;
+        self
+    }
+
+    /// Log every statement sent to instances, including the parameters of prepared
+    /// statements, to the [CSV log](Self::with_csv_log), so the traffic of a test can be
+    /// listed with
+    /// [`recorded_statements`](crate::synchronous::ProcessGuard::recorded_statements), saved
+    /// and replayed against a fresh instance, or checked against a
+    /// [budget](crate::synchronous::ProcessGuard::start_budget).
+    #[must_use]
+    pub fn with_statement_recording(mut self, statement_recording: bool) -> Self {
+        self.statement_recording = statement_recording;
+        self
+    }
+
+    /// Preload `auto_explain` into every instance, logging the plans of statements running
+    /// for at least `min_duration` to the [CSV log](Self::with_csv_log), so the plans of slow
+    /// queries of a test can be listed with
+    /// [`slow_query_plans`](crate::synchronous::ProcessGuard::slow_query_plans).
+    #[must_use]
+    pub fn with_auto_explain(self, min_duration: Duration) -> Self {
+        self.with_background_worker_extension(BackgroundWorkerExtension::auto_explain(min_duration))
+            .with_csv_log(true)
+    }
+
+    /// Preload an extension running background workers, such as
+    /// [`pg_cron`](BackgroundWorkerExtension::pg_cron), into every instance and wait for its
+    /// workers to register when an instance starts.
+    #[must_use]
+    pub fn with_background_worker_extension(
+        mut self,
+        extension: BackgroundWorkerExtension,
+    ) -> Self {
+        self.background_workers.push(extension);
+        self
+    }
+
+    /// Set `max_worker_processes` of every instance instead of the server default of 8, e.g.
+    /// when background worker extensions start several workers each.
+    #[must_use]
+    pub fn with_max_worker_processes(mut self, max_worker_processes: u32) -> Self {
+        self.max_worker_processes = Some(max_worker_processes);
+        self
+    }
+
+    /// Apply the settings of `preset`. Settings configured afterwards take precedence.
+    #[must_use]
+    pub fn with_preset(self, preset: Preset) -> Self {
+        preset.apply(self)
+    }
+
+    /// Allow at most `limit` instances of the factory to run at the same time, counting both
+    /// the synchronous and the asynchronous API, instead of 8. Every factory has its own limit.
+    #[must_use]
+    pub fn with_max_concurrent_instances(mut self, limit: usize) -> Self {
+        self.max_concurrent_instances = Some(limit.max(1));
+        self
+    }
+
+    /// Let any number of instances of the factory run at the same time, e.g. on machines with
+    /// many cores where the default limit of 8 throttles the test suite.
+    #[must_use]
+    pub fn with_unlimited_concurrent_instances(mut self) -> Self {
+        self.max_concurrent_instances = Some(usize::MAX);
+        self
+    }
+
+    /// Set `max_connections` of every instance instead of the server default of 100.
+    #[must_use]
+    pub fn with_max_connections(mut self, max_connections: u32) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// What [`new_instance`](TmpPostgrustFactory::new_instance) and its variants do while the
+    /// limit of running instances is reached, see
+    /// [`with_max_concurrent_instances`](Self::with_max_concurrent_instances). By default they
+    /// wait in line.
+    #[must_use]
+    pub fn with_instance_limit_behavior(mut self, behavior: InstanceLimitBehavior) -> Self {
+        self.instance_limit_behavior = behavior;
+        self
+    }
+
+    /// Share the cached cluster and a limit of running instances with the factories of other
+    /// processes using the same `workspace_dir`, e.g. `target/tmp-postgrust` for every test
+    /// binary of a `cargo test` run in a workspace, coordinated with file locks. The cache
+    /// directory is set to `cache` inside `workspace_dir`.
+    #[must_use]
+    pub fn with_workspace_dir(mut self, workspace_dir: impl Into<PathBuf>) -> Self {
+        let workspace_dir = workspace_dir.into();
+        self.cache_dir = Some(Workspace::cache_dir(&workspace_dir));
+        self.workspace_dir = Some(workspace_dir);
+        self
+    }
+
+    /// Number of instances that may run at the same time across all factories sharing the
+    /// [`workspace_dir`](Self::with_workspace_dir), by default the available parallelism.
+    #[must_use]
+    pub fn with_workspace_instance_limit(mut self, instance_limit: usize) -> Self {
+        self.workspace_instance_limit = Some(instance_limit);
+        self
+    }
+
+    fn workspace(&self) -> TmpPostgrustResult<Option<Workspace>> {
+        let Some(workspace_dir) = &self.workspace_dir else {
+            return Ok(None);
+        };
+        let instance_limit = self.workspace_instance_limit.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        });
+        Workspace::new(workspace_dir, instance_limit)
+            .map(Some)
+            .map_err(TmpPostgrustError::WorkspaceFailed)
+    }
+
+    /// Container-safe shared memory preset for Docker based CI, where `/dev/shm` is limited
+    /// to 64MB and parallel queries crash with "could not resize shared memory segment".
+    /// Dynamic shared memory is backed by files in the data directory instead (`mmap`) and
+    /// `shared_buffers` stays at the default 12MB.
+    #[must_use]
+    pub fn with_container_safe_shm(mut self) -> Self {
+        self.dynamic_shared_memory_type = Some(DynamicSharedMemoryType::Mmap);
+        self.shared_buffers_mb = None;
+        self
+    }
+
+    /// Send every [step](BuildStep) of building the factory to `progress` when it starts, so
+    /// interactive tools can show progress instead of appearing hung while `initdb` runs. The
+    /// last step is [`Finished`](BuildStep::Finished), unless building fails.
+    #[must_use]
+    pub fn with_progress(mut self, progress: Sender<BuildStep>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn report(&self, step: BuildStep) {
+        if let Some(progress) = &self.progress {
+            // Nobody is interested in progress anymore once the receiver is gone.
+            let _ = progress.send(step);
+        }
+    }
+
+    /// Fail to build the factory unless the postgresql binaries match `manifest`, a manifest
+    /// previously exported with
+    /// [`binary_manifest`](TmpPostgrustFactory::binary_manifest).
+    #[must_use]
+    pub fn with_required_manifest(mut self, manifest: BinaryManifest) -> Self {
+        self.required_manifest = Some(manifest);
+        self
+    }
+
+    fn initdb_args(&self, platform: &Platform) -> Vec<String> {
+        let mut args: Vec<String> = platform
+            .initdb_args()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if self.data_checksums {
+            args.push("--data-checksums".to_string());
+        }
+        if let Some(local) = self.auth_local {
+            args.push(format!("--auth-local={}", local.name()));
+        }
+        if let Some(host) = self.auth_host {
+            args.push(format!("--auth-host={}", host.name()));
+        }
+        if let Some(pwfile) = &self.pwfile {
+            args.push(format!("--pwfile={}", pwfile.display()));
+        }
+        args
+    }
+
+    /// Fail before running `initdb` when it would refuse the authentication methods.
+    fn check_initdb_auth(&self) -> TmpPostgrustResult<()> {
+        let requires_password = [self.auth_local, self.auth_host]
+            .iter()
+            .flatten()
+            .any(|method| method.requires_password());
+        if requires_password && self.pwfile.is_none() {
+            return Err(TmpPostgrustError::SuperuserPasswordRequired);
+        }
+        Ok(())
+    }
+
+    /// Create the factory, running `initdb` unless the cache directory is already initialized.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the postgresql binaries cannot be found, `initdb` fails or the settings of the
+    /// factory are invalid, e.g. with [`InvalidSettings`](TmpPostgrustError::InvalidSettings).
+    #[instrument]
+    pub fn build(self) -> TmpPostgrustResult<TmpPostgrustFactory> {
+        self.report(BuildStep::LocatingBinaries);
+        search::snapshot_path();
+        self.check_initdb_auth()?;
+        if let Some(required) = &self.required_manifest {
+            BinaryManifest::collect()?.require(required)?;
+        }
+        let platform = Platform::detect();
+        let copy_strategy = detect_copy_strategy(&platform);
+        let temp_root = platform.temp_root()?;
+        let socket_dir = InstanceDir::temporary(&temp_root, "tmp-postgrust-socket")
+            .map_err(TmpPostgrustError::CreateSocketDirFailed)?;
+
+        let started = Instant::now();
+        let workspace = self.workspace()?;
+        // Only one process initializes the cache of a workspace, the others reuse it.
+        let cache_lock = workspace
+            .as_ref()
+            .map(Workspace::lock_cache)
+            .transpose()
+            .map_err(TmpPostgrustError::WorkspaceFailed)?;
+        let reused = self
+            .cache_dir
+            .as_ref()
+            .is_some_and(|cache_dir| cache_dir.join("PG_VERSION").exists());
+        let cache_dir = match &self.cache_dir {
+            None => {
+                let cache_dir = TempDir::new_in(&temp_root, "tmp-postgrust-cache")
+                    .map_err(TmpPostgrustError::CreateCacheDirFailed)?;
+                self.report(BuildStep::RunningInitdb);
+                crate::synchronous::exec_init_db(
+                    cache_dir.path(),
+                    &self.initdb_args(&platform),
+                    self.verbosity,
+                )?;
+                CacheDir::Temporary(cache_dir)
+            }
+            Some(cache_dir) if reused => {
+                self.report(BuildStep::ReusingCache);
+                info!("reusing initialized database cluster in {:?}", cache_dir);
+                CacheDir::Persistent(cache_dir.clone())
+            }
+            Some(cache_dir) => {
+                let partial = CacheDir::partial_path(cache_dir);
+                self.report(BuildStep::RunningInitdb);
+                std::fs::create_dir_all(&partial)
+                    .map_err(TmpPostgrustError::CreateCacheDirFailed)?;
+                crate::synchronous::exec_init_db(
+                    &partial,
+                    &self.initdb_args(&platform),
+                    self.verbosity,
+                )?;
+                CacheDir::persist(&partial, cache_dir)?;
+                CacheDir::Persistent(cache_dir.clone())
+            }
+        };
+        let cache_built = LifecycleEvent::CacheBuilt {
+            duration: started.elapsed(),
+            reused,
+        };
+        drop(cache_lock);
+
+        self.report(BuildStep::BuildingConfig);
+        let factory = TmpPostgrustFactory::from_builder(
+            &self,
+            socket_dir,
+            cache_dir,
+            copy_strategy,
+            &platform,
+            cache_built,
+            workspace.map(Arc::new),
+        )?;
+        if self.validate_settings {
+            self.report(BuildStep::ValidatingSettings);
+            factory.validate_settings()?;
+        }
+        self.report(BuildStep::Finished);
+        Ok(factory)
+    }
+
+    /// Build the factory on a background thread and return right away, see
+    /// [`TmpPostgrustFactory::spawn_lazy`].
+    #[cfg(feature = "tokio-process")]
+    #[must_use]
+    pub fn spawn_lazy(self) -> LazyFactory {
+        LazyFactory::spawn(self)
+    }
+
+    /// Create the factory, running `initdb` unless the cache directory is already initialized.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the postgresql binaries cannot be found, `initdb` fails or the settings of the
+    /// factory are invalid, e.g. with [`InvalidSettings`](TmpPostgrustError::InvalidSettings).
+    #[cfg(feature = "tokio-process")]
+    #[instrument]
+    pub async fn build_async(self) -> TmpPostgrustResult<TmpPostgrustFactory> {
+        self.report(BuildStep::LocatingBinaries);
+        search::snapshot_path();
+        self.check_initdb_auth()?;
+        if let Some(required) = self.required_manifest.clone() {
+            tokio::task::spawn_blocking(move || BinaryManifest::collect()?.require(&required))
+                .await
+                .map_err(|e| TmpPostgrustError::ManifestFailed(e.to_string()))??;
+        }
+        let (platform, copy_strategy) = tokio::task::spawn_blocking(|| {
+            let platform = Platform::detect();
+            let copy_strategy = detect_copy_strategy(&platform);
+            (platform, copy_strategy)
+        })
+        .await
+        .map_err(TmpPostgrustError::CopyCachedInitDBFailedJoinError)?;
+        let temp_root = platform.temp_root()?;
+        let socket_dir = InstanceDir::temporary(&temp_root, "tmp-postgrust-socket")
+            .map_err(TmpPostgrustError::CreateSocketDirFailed)?;
+
+        let started = Instant::now();
+        let workspace = self.workspace()?.map(Arc::new);
+        // Only one process initializes the cache of a workspace, the others reuse it.
+        let cache_lock = match &workspace {
+            Some(workspace) => {
+                let workspace = Arc::clone(workspace);
+                Some(
+                    tokio::task::spawn_blocking(move || workspace.lock_cache())
+                        .await
+                        .map_err(TmpPostgrustError::CopyCachedInitDBFailedJoinError)?
+                        .map_err(TmpPostgrustError::WorkspaceFailed)?,
+                )
+            }
+            None => None,
+        };
+        let reused = self
+            .cache_dir
+            .as_ref()
+            .is_some_and(|cache_dir| cache_dir.join("PG_VERSION").exists());
+        let cache_dir = match &self.cache_dir {
+            None => {
+                let cache_dir = TempDir::new_in(&temp_root, "tmp-postgrust-cache")
+                    .map_err(TmpPostgrustError::CreateCacheDirFailed)?;
+                self.report(BuildStep::RunningInitdb);
+                crate::asynchronous::exec_init_db(
+                    cache_dir.path(),
+                    &self.initdb_args(&platform),
+                    self.verbosity,
+                )
+                .await?;
+                CacheDir::Temporary(cache_dir)
+            }
+            Some(cache_dir) if reused => {
+                self.report(BuildStep::ReusingCache);
+                info!("reusing initialized database cluster in {:?}", cache_dir);
+                CacheDir::Persistent(cache_dir.clone())
+            }
+            Some(cache_dir) => {
+                let partial = CacheDir::partial_path(cache_dir);
+                self.report(BuildStep::RunningInitdb);
+                tokio::fs::create_dir_all(&partial)
+                    .await
+                    .map_err(TmpPostgrustError::CreateCacheDirFailed)?;
+                crate::asynchronous::exec_init_db(
+                    &partial,
+                    &self.initdb_args(&platform),
+                    self.verbosity,
+                )
+                .await?;
+                CacheDir::persist(&partial, cache_dir)?;
+                CacheDir::Persistent(cache_dir.clone())
+            }
+        };
+        let cache_built = LifecycleEvent::CacheBuilt {
+            duration: started.elapsed(),
+            reused,
+        };
+        drop(cache_lock);
+
+        self.report(BuildStep::BuildingConfig);
+        let factory = TmpPostgrustFactory::from_builder(
+            &self,
+            socket_dir,
+            cache_dir,
+            copy_strategy,
+            &platform,
+            cache_built,
+            workspace,
+        )?;
+        if self.validate_settings {
+            self.report(BuildStep::ValidatingSettings);
+            factory.validate_settings_async().await?;
+        }
+        self.report(BuildStep::Finished);
+        Ok(factory)
+    }
+}
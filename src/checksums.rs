@@ -0,0 +1,90 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use crate::errors::{ProcessCapture, TmpPostgrustError, TmpPostgrustResult};
+
+/// Block of a relation file whose contents do not match its checksum.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumFailure {
+    /// Path of the relation file.
+    pub file: PathBuf,
+    /// Number of the block within the file.
+    pub block: u64,
+}
+
+/// Result of verifying the data checksums of a stopped instance with `pg_checksums --check`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChecksumReport {
+    /// Number of relation files that were checked.
+    pub files_scanned: u64,
+    /// Number of blocks that were checked.
+    pub blocks_scanned: u64,
+    /// Blocks with a bad checksum.
+    pub failures: Vec<ChecksumFailure>,
+}
+
+impl ChecksumReport {
+    /// True when every block had a valid checksum.
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Arguments for `pg_checksums` to check the cluster in `data_directory`.
+pub(crate) fn check_args(data_directory: &Path) -> [&OsStr; 3] {
+    [
+        OsStr::new("--check"),
+        OsStr::new("--pgdata"),
+        data_directory.as_os_str(),
+    ]
+}
+
+fn parse_count(stdout: &str, name: &str) -> u64 {
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+/// Parse a line such as `pg_checksums: error: checksum verification failed in file "base/1/1259",
+/// block 0: calculated checksum 8B50 but block contains 2190`.
+fn parse_failure(line: &str) -> Option<ChecksumFailure> {
+    let (_, rest) = line.split_once("checksum verification failed in file \"")?;
+    let (file, rest) = rest.split_once("\", block ")?;
+    let (block, _) = rest.split_once(':')?;
+    Some(ChecksumFailure {
+        file: PathBuf::from(file),
+        block: block.parse().ok()?,
+    })
+}
+
+fn parse_report(capture: &ProcessCapture) -> ChecksumReport {
+    ChecksumReport {
+        files_scanned: parse_count(&capture.stdout, "Files scanned:"),
+        blocks_scanned: parse_count(&capture.stdout, "Blocks scanned:"),
+        failures: capture.stderr.lines().filter_map(parse_failure).collect(),
+    }
+}
+
+/// Turn the outcome of running `pg_checksums --check` into a report. Bad checksums make the tool
+/// fail, but are reported rather than returned as an error.
+pub(crate) fn checksum_report(
+    result: TmpPostgrustResult<ProcessCapture>,
+) -> TmpPostgrustResult<ChecksumReport> {
+    match result {
+        Ok(capture) => Ok(parse_report(&capture)),
+        Err(TmpPostgrustError::PgToolFailed(capture)) => {
+            if capture.stderr.contains("data checksums are not enabled") {
+                return Err(TmpPostgrustError::ChecksumsDisabled);
+            }
+            let report = parse_report(&capture);
+            if report.is_ok() {
+                Err(TmpPostgrustError::PgToolFailed(capture))
+            } else {
+                Ok(report)
+            }
+        }
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,79 @@
+use tracing::info;
+
+use crate::errors::TmpPostgrustResult;
+use crate::sql::quote_literal;
+use crate::synchronous::ProcessGuard;
+
+/// Local Citus cluster of a coordinator and its workers, each a separate instance, started
+/// with [`new_citus_cluster`](crate::TmpPostgrustFactory::new_citus_cluster). Every instance
+/// is stopped when the cluster is dropped.
+pub struct CitusCluster {
+    coordinator: ProcessGuard,
+    workers: Vec<ProcessGuard>,
+}
+
+impl CitusCluster {
+    /// Register `workers` with `coordinator`.
+    pub(crate) fn connect(
+        coordinator: ProcessGuard,
+        workers: Vec<ProcessGuard>,
+    ) -> TmpPostgrustResult<Self> {
+        coordinator.run_pg_tool(
+            "psql",
+            [
+                "-X",
+                "-v",
+                "ON_ERROR_STOP=1",
+                "-c",
+                &add_node_sql("citus_set_coordinator_host", &coordinator),
+            ],
+        )?;
+        for worker in &workers {
+            coordinator.run_pg_tool(
+                "psql",
+                [
+                    "-X",
+                    "-v",
+                    "ON_ERROR_STOP=1",
+                    "-c",
+                    &add_node_sql("citus_add_node", worker),
+                ],
+            )?;
+        }
+        info!("registered {} citus workers", workers.len());
+        Ok(CitusCluster {
+            coordinator,
+            workers,
+        })
+    }
+
+    /// Coordinator of the cluster, which distributed tables are created on and queried
+    /// through.
+    #[must_use]
+    pub fn coordinator(&self) -> &ProcessGuard {
+        &self.coordinator
+    }
+
+    /// Workers registered with the coordinator.
+    #[must_use]
+    pub fn workers(&self) -> &[ProcessGuard] {
+        &self.workers
+    }
+
+    /// Connection string of the coordinator.
+    #[must_use]
+    pub fn connection_string(&self) -> &str {
+        &self.coordinator.connection_string
+    }
+}
+
+/// Call the node management function `function` with the socket directory and port of `node`.
+fn add_node_sql(function: &str, node: &ProcessGuard) -> String {
+    "SELECT ".to_string()
+        + function
+        + "("
+        + &quote_literal(&node.auth.host.to_string_lossy())
+        + ", "
+        + &node.auth.port.to_string()
+        + ")"
+}
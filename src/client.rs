@@ -0,0 +1,206 @@
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+use tokio_postgres::types::FromSql;
+use tokio_postgres::{Client, NoTls, SimpleQueryMessage};
+use tracing::error;
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::sql::quote_ident;
+
+/// Connections opened at the same time by [`stress`], leaving room below the default
+/// `max_connections` of 100 for other clients of the instance.
+const MAX_STRESS_CONNECTIONS: usize = 64;
+
+/// Connect to `connection_string`, driving the connection on a background task.
+pub(crate) async fn connect(connection_string: &str) -> TmpPostgrustResult<Client> {
+    let (client, connection) = tokio_postgres::connect(connection_string, NoTls)
+        .await
+        .map_err(TmpPostgrustError::ClientFailed)?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            error!("connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+/// Run `sql`, which has to return exactly one row, and return its first column.
+pub(crate) async fn query_scalar<T>(connection_string: &str, sql: &str) -> TmpPostgrustResult<T>
+where
+    T: for<'a> FromSql<'a>,
+{
+    connect(connection_string)
+        .await?
+        .query_one(sql, &[])
+        .await
+        .and_then(|row| row.try_get(0))
+        .map_err(TmpPostgrustError::ClientFailed)
+}
+
+/// Run `sql` and panic unless its rows, in text format, equal `expected_rows`.
+pub(crate) async fn assert_query_eq(connection_string: &str, sql: &str, expected_rows: &[&[&str]]) {
+    let messages = connect(connection_string)
+        .await
+        .expect("failed to connect")
+        .simple_query(sql)
+        .await
+        .unwrap_or_else(|e| panic!("query `{}` failed: {}", sql, e));
+    let rows: Vec<Vec<&str>> = messages
+        .iter()
+        .filter_map(|message| match message {
+            SimpleQueryMessage::Row(row) => Some(
+                (0..row.len())
+                    .map(|i| row.get(i).unwrap_or("NULL"))
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rows, expected_rows, "unexpected rows returned by `{sql}`");
+}
+
+/// Connection with an open transaction that is rolled back once the closure passed to
+/// `with_rollback` finishes. Dereferences to the [`Client`] running the transaction.
+#[derive(Debug, Clone)]
+pub struct RollbackScope {
+    client: Arc<Client>,
+    depth: usize,
+}
+
+impl RollbackScope {
+    /// Run `f` in a nested scope whose changes are rolled back to a savepoint once it finishes,
+    /// while changes of the enclosing scope stay visible. Useful for table-driven sub-cases
+    /// sharing the setup of one test. Scopes on the same connection must not run concurrently.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if the savepoint cannot be
+    /// created or rolled back to.
+    pub async fn nested<F, Fut, T>(&self, f: F) -> TmpPostgrustResult<T>
+    where
+        F: FnOnce(RollbackScope) -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let depth = self.depth + 1;
+        let savepoint = format!("tmp_postgrust_scope_{depth}");
+        self.client
+            .batch_execute(&format!("SAVEPOINT {savepoint}"))
+            .await
+            .map_err(TmpPostgrustError::ClientFailed)?;
+        let output = f(RollbackScope {
+            client: Arc::clone(&self.client),
+            depth,
+        })
+        .await;
+        // Rolling back also recovers the transaction if a statement of the scope failed.
+        self.client
+            .batch_execute(&format!(
+                "ROLLBACK TO SAVEPOINT {savepoint}; RELEASE SAVEPOINT {savepoint}"
+            ))
+            .await
+            .map_err(TmpPostgrustError::ClientFailed)?;
+        Ok(output)
+    }
+}
+
+impl Deref for RollbackScope {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.client
+    }
+}
+
+/// Run `f` inside a transaction that is always rolled back, on a new connection.
+pub(crate) async fn with_rollback<F, Fut, T>(connection_string: &str, f: F) -> TmpPostgrustResult<T>
+where
+    F: FnOnce(RollbackScope) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let client = Arc::new(connect(connection_string).await?);
+    client
+        .batch_execute("BEGIN")
+        .await
+        .map_err(TmpPostgrustError::ClientFailed)?;
+    let output = f(RollbackScope {
+        client: Arc::clone(&client),
+        depth: 0,
+    })
+    .await;
+    client
+        .batch_execute("ROLLBACK")
+        .await
+        .map_err(TmpPostgrustError::ClientFailed)?;
+    Ok(output)
+}
+
+/// Run `f` on a new connection with the setting `name` set to `value` for the session, and
+/// reset it once `f` finished.
+pub(crate) async fn with_setting<F, Fut, T>(
+    connection_string: &str,
+    name: &str,
+    value: &str,
+    f: F,
+) -> TmpPostgrustResult<T>
+where
+    F: FnOnce(Arc<Client>) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let client = Arc::new(connect(connection_string).await?);
+    client
+        .execute("SELECT set_config($1, $2, false)", &[&name, &value])
+        .await
+        .map_err(TmpPostgrustError::ClientFailed)?;
+    let output = f(Arc::clone(&client)).await;
+    client
+        .batch_execute(&format!("RESET {}", quote_ident(name)))
+        .await
+        .map_err(TmpPostgrustError::ClientFailed)?;
+    Ok(output)
+}
+
+/// Run `f` on `n_connections` connections concurrently, returning the errors by connection
+/// index. A panic in `f` is resumed once every connection finished.
+pub(crate) async fn stress<F, Fut, E>(
+    connection_string: &str,
+    n_connections: usize,
+    f: F,
+) -> TmpPostgrustResult<Vec<(usize, E)>>
+where
+    F: Fn(usize, Client) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let f = Arc::new(f);
+    let permits = Arc::new(Semaphore::new(MAX_STRESS_CONNECTIONS));
+    let tasks: Vec<_> = (0..n_connections)
+        .map(|index| {
+            let f = Arc::clone(&f);
+            let permits = Arc::clone(&permits);
+            let connection_string = connection_string.to_string();
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await.unwrap();
+                let client = connect(&connection_string).await?;
+                Ok::<_, TmpPostgrustError>(f(index, client).await)
+            })
+        })
+        .collect();
+
+    let mut results = Vec::with_capacity(n_connections);
+    for task in tasks {
+        results.push(task.await);
+    }
+    let mut errors = Vec::new();
+    for (index, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(Ok(Ok(()))) => {}
+            Ok(Ok(Err(e))) => errors.push((index, e)),
+            Ok(Err(e)) => return Err(e),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    Ok(errors)
+}
@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use tracing::warn;
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::sql::quote_literal;
+
+/// Directives of `postgresql.conf` that are not settings.
+const INCLUDE_DIRECTIVES: [&str; 3] = ["include", "include_if_exists", "include_dir"];
+
+/// Raw `postgresql.conf` fragment appended verbatim after the generated settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ConfFragment {
+    pub(crate) path: PathBuf,
+    pub(crate) contents: String,
+}
+
+impl ConfFragment {
+    pub(crate) fn read(path: &Path) -> TmpPostgrustResult<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| TmpPostgrustError::ReadConfFragmentFailed(path.to_path_buf(), err))?;
+        Ok(ConfFragment {
+            path: path.to_path_buf(),
+            contents,
+        })
+    }
+}
+
+/// Name of the setting assigned by `line` of a configuration file, lowercased as postgresql
+/// treats them case insensitively.
+fn setting_name(line: &str) -> Option<String> {
+    let name = line
+        .trim_start()
+        .split(|c: char| c.is_whitespace() || c == '=')
+        .next()?
+        .to_lowercase();
+    (!name.is_empty() && !name.starts_with('#') && !INCLUDE_DIRECTIVES.contains(&name.as_str()))
+        .then_some(name)
+}
+
+/// Names of the settings assigned in `conf`.
+fn setting_names(conf: &str) -> impl Iterator<Item = String> + '_ {
+    conf.lines().filter_map(setting_name)
+}
+
+/// Check that no setting is assigned twice by `settings`.
+pub(crate) fn check_settings(settings: &[(String, String)]) -> TmpPostgrustResult<()> {
+    let mut names = BTreeSet::new();
+    for (name, _) in settings {
+        if !names.insert(name.to_lowercase()) {
+            return Err(TmpPostgrustError::DuplicateConfSetting(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Merge `settings` into the generated `config`, replacing generated assignments of the same
+/// settings.
+pub(crate) fn merge_settings(config: &mut String, settings: &[(String, String)]) {
+    let names: BTreeSet<String> = settings
+        .iter()
+        .map(|(name, _)| name.to_lowercase())
+        .collect();
+    let mut merged: String = config
+        .lines()
+        .filter(|line| setting_name(line).is_none_or(|name| !names.contains(&name)))
+        .flat_map(|line| [line, "\n"])
+        .collect();
+    for (name, value) in settings {
+        merged.push_str(name);
+        merged.push_str(" = ");
+        merged.push_str(&quote_literal(value));
+        merged.push('\n');
+    }
+    *config = merged;
+}
+
+/// Setting assigned more than once, where the last assignment wins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ConfConflict {
+    pub(crate) setting: String,
+    /// Fragment whose assignment is overridden, `None` for the generated settings.
+    pub(crate) overridden: Option<PathBuf>,
+    pub(crate) by: PathBuf,
+}
+
+/// Settings of `fragments` that override the `generated` settings or an earlier fragment.
+pub(crate) fn conflicts(generated: &str, fragments: &[ConfFragment]) -> Vec<ConfConflict> {
+    let mut assigned: BTreeMap<String, Option<&Path>> =
+        setting_names(generated).map(|name| (name, None)).collect();
+    let mut conflicts = Vec::new();
+    for fragment in fragments {
+        for setting in setting_names(&fragment.contents) {
+            match assigned.insert(setting.clone(), Some(&fragment.path)) {
+                Some(overridden) if overridden != Some(&fragment.path) => {
+                    conflicts.push(ConfConflict {
+                        setting,
+                        overridden: overridden.map(Path::to_path_buf),
+                        by: fragment.path.clone(),
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+    conflicts
+}
+
+/// Warn about the settings of `fragments` that override other settings.
+pub(crate) fn warn_conflicts(generated: &str, fragments: &[ConfFragment]) {
+    for conflict in conflicts(generated, fragments) {
+        match &conflict.overridden {
+            None => warn!(
+                "{} overrides the generated setting {}, which may break instances",
+                conflict.by.display(),
+                conflict.setting
+            ),
+            Some(overridden) => warn!(
+                "{} overrides setting {} of {}",
+                conflict.by.display(),
+                conflict.setting,
+                overridden.display()
+            ),
+        }
+    }
+}
+
+/// Append `fragments` to the generated `config` in order.
+pub(crate) fn append_fragments(config: &mut String, fragments: &[ConfFragment]) {
+    for fragment in fragments {
+        config.push_str("\n# ");
+        config.push_str(&fragment.path.to_string_lossy());
+        config.push('\n');
+        config.push_str(&fragment.contents);
+        if !fragment.contents.ends_with('\n') {
+            config.push('\n');
+        }
+    }
+}
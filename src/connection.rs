@@ -0,0 +1,182 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::auth::AuthContext;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::sql::{quote_ident, quote_literal, split_records};
+
+/// Address of the loopback interface instances listen on when they listen on TCP.
+pub(crate) const TCP_HOST: &str = "127.0.0.1";
+
+/// Structured details for connecting to an instance, e.g. to persist them in a setup binary
+/// and reload them in a test binary. With the `serde` feature it implements `Serialize` and
+/// `Deserialize`; use [`redacted`](Self::redacted) before writing it somewhere credentials
+/// must not end up.
+#[derive(Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionInfo {
+    /// Directory containing the unix socket of the server, or the address it listens on over
+    /// TCP.
+    pub host: PathBuf,
+    /// Port the server listens on.
+    pub port: u32,
+    /// Database user.
+    pub user: String,
+    /// Password of the user, if it needs one.
+    pub password: Option<String>,
+    /// Database created for the user.
+    pub dbname: String,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(auth: &AuthContext, dbname: &str) -> Self {
+        ConnectionInfo {
+            host: auth.host.clone(),
+            port: auth.port,
+            user: auth.user.clone(),
+            password: auth.password.clone(),
+            dbname: dbname.to_string(),
+        }
+    }
+
+    /// The same connection details without the password, which has to be supplied separately,
+    /// e.g. through `PGPASSWORD`.
+    #[must_use]
+    pub fn redacted(&self) -> Self {
+        ConnectionInfo {
+            password: None,
+            ..self.clone()
+        }
+    }
+
+    /// `postgresql://` connection string for the details.
+    #[must_use]
+    pub fn connection_string(&self) -> String {
+        let credentials = match &self.password {
+            Some(password) => percent_encode(&self.user) + ":" + &percent_encode(password),
+            None => percent_encode(&self.user),
+        };
+        if !self.host.is_absolute() {
+            return format!(
+                "postgresql://{}@{}:{}/{}",
+                credentials,
+                self.host.to_string_lossy(),
+                self.port,
+                percent_encode(&self.dbname)
+            );
+        }
+        format!(
+            "postgresql://{}@localhost:{}/{}?host={}",
+            credentials,
+            self.port,
+            percent_encode(&self.dbname),
+            percent_encode(&self.host.to_string_lossy())
+        )
+    }
+
+    /// `jdbc:postgresql://` URL for the details with the credentials as properties, for JVM
+    /// services started by the tests.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`TcpRequired`](TmpPostgrustError::TcpRequired) unless the server listens on
+    /// TCP, as the JDBC driver cannot connect to unix sockets.
+    pub fn jdbc_url(&self) -> TmpPostgrustResult<String> {
+        if self.host.is_absolute() {
+            return Err(TmpPostgrustError::TcpRequired);
+        }
+        let mut url = "jdbc:postgresql://".to_string() + &self.host.to_string_lossy();
+        url.push(':');
+        url.push_str(&self.port.to_string());
+        url.push('/');
+        url.push_str(&percent_encode(&self.dbname));
+        url.push_str("?user=");
+        url.push_str(&percent_encode(&self.user));
+        if let Some(password) = &self.password {
+            url.push_str("&password=");
+            url.push_str(&percent_encode(password));
+        }
+        Ok(url)
+    }
+
+    /// libpq keyword/value connection string for the details, e.g.
+    /// `host=/tmp/socket port=5432 user=demo_user dbname=demo`, for tools that do not accept URLs.
+    #[must_use]
+    pub fn kv_connection_string(&self) -> String {
+        let mut connection_string = "host=".to_string() + &kv_quote(&self.host.to_string_lossy());
+        connection_string.push_str(" port=");
+        connection_string.push_str(&self.port.to_string());
+        connection_string.push_str(" user=");
+        connection_string.push_str(&kv_quote(&self.user));
+        if let Some(password) = &self.password {
+            connection_string.push_str(" password=");
+            connection_string.push_str(&kv_quote(password));
+        }
+        connection_string.push_str(" dbname=");
+        connection_string.push_str(&kv_quote(&self.dbname));
+        connection_string
+    }
+}
+
+/// Quote `value` for a keyword/value connection string when it is empty or contains spaces,
+/// quotes or backslashes.
+fn kv_quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains([' ', '\'', '\\']) {
+        return value.to_string();
+    }
+    "'".to_string() + &value.replace('\\', "\\\\").replace('\'', "\\'") + "'"
+}
+
+/// Query telling whether the role `user` and the database `dbname` exist.
+pub(crate) fn existence_query(user: &str, dbname: &str) -> String {
+    "SELECT EXISTS (SELECT FROM pg_roles WHERE rolname = ".to_string()
+        + &quote_literal(user)
+        + "), EXISTS (SELECT FROM pg_database WHERE datname = "
+        + &quote_literal(dbname)
+        + ")"
+}
+
+/// Parse whether the role and the database exist from the output of [`existence_query`].
+pub(crate) fn parse_existence(output: &str) -> (bool, bool) {
+    split_records(output)
+        .next()
+        .map_or((false, false), |fields| {
+            (fields.first() == Some(&"t"), fields.get(1) == Some(&"t"))
+        })
+}
+
+/// Statement creating the role `user`, which can log in without a password.
+pub(crate) fn create_role_sql(user: &str) -> String {
+    "CREATE ROLE ".to_string() + &quote_ident(user) + " LOGIN"
+}
+
+/// Statement creating the database `dbname` owned by `user`.
+pub(crate) fn create_database_sql(user: &str, dbname: &str) -> String {
+    "CREATE DATABASE ".to_string() + &quote_ident(dbname) + " OWNER " + &quote_ident(user)
+}
+
+/// Never prints the password.
+impl fmt::Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionInfo")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("dbname", &self.dbname)
+            .finish()
+    }
+}
+
+/// Escape everything but unreserved characters and `/`, which is common in socket paths.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
@@ -0,0 +1,147 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use tempdir::TempDir;
+use tracing::{info, instrument};
+
+use crate::platform::Platform;
+
+/// How the cached database cluster is copied into the data directory of a new instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyStrategy {
+    /// `cp` with copy-on-write clones (`--reflink` on Linux, `-c` on macOS), which makes
+    /// copies nearly free on filesystems such as btrfs, xfs and apfs.
+    Reflink,
+    /// Plain recursive `cp`, for filesystems or `cp` implementations without clone support,
+    /// such as the `cp` of the BSDs.
+    Cp,
+    /// Recursive copy implemented in Rust, for systems where `cp` is unavailable or unusable.
+    Native,
+}
+
+impl CopyStrategy {
+    /// Arguments for `cp` implementing this strategy, or `None` for the native copy.
+    pub(crate) fn cp_args(self) -> Option<&'static [&'static str]> {
+        match self {
+            #[cfg(target_os = "macos")]
+            CopyStrategy::Reflink => Some(&["-R", "-c"]),
+            #[cfg(not(target_os = "macos"))]
+            CopyStrategy::Reflink => Some(&["-R", "--reflink=auto"]),
+            CopyStrategy::Cp => Some(&["-R"]),
+            CopyStrategy::Native => None,
+        }
+    }
+}
+
+/// Pick the best copy strategy that works in the temporary directory by copying a small file
+/// with each candidate. `BusyBox` `cp` is never used as its behaviour differs between builds.
+#[instrument]
+pub(crate) fn detect_copy_strategy(platform: &Platform) -> CopyStrategy {
+    let strategy = if platform.busybox_cp {
+        CopyStrategy::Native
+    } else {
+        probe_copy_strategy(platform).unwrap_or(CopyStrategy::Native)
+    };
+    info!("copying cached databases with {:?}", strategy);
+    strategy
+}
+
+/// Strategies tried in order of preference, with the `cp` arguments that only succeed if the
+/// strategy works. The `cp` of the BSDs cannot clone files.
+#[cfg(target_os = "macos")]
+const PROBED_STRATEGIES: [(CopyStrategy, &[&str]); 2] =
+    [(CopyStrategy::Reflink, &["-c"]), (CopyStrategy::Cp, &[])];
+#[cfg(target_os = "linux")]
+const PROBED_STRATEGIES: [(CopyStrategy, &[&str]); 2] = [
+    (CopyStrategy::Reflink, &["--reflink=always"]),
+    (CopyStrategy::Cp, &[]),
+];
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+const PROBED_STRATEGIES: [(CopyStrategy, &[&str]); 1] = [(CopyStrategy::Cp, &[])];
+
+fn probe_copy_strategy(platform: &Platform) -> io::Result<CopyStrategy> {
+    // Probed where the data directories of instances are created.
+    let temp_root = platform
+        .temp_root
+        .clone()
+        .unwrap_or_else(std::env::temp_dir);
+    let probe_dir = TempDir::new_in(temp_root, "tmp-postgrust-copy-probe")?;
+    let source = probe_dir.path().join("source");
+    fs::write(&source, b"tmp-postgrust")?;
+
+    for (strategy, args) in PROBED_STRATEGIES {
+        // Cloning may appear to work on overlayfs but copies up the whole file anyway.
+        if platform.overlay_tmp && strategy == CopyStrategy::Reflink {
+            continue;
+        }
+        let destination = probe_dir.path().join(format!("{strategy:?}"));
+        let copied = Command::new("cp")
+            .args(args)
+            .arg(&source)
+            .arg(&destination)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        if copied && fs::read(&destination)? == b"tmp-postgrust" {
+            return Ok(strategy);
+        }
+    }
+    Ok(CopyStrategy::Native)
+}
+
+/// Top level entries of the cached cluster that a new instance never needs: leftovers of a
+/// server that ran in the cache directory and its log files.
+pub(crate) const DEFAULT_COPY_EXCLUDES: [&str; 4] = [
+    "postmaster.pid",
+    "postmaster.opts",
+    "current_logfiles",
+    "log",
+];
+
+/// Top level entries of `src_dir` that are copied into a new data directory, skipping
+/// entries named in `excludes`.
+pub(crate) fn copy_sources(src_dir: &Path, excludes: &[OsString]) -> io::Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for entry in src_dir.read_dir()? {
+        let entry = entry?;
+        if !excludes.contains(&entry.file_name()) {
+            sources.push(entry.path());
+        }
+    }
+    Ok(sources)
+}
+
+/// Copy `sources` into the existing directory `dst_dir` without spawning `cp`.
+pub(crate) fn copy_native(sources: &[PathBuf], dst_dir: &Path) -> io::Result<()> {
+    for source in sources {
+        if let Some(file_name) = source.file_name() {
+            copy_path_native(source, &dst_dir.join(file_name))?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_path_native(source: &Path, destination: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(source)?;
+    if metadata.is_dir() {
+        fs::create_dir(destination)?;
+        fs::set_permissions(destination, metadata.permissions())?;
+        for entry in source.read_dir()? {
+            let entry = entry?;
+            copy_path_native(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+    } else if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(fs::read_link(source)?, destination)?;
+        // Without unix symlinks the target is copied instead, whether a file or a directory.
+        #[cfg(not(unix))]
+        copy_path_native(&fs::canonicalize(source)?, destination)?;
+    } else {
+        fs::copy(source, destination)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing::error;
+
+use crate::activity;
+#[cfg(feature = "tokio-process")]
+use crate::asynchronous;
+use crate::auth::AuthContext;
+use crate::builder::Verbosity;
+use crate::connection::ConnectionInfo;
+use crate::errors::TmpPostgrustResult;
+use crate::sql::quote_ident;
+use crate::synchronous::{self, ProcessGuard};
+
+/// Database on the shared server that every shared database is cloned from.
+pub(crate) const TEMPLATE_DATABASE: &str = "template_cache";
+
+/// Long-lived server of a factory whose databases are cloned from [`TEMPLATE_DATABASE`].
+pub(crate) struct SharedServer {
+    pub(crate) guard: ProcessGuard,
+    next_database: AtomicU32,
+}
+
+impl SharedServer {
+    pub(crate) fn new(guard: ProcessGuard) -> Self {
+        SharedServer {
+            guard,
+            next_database: AtomicU32::new(1),
+        }
+    }
+
+    /// Name for the next database cloned on the server.
+    pub(crate) fn next_database_name(&self) -> String {
+        format!(
+            "{}_{}",
+            crate::DATABASE_NAME,
+            self.next_database.fetch_add(1, Ordering::SeqCst)
+        )
+    }
+}
+
+impl fmt::Debug for SharedServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedServer")
+            .field("port", &self.guard.auth.port)
+            .field("next_database", &self.next_database)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Statement closing the template to connections, which would make cloning it fail.
+pub(crate) fn close_template_sql() -> String {
+    format!(
+        "ALTER DATABASE {} ALLOW_CONNECTIONS false;",
+        quote_ident(TEMPLATE_DATABASE)
+    )
+}
+
+/// Statement cloning the template as `dbname` owned by `owner`.
+pub(crate) fn clone_template_sql(dbname: &str, owner: &str) -> String {
+    format!(
+        "CREATE DATABASE {} TEMPLATE {} OWNER {};",
+        quote_ident(dbname),
+        quote_ident(TEMPLATE_DATABASE),
+        quote_ident(owner)
+    )
+}
+
+/// Statements dropping `dbname` after disconnecting its remaining sessions, which
+/// `DROP DATABASE ... WITH (FORCE)` only does from version 13 on. They run one by one, as
+/// `DROP DATABASE` cannot run in the transaction of a query with several statements.
+pub(crate) fn drop_database_statements(dbname: &str) -> [String; 3] {
+    [
+        format!(
+            "ALTER DATABASE {} ALLOW_CONNECTIONS false;",
+            quote_ident(dbname)
+        ),
+        activity::terminate_database_query(dbname),
+        format!("DROP DATABASE {};", quote_ident(dbname)),
+    ]
+}
+
+/// Databases created with `create_database` on the guard of an instance that were not dropped
+/// yet, in the order they were created.
+#[derive(Debug, Default)]
+pub(crate) struct CreatedDatabases(Mutex<Vec<String>>);
+
+impl CreatedDatabases {
+    pub(crate) fn track(&self, dbname: &str) {
+        self.0.lock().unwrap().push(dbname.to_string());
+    }
+
+    /// Stop tracking `dbname`, returning whether it was still tracked, i.e. not dropped by the
+    /// guard of the instance already.
+    fn untrack(&self, dbname: &str) -> bool {
+        let mut dbnames = self.0.lock().unwrap();
+        let tracked = dbnames.iter().position(|tracked| tracked == dbname);
+        tracked.map(|index| dbnames.remove(index)).is_some()
+    }
+
+    /// Drop the databases still tracked, newest first, when the guard of the instance stops
+    /// managing it, so instances left running do not accumulate databases whose guards
+    /// outlived them or were leaked. Failures are logged, as this runs while the guard is
+    /// dropped.
+    pub(crate) fn drop_all(&self, superuser: &AuthContext, verbosity: Verbosity) {
+        let dbnames = std::mem::take(&mut *self.0.lock().unwrap());
+        for dbname in dbnames.iter().rev() {
+            drop_database(superuser, dbname, verbosity);
+        }
+    }
+}
+
+/// Drop `dbname`, logging failures.
+fn drop_database(superuser: &AuthContext, dbname: &str, verbosity: Verbosity) {
+    let dropped = drop_database_statements(dbname)
+        .iter()
+        .try_for_each(|sql| synchronous::exec_psql(superuser, "postgres", sql, verbosity));
+    log_drop_failure(superuser, dbname, dropped);
+}
+
+/// Drop `dbname` like [`drop_database`] without blocking the runtime.
+#[cfg(feature = "tokio-process")]
+async fn drop_database_async(superuser: &AuthContext, dbname: &str, verbosity: Verbosity) {
+    let mut dropped = Ok(());
+    for sql in &drop_database_statements(dbname) {
+        dropped = asynchronous::exec_psql(superuser, "postgres", sql, verbosity).await;
+        if dropped.is_err() {
+            break;
+        }
+    }
+    log_drop_failure(superuser, dbname, dropped);
+}
+
+fn log_drop_failure(superuser: &AuthContext, dbname: &str, dropped: TmpPostgrustResult<()>) {
+    if let Err(err) = dropped {
+        error!(
+            "failed to drop database {} on port {}: {}",
+            dbname, superuser.port, err
+        );
+    }
+}
+
+/// Database created with `create_database` on the guard of an instance, dropped when the guard
+/// is dropped. Databases still existing when the guard of the instance stops, persists or
+/// detaches it are dropped by that guard, newest first.
+///
+/// Guards of databases created by the asynchronous guard of an instance drop them on the
+/// runtime they were created on instead of blocking it.
+pub struct DatabaseGuard {
+    /// Connection string for connecting to the database.
+    pub connection_string: String,
+
+    // Connection target and credentials of the application user.
+    pub(crate) auth: AuthContext,
+    // Database created for the application user.
+    pub(crate) dbname: String,
+    // How much output of client tools is logged.
+    pub(crate) verbosity: Verbosity,
+    // Keep the shared server running while the database exists.
+    #[cfg_attr(not(feature = "tokio-process"), allow(dead_code))]
+    pub(crate) server: Option<Arc<SharedServer>>,
+    // Databases of the instance the database was created on with `create_database`.
+    pub(crate) created: Option<Arc<CreatedDatabases>>,
+    // The database was dropped already or is kept.
+    pub(crate) released: bool,
+    // Runtime the database is dropped on, for databases created asynchronously.
+    #[cfg(feature = "tokio-process")]
+    pub(crate) runtime: Option<tokio::runtime::Handle>,
+}
+
+impl DatabaseGuard {
+    pub(crate) fn new(
+        auth: AuthContext,
+        dbname: String,
+        verbosity: Verbosity,
+        server: Option<Arc<SharedServer>>,
+        created: Option<Arc<CreatedDatabases>>,
+    ) -> Self {
+        DatabaseGuard {
+            connection_string: crate::instance_connection_string(&auth, &dbname, &auth.host),
+            auth,
+            dbname,
+            verbosity,
+            server,
+            created,
+            released: false,
+            #[cfg(feature = "tokio-process")]
+            runtime: None,
+        }
+    }
+
+    /// Drop the database on the current runtime instead of blocking it.
+    #[cfg(feature = "tokio-process")]
+    pub(crate) fn on_current_runtime(mut self) -> Self {
+        self.runtime = tokio::runtime::Handle::try_current().ok();
+        self
+    }
+
+    /// Leave the database in place when the guard is dropped.
+    pub(crate) fn keep(&mut self) {
+        self.released = true;
+    }
+
+    /// Stop tracking the database with the instance it was created on, returning whether it
+    /// still has to be dropped.
+    fn release(&mut self) -> bool {
+        if std::mem::replace(&mut self.released, true) {
+            return false;
+        }
+        self.created
+            .as_ref()
+            .is_none_or(|created| created.untrack(&self.dbname))
+    }
+
+    /// Drop the database now, waiting for it to be gone.
+    #[cfg(feature = "tokio-process")]
+    pub(crate) async fn drop_async(mut self) {
+        if self.release() {
+            drop_database_async(&self.auth.as_superuser(), &self.dbname, self.verbosity).await;
+        }
+    }
+
+    /// Name of the database.
+    #[must_use]
+    pub fn dbname(&self) -> &str {
+        &self.dbname
+    }
+
+    /// Host, port, user, password and database of the database.
+    #[must_use]
+    pub fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo::new(&self.auth, &self.dbname)
+    }
+}
+
+impl Drop for DatabaseGuard {
+    fn drop(&mut self) {
+        if !self.release() {
+            return;
+        }
+        let superuser = self.auth.as_superuser();
+        #[cfg(feature = "tokio-process")]
+        if let Some(runtime) = &self.runtime {
+            let dbname = self.dbname.clone();
+            let verbosity = self.verbosity;
+            // Keep the shared server running until the database is gone.
+            let server = self.server.take();
+            runtime.spawn(async move {
+                drop_database_async(&superuser, &dbname, verbosity).await;
+                // Stopping the server blocks if this was the last reference to it.
+                tokio::task::spawn_blocking(move || drop(server));
+            });
+            return;
+        }
+        drop_database(&superuser, &self.dbname, self.verbosity);
+    }
+}
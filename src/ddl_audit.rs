@@ -0,0 +1,53 @@
+use crate::sql::{split_records, unaligned_query_args};
+
+/// Create an event trigger recording every DDL command in `tmp_postgrust_audit.ddl_history`.
+/// The trigger function runs as the superuser, so any role can run DDL while it is installed.
+pub(crate) const INSTALL_SQL: &str = "
+CREATE SCHEMA tmp_postgrust_audit;
+CREATE TABLE tmp_postgrust_audit.ddl_history (
+    id bigserial PRIMARY KEY,
+    command_tag text NOT NULL,
+    query text NOT NULL
+);
+CREATE FUNCTION tmp_postgrust_audit.record_ddl() RETURNS event_trigger
+LANGUAGE plpgsql SECURITY DEFINER SET search_path = pg_catalog
+AS $$
+BEGIN
+    INSERT INTO tmp_postgrust_audit.ddl_history (command_tag, query)
+    VALUES (tg_tag, current_query());
+END
+$$;
+CREATE EVENT TRIGGER tmp_postgrust_ddl_audit ON ddl_command_end
+EXECUTE FUNCTION tmp_postgrust_audit.record_ddl();
+";
+
+/// Lists recorded DDL in the order it ran.
+const HISTORY_QUERY: &str =
+    "SELECT command_tag, query FROM tmp_postgrust_audit.ddl_history ORDER BY id;";
+
+/// DDL command recorded by the audit event trigger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DdlCommand {
+    /// Command tag such as `CREATE TABLE` or `ALTER INDEX`.
+    pub command_tag: String,
+    /// Text of the query that ran the command, which contains every statement if several
+    /// were sent at once.
+    pub query: String,
+}
+
+/// Arguments for `psql` to print the DDL history in a parseable form.
+pub(crate) fn history_query_args() -> [&'static str; 9] {
+    unaligned_query_args(HISTORY_QUERY)
+}
+
+/// Parse the output of the DDL history query.
+pub(crate) fn parse_history(output: &str) -> Vec<DdlCommand> {
+    split_records(output)
+        .filter_map(|fields| {
+            Some(DdlCommand {
+                command_tag: (*fields.first()?).to_string(),
+                query: (*fields.get(1)?).to_string(),
+            })
+        })
+        .collect()
+}
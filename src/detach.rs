@@ -0,0 +1,173 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use tracing::{info, instrument, warn};
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::socket_path;
+
+/// Running server handed off by [`detach`](crate::synchronous::ProcessGuard::detach), so
+/// another process can adopt it with [`attach`].
+///
+/// Nothing stops a detached server; it keeps running until it is attached again and the
+/// [`AttachedInstance`] is dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachedInstance {
+    /// Process id of the postmaster.
+    pub pid: u32,
+    /// Label of the instance.
+    pub label: String,
+    /// Connection string for the database user.
+    pub connection_string: String,
+    /// Directory containing the unix socket of the server.
+    pub host: PathBuf,
+    /// Port the server listens on.
+    pub port: u32,
+    /// Database user.
+    pub user: String,
+    /// Database created for the user.
+    pub dbname: String,
+    /// Data directory of the server.
+    pub data_directory: PathBuf,
+}
+
+impl DetachedInstance {
+    /// Write the state file describing the instance.
+    pub(crate) fn write(&self, state_file: &Path) -> TmpPostgrustResult<()> {
+        std::fs::write(state_file, self.to_string()).map_err(TmpPostgrustError::StateFileFailed)
+    }
+
+    fn lock_file(&self) -> PathBuf {
+        let mut lock_file = socket_path(&self.host, self.port).into_os_string();
+        lock_file.push(".lock");
+        PathBuf::from(lock_file)
+    }
+}
+
+/// One `key<TAB>value` line per field.
+impl fmt::Display for DetachedInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pid\t{}", self.pid)?;
+        writeln!(f, "label\t{}", self.label)?;
+        writeln!(f, "connection_string\t{}", self.connection_string)?;
+        writeln!(f, "host\t{}", self.host.display())?;
+        writeln!(f, "port\t{}", self.port)?;
+        writeln!(f, "user\t{}", self.user)?;
+        writeln!(f, "dbname\t{}", self.dbname)?;
+        writeln!(f, "data_directory\t{}", self.data_directory.display())
+    }
+}
+
+impl FromStr for DetachedInstance {
+    type Err = TmpPostgrustError;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        let field = |key: &str| {
+            state
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('\t'))
+                .ok_or_else(|| TmpPostgrustError::InvalidStateFile(format!("missing {key}")))
+        };
+        let number = |key: &str| {
+            field(key)?
+                .parse()
+                .map_err(|_| TmpPostgrustError::InvalidStateFile(format!("invalid {key}")))
+        };
+        Ok(DetachedInstance {
+            pid: number("pid")?,
+            label: field("label")?.to_string(),
+            connection_string: field("connection_string")?.to_string(),
+            host: PathBuf::from(field("host")?),
+            port: number("port")?,
+            user: field("user")?.to_string(),
+            dbname: field("dbname")?.to_string(),
+            data_directory: PathBuf::from(field("data_directory")?),
+        })
+    }
+}
+
+/// Resume managing the server described by `state_file`, written by
+/// [`detach`](crate::synchronous::ProcessGuard::detach) in this or another process.
+///
+/// # Errors
+///
+/// Fails with [`StateFileFailed`](TmpPostgrustError::StateFileFailed) if `state_file` cannot be
+/// read, with [`InvalidStateFile`](TmpPostgrustError::InvalidStateFile) if it is malformed and with
+/// [`AttachFailed`](TmpPostgrustError::AttachFailed) if the server is no longer running.
+#[instrument]
+pub fn attach(state_file: &Path) -> TmpPostgrustResult<AttachedInstance> {
+    let state = std::fs::read_to_string(state_file).map_err(TmpPostgrustError::StateFileFailed)?;
+    let instance: DetachedInstance = state.parse()?;
+    if !socket_path(&instance.host, instance.port).exists() {
+        return Err(TmpPostgrustError::AttachFailed(format!(
+            "instance {} is not running on port {}",
+            instance.label, instance.port
+        )));
+    }
+    info!(
+        "attached to instance {} (pid {})",
+        instance.label, instance.pid
+    );
+    Ok(AttachedInstance {
+        instance,
+        state_file: state_file.to_path_buf(),
+        detached: false,
+    })
+}
+
+/// Server adopted with [`attach`]. Dropping it stops the server and removes its data
+/// directory and state file. Without the `unix-signals` feature the server cannot be stopped,
+/// so it is left running with its files in place.
+#[derive(Debug)]
+pub struct AttachedInstance {
+    instance: DetachedInstance,
+    state_file: PathBuf,
+    detached: bool,
+}
+
+impl AttachedInstance {
+    /// Details of the adopted instance.
+    #[must_use]
+    pub fn instance(&self) -> &DetachedInstance {
+        &self.instance
+    }
+
+    /// Connection string for the database user.
+    #[must_use]
+    pub fn connection_string(&self) -> &str {
+        &self.instance.connection_string
+    }
+
+    /// Stop managing the server again, leaving it running for the next process to attach.
+    #[must_use]
+    pub fn detach(mut self) -> DetachedInstance {
+        self.detached = true;
+        self.instance.clone()
+    }
+}
+
+impl Drop for AttachedInstance {
+    fn drop(&mut self) {
+        if self.detached {
+            return;
+        }
+        let instance = &self.instance;
+        if let Err(err) =
+            crate::stop_unmanaged_server(&instance.label, Some(instance.pid), &instance.lock_file())
+        {
+            warn!("failed to stop instance {}: {}", instance.label, err);
+            return;
+        }
+        if let Err(err) = std::fs::remove_dir_all(&instance.data_directory) {
+            warn!(
+                "failed to remove directory {}: {}",
+                instance.data_directory.display(),
+                err
+            );
+        }
+        // Other instances of the factory that created it may still use the socket directory.
+        let _ = std::fs::remove_dir(&instance.host);
+        let _ = std::fs::remove_file(&self.state_file);
+    }
+}
@@ -0,0 +1,153 @@
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tempdir::TempDir;
+use tracing::{info, warn};
+
+#[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+use crate::loopback::LoopbackFs;
+
+/// Directory holding the short symlinks to the socket directories of instances.
+pub(crate) const SOCKET_LINK_DIR: &str = "/tmp/tmp-postgrust";
+
+/// Directory used by an instance that is removed when dropped, unless it has been kept.
+#[derive(Debug)]
+pub(crate) struct InstanceDir {
+    path: PathBuf,
+    remove_on_drop: AtomicBool,
+    // Size limited filesystem the directory is on, unmounted after the directory is removed.
+    #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+    loopback: Option<LoopbackFs>,
+}
+
+impl InstanceDir {
+    /// Create a new uniquely named directory in `root`.
+    pub(crate) fn temporary(root: &Path, prefix: &str) -> io::Result<Self> {
+        Ok(InstanceDir {
+            path: TempDir::new_in(root, prefix)?.into_path(),
+            remove_on_drop: AtomicBool::new(true),
+            #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+            loopback: None,
+        })
+    }
+
+    /// Use a directory at a fixed location, creating it if necessary.
+    pub(crate) fn fixed(path: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&path)?;
+        Ok(InstanceDir {
+            path,
+            remove_on_drop: AtomicBool::new(true),
+            #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+            loopback: None,
+        })
+    }
+
+    /// Use a new directory in the root of the filesystem of `loopback`, which stays mounted
+    /// as long as the directory exists.
+    #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+    pub(crate) fn on_loopback(loopback: LoopbackFs) -> io::Result<Self> {
+        let path = loopback.mount_point().join("data");
+        std::fs::create_dir(&path)?;
+        Ok(InstanceDir {
+            path,
+            remove_on_drop: AtomicBool::new(true),
+            loopback: Some(loopback),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the directory in place when dropped.
+    pub(crate) fn keep(&self) {
+        if self.remove_on_drop.swap(false, Ordering::SeqCst) {
+            info!("keeping directory {:?}", self.path);
+        }
+        #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+        if let Some(loopback) = &self.loopback {
+            loopback.keep();
+        }
+    }
+}
+
+impl AsRef<Path> for InstanceDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for InstanceDir {
+    fn drop(&mut self) {
+        if self.remove_on_drop.load(Ordering::SeqCst) {
+            if let Err(err) = std::fs::remove_dir_all(&self.path) {
+                warn!("failed to remove directory {:?}: {}", self.path, err);
+            }
+        }
+    }
+}
+
+/// Symlink at a short path in [`SOCKET_LINK_DIR`] pointing at the socket directory of an
+/// instance, removed when dropped.
+#[derive(Debug)]
+pub(crate) struct SocketLink {
+    path: PathBuf,
+    remove_on_drop: AtomicBool,
+}
+
+impl SocketLink {
+    /// Link `socket_dir` as `name` in [`SOCKET_LINK_DIR`], replacing a link a crashed run
+    /// left behind.
+    pub(crate) fn create(socket_dir: &Path, name: &str) -> io::Result<Self> {
+        let dir = Path::new(SOCKET_LINK_DIR);
+        if !dir.exists() {
+            std::fs::create_dir_all(dir)?;
+            // Shared by every user like /tmp, each only removing their own links.
+            #[cfg(unix)]
+            std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o1777))?;
+        }
+        let path = dir.join(name);
+        let _ = std::fs::remove_file(&path);
+        symlink_dir(socket_dir, &path)?;
+        Ok(SocketLink {
+            path,
+            remove_on_drop: AtomicBool::new(true),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leave the link in place when dropped.
+    pub(crate) fn keep(&self) {
+        self.remove_on_drop.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+/// Socket directories only exist on unix, elsewhere there is nothing to link.
+#[cfg(not(unix))]
+fn symlink_dir(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "socket links require unix symlinks",
+    ))
+}
+
+impl Drop for SocketLink {
+    fn drop(&mut self) {
+        if self.remove_on_drop.load(Ordering::SeqCst) {
+            if let Err(err) = std::fs::remove_file(&self.path) {
+                warn!("failed to remove socket link {:?}: {}", self.path, err);
+            }
+        }
+    }
+}
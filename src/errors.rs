@@ -1,6 +1,7 @@
 use thiserror::Error;
 
-/// UTF-8 captures of stdout and stderr for child processes used by the library.
+/// UTF-8 captures of stdout and stderr for child processes used by the library, with invalid
+/// UTF-8 replaced by `U+FFFD`.
 #[derive(Debug)]
 pub struct ProcessCapture {
     /// Capture of stdout from the process
@@ -24,6 +25,20 @@ pub enum TmpPostgrustError {
     /// Catchall error for when a subprocess fails to start
     #[error("subprocess failed to spawn")]
     SpawnSubprocessFailed(#[source] std::io::Error),
+    /// Error when a postgresql binary cannot be found in `$PATH` or common install locations.
+    #[error("failed to find postgresql binary `{0}`")]
+    FindBinaryFailed(String),
+    /// Error when a client tool run against an instance exits unsuccessfully.
+    #[error("postgresql client tool failed")]
+    PgToolFailed(ProcessCapture),
+    /// Error when connecting to an instance or running a query with `tokio-postgres` fails.
+    #[cfg(feature = "client")]
+    #[error("postgresql client failed")]
+    ClientFailed(#[source] tokio_postgres::Error),
+    /// Error when instance metadata cannot be serialized to JSON.
+    #[cfg(feature = "serde")]
+    #[error("failed to serialize instance metadata")]
+    MetadataSerializationFailed(#[source] serde_json::Error),
     /// Error when `initdb` fails to execute.
     #[error("initdb failed")]
     InitDBFailed(ProcessCapture),
@@ -37,12 +52,235 @@ pub enum TmpPostgrustError {
     #[cfg(feature = "tokio-process")]
     #[error("copying cached database failed, failed to join cp process")]
     CopyCachedInitDBFailedJoinError(#[source] tokio::task::JoinError),
+    /// Error when creating an instance was cancelled.
+    #[cfg(feature = "tokio-process")]
+    #[error("creating the instance was cancelled")]
+    Cancelled,
+    /// Error when a factory built in the background is not available because building it
+    /// failed, which was reported to the first caller waiting for it.
+    #[cfg(feature = "tokio-process")]
+    #[error("building the factory in the background failed")]
+    LazyFactoryFailed,
+    /// Error when the task stopping a server fails before the server exited.
+    #[cfg(feature = "tokio-process")]
+    #[error("failed to wait for postgresql to shut down")]
+    ShutdownFailed(#[source] tokio::task::JoinError),
     /// Error when `createdb` fails to execute.
     #[error("createdb failed")]
     CreateDBFailed(ProcessCapture),
+    /// Error when `createuser` fails to execute, e.g. because the role already exists.
+    #[error("createuser failed")]
+    CreateUserFailed(ProcessCapture),
+    /// Error when a server does not accept connections on an endpoint in time.
+    #[error("postgresql is not accepting connections on {0}")]
+    NotAcceptingConnections(String),
     /// Error when `postgresql.conf` cannot be written.
     #[error("failed to write postgresql.conf")]
     CreateConfigFailed(#[source] std::io::Error),
+    /// Error when a `postgresql.conf` fragment added to the builder cannot be read.
+    #[error("failed to read configuration fragment {}", .0.display())]
+    ReadConfFragmentFailed(std::path::PathBuf, #[source] std::io::Error),
+    /// Error when a grant added to the builder names no grantee or privileges, or privileges
+    /// the object does not have.
+    #[error("invalid grant: {0}")]
+    InvalidGrant(String),
+    /// Error when the same setting is added to the builder more than once.
+    #[error("setting `{0}` is configured more than once")]
+    DuplicateConfSetting(String),
+    /// Error when the server rejects settings of the configuration of instances.
+    #[error(
+        "invalid settings: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    InvalidSettings(Vec<crate::settings::InvalidSetting>),
+    /// Error when checking the configuration of instances fails for another reason.
+    #[error("checking settings failed")]
+    CheckSettingsFailed(ProcessCapture),
+    /// Error when the operating system user allowed to connect to hardened instances cannot
+    /// be determined.
+    #[error("failed to determine the current operating system user")]
+    CurrentUserFailed(#[source] std::io::Error),
+    /// Error when a hardened instance can be reached by other users of the machine.
+    #[error("socket hardening failed: {0}")]
+    SocketHardeningFailed(String),
+    /// Error when the system temporary directory is mounted `noexec` and no other location
+    /// for temporary directories was found.
+    #[error(
+        "{} is mounted noexec and no alternative was found, set TMPDIR or CARGO_TARGET_DIR to \
+         a writable directory that allows execution",
+        .0.display()
+    )]
+    NoexecTempDir(std::path::PathBuf),
+    /// Error when background workers of preloaded extensions did not start.
+    #[error("background workers did not start: {}", .0.join(", "))]
+    BackgroundWorkersMissing(Vec<String>),
+    /// Error when an extension to create is not installed.
+    #[error("extension {0} is not installed")]
+    ExtensionNotAvailable(String),
+    /// Error when the version of a created extension differs from its installed library.
+    #[error(
+        "extension {name} was created at version {created:?} but version {installed:?} is \
+         installed"
+    )]
+    ExtensionVersionMismatch {
+        /// Name of the extension.
+        name: String,
+        /// Version created in the database, empty when it was not created.
+        created: String,
+        /// Version of the installed library.
+        installed: String,
+    },
+    /// Error when a condition waited for did not become true in time.
+    #[error(
+        "`{predicate}` did not become true within {timeout:?}{}",
+        .last_error.as_ref().map(|error| ", last error: ".to_string() + error).unwrap_or_default()
+    )]
+    ConditionTimeout {
+        /// SQL expression that was waited for.
+        predicate: String,
+        /// How long it was waited for.
+        timeout: std::time::Duration,
+        /// Error of the last evaluation of the expression, if it failed.
+        last_error: Option<String>,
+    },
+    /// Error when the short symlink to the socket directory of an instance cannot be created.
+    #[error("failed to create socket symlink")]
+    CreateSocketLinkFailed(#[source] std::io::Error),
+    /// Error when a connection needs the server to listen on TCP but it only listens on a unix
+    /// socket.
+    #[error("connecting requires the server to listen on TCP")]
+    TcpRequired,
+    /// Error when no free TCP port can be found for an instance listening on TCP.
+    #[error("failed to find a free TCP port")]
+    FindFreePortFailed(#[source] std::io::Error),
+    /// Error when a factory is configured to listen on TCP as well as to harden its sockets,
+    /// which only allows connections over the unix socket.
+    #[error("instances with socket hardening cannot listen on TCP")]
+    TcpWithSocketHardening,
+    /// Error when `initdb` is asked to require passwords without a password file for the
+    /// superuser.
+    #[error("password authentication requires a superuser password file")]
+    SuperuserPasswordRequired,
+    /// Error when the superuser password file cannot be read.
+    #[error("failed to read superuser password file")]
+    ReadPwfileFailed(#[source] std::io::Error),
+    /// Error when verifying checksums of a cluster that was initialized without them.
+    #[error("data checksums are not enabled in the cluster")]
+    ChecksumsDisabled,
+    /// Error when recorded statements cannot be written to a file.
+    #[error("failed to write recorded statements")]
+    WriteRecordingFailed(#[source] std::io::Error),
+    /// Error when the statements sent during a budget were not written to the CSV log in time,
+    /// e.g. because the factory was built without statement recording.
+    #[error("statements were not logged within {0:?}, is statement recording enabled?")]
+    StatementLogTimeout(std::time::Duration),
+    /// Error when the statements sent during a budget exceed its limits.
+    #[error("{0}")]
+    BudgetExceeded(crate::budget::BudgetExceeded),
+    /// Error when the server reports a WAL position that cannot be parsed.
+    #[error("{0}")]
+    InvalidWalLsn(String),
+    /// Error when the socket of a latency shim cannot be created.
+    #[error("failed to start latency shim")]
+    LatencyShimFailed(#[source] std::io::Error),
+    /// Error when the cgroup limiting the I/O of an instance cannot be set up.
+    #[error("failed to set up I/O throttling")]
+    IoThrottleFailed(#[source] std::io::Error),
+    /// Error when creating, mounting or unmounting the loopback filesystem of a data directory
+    /// fails.
+    #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+    #[error("loopback filesystem command failed")]
+    LoopbackFsFailed(ProcessCapture),
+    /// Error when the image or mount point of a loopback filesystem cannot be created.
+    #[cfg(all(target_os = "linux", feature = "loopback-fs"))]
+    #[error("failed to create loopback filesystem")]
+    CreateLoopbackFsFailed(#[source] std::io::Error),
+    /// Error when a role to connect as does not exist.
+    #[error("role {0} does not exist")]
+    RoleNotFound(String),
+    /// Error when a database to connect to does not exist.
+    #[error("database {0} does not exist")]
+    DatabaseNotFound(String),
+    /// Error when the artifacts of an instance cannot be written.
+    #[error("failed to collect artifacts")]
+    CollectArtifactsFailed(#[source] std::io::Error),
+    /// Error when the CSV log of an instance cannot be read.
+    #[error("failed to read the csv log")]
+    ReadCsvLogFailed(#[source] std::io::Error),
+    /// Error when starting a Citus cluster with a factory that does not preload Citus.
+    #[error("citus is not preloaded, build the factory with Preset::Citus")]
+    CitusNotPreloaded,
+    /// Error when the server exits while starting, with the fatal error it logged.
+    #[error("postgresql failed to start: {0}")]
+    ServerStartFailed(String),
+    /// Error when a configuration file of a prepared instance cannot be read.
+    #[error("failed to read configuration file")]
+    ReadConfigFailed(#[source] std::io::Error),
+    /// Error when the initialized database cluster of a factory is missing or incomplete.
+    #[error("cached database cluster in {0:?} is missing or incomplete")]
+    InvalidCacheDir(std::path::PathBuf),
+    /// Error when the directory of a tablespace is not a relative path inside the instance.
+    #[error("tablespace directory {0:?} must be a relative path without `..`")]
+    InvalidTablespaceDir(std::path::PathBuf),
+    /// Error when the directory of a tablespace cannot be created.
+    #[error("failed to create tablespace directory")]
+    CreateTablespaceDirFailed(#[source] std::io::Error),
+    /// Error when a binary manifest cannot be created or parsed.
+    #[error("binary manifest failed: {0}")]
+    ManifestFailed(String),
+    /// Error when the state file of a detached instance cannot be read or written.
+    #[error("failed to access the state file of a detached instance")]
+    StateFileFailed(#[source] std::io::Error),
+    /// Error when the state file of a detached instance cannot be parsed.
+    #[error("invalid state file of a detached instance: {0}")]
+    InvalidStateFile(String),
+    /// Error when the server of a detached instance is no longer running.
+    #[error("failed to attach: {0}")]
+    AttachFailed(String),
+    /// Error when a server that is not managed by a guard cannot be asked to shut down.
+    #[error("failed to stop postgresql")]
+    StopServerFailed(#[source] std::io::Error),
+    /// Error when a server that is not managed by a guard did not shut down in time.
+    #[error("postgresql (pid {pid}) did not shut down within {timeout:?}")]
+    StopServerTimeout {
+        /// Process id of the server.
+        pid: u32,
+        /// How long it was waited for.
+        timeout: std::time::Duration,
+    },
+    /// Error when an operation needs a server of its own but the instance is a database on the
+    /// shared server of its factory.
+    #[error("{0} is not supported by instances on a shared server")]
+    SharedServerUnsupported(&'static str),
+    /// Error when the limit of running instances is reached and the factory fails fast.
+    #[error("too many instances are running")]
+    InstanceLimitReached,
+    /// Error when no running instance stopped within the timeout of the factory.
+    #[error("no instance stopped within {0:?} while too many instances were running")]
+    InstanceLimitTimeout(std::time::Duration),
+    /// Error when the lock files of a shared workspace cannot be used.
+    #[error("failed to lock the shared workspace")]
+    WorkspaceFailed(#[source] std::io::Error),
+    /// Error when communicating with a broker fails.
+    #[error("broker connection failed")]
+    BrokerFailed(#[source] std::io::Error),
+    /// Error when a broker does not lend an instance.
+    #[error("broker refused the lease: {0}")]
+    LeaseRefused(String),
+    /// Error when a broker stopped a leased instance because its lease expired.
+    #[error("the lease expired and the broker stopped the instance")]
+    LeaseExpired,
+    /// Error when a pool has more slots than instances of its factory may run at the same time.
+    #[error("pool of {size} instances exceeds the limit of {limit} running instances")]
+    PoolTooLarge {
+        /// Requested number of slots.
+        size: usize,
+        /// Number of instances the factory may run at the same time.
+        limit: usize,
+    },
+    /// Error when the postgresql binaries differ from the required manifest.
+    #[error("postgresql binaries do not match the required manifest: {0}")]
+    ManifestMismatch(String),
     /// Error when the PGDATA directory is empty.
     #[error("failed to find temporary data directory")]
     EmptyDataDirectory,
@@ -0,0 +1,108 @@
+use std::fmt;
+use std::process::ExitStatus;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Lifecycle event of a factory or one of its instances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// The cached database cluster is ready. Every subscriber receives this event first.
+    CacheBuilt {
+        /// Time spent running `initdb`, or checking the cache directory when it was reused.
+        duration: Duration,
+        /// A persistent cache directory initialized by a previous run was reused.
+        reused: bool,
+    },
+    /// A server is being started for a new instance.
+    InstanceStarting {
+        /// Label of the instance.
+        label: String,
+        /// Port the server listens on.
+        port: u32,
+    },
+    /// The server accepts connections and its database is set up.
+    InstanceReady {
+        /// Label of the instance.
+        label: String,
+        /// Port the server listens on.
+        port: u32,
+        /// Time from starting the instance until it was ready.
+        duration: Duration,
+    },
+    /// The server of an instance exited.
+    InstanceStopped {
+        /// Label of the instance.
+        label: String,
+        /// Port the server listened on.
+        port: u32,
+        /// Exit status of the server, if it could be collected.
+        exit: Option<ExitStatus>,
+    },
+}
+
+/// Step of building a factory, sent to the channel passed to
+/// [`with_progress`](crate::TmpPostgrustFactoryBuilder::with_progress) when it starts, so
+/// interactive tools can show what takes the seconds `initdb` needs. `Display` describes the
+/// step for humans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildStep {
+    /// Looking up the postgresql binaries and the capabilities of the platform.
+    LocatingBinaries,
+    /// Running `initdb` to initialize the cached database cluster.
+    RunningInitdb,
+    /// Reusing a cache directory initialized by a previous run.
+    ReusingCache,
+    /// Reading configuration fragments and rendering the configuration of instances.
+    BuildingConfig,
+    /// Checking the configuration with `postgres -C`.
+    ValidatingSettings,
+    /// The factory is ready.
+    Finished,
+}
+
+impl fmt::Display for BuildStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BuildStep::LocatingBinaries => "locating postgresql binaries",
+            BuildStep::RunningInitdb => "running initdb",
+            BuildStep::ReusingCache => "reusing cached database cluster",
+            BuildStep::BuildingConfig => "building configuration",
+            BuildStep::ValidatingSettings => "validating settings",
+            BuildStep::Finished => "factory ready",
+        })
+    }
+}
+
+/// Delivers lifecycle events to every subscriber of a factory.
+#[derive(Debug)]
+pub(crate) struct EventBus {
+    cache_built: LifecycleEvent,
+    subscribers: Mutex<Vec<Sender<LifecycleEvent>>>,
+}
+
+impl EventBus {
+    pub(crate) fn new(cache_built: LifecycleEvent) -> Self {
+        EventBus {
+            cache_built,
+            subscribers: Mutex::default(),
+        }
+    }
+
+    /// Receive all events emitted from now on, preceded by the `CacheBuilt` event.
+    pub(crate) fn subscribe(&self) -> Receiver<LifecycleEvent> {
+        let (sender, receiver) = channel();
+        // Cannot fail as the receiver is still around.
+        let _ = sender.send(self.cache_built.clone());
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Send `event` to every subscriber, forgetting subscribers that dropped their receiver.
+    pub(crate) fn emit(&self, event: &LifecycleEvent) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::audit::{self, DATABASE_COLUMN, MESSAGE_COLUMN, USER_COLUMN};
+use crate::auth::SUPERUSER;
+
+/// Plan of a slow statement logged by `auto_explain`, as listed by
+/// [`slow_query_plans`](crate::synchronous::ProcessGuard::slow_query_plans).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowQueryPlan {
+    /// Role of the session that ran the statement.
+    pub user: String,
+    /// Database the statement ran in.
+    pub database: String,
+    /// How long the statement ran.
+    pub duration: Duration,
+    /// Text of the statement.
+    pub query: String,
+    /// Plan of the statement in the text format of `EXPLAIN`.
+    pub plan: String,
+}
+
+impl SlowQueryPlan {
+    /// Parse a message such as `duration: 12.345 ms  plan:\nQuery Text: SELECT 1\nResult
+    /// (cost=0.00..0.01 rows=1 width=4)`.
+    fn parse(user: &str, database: &str, message: &str) -> Option<Self> {
+        let (millis, explain) = message
+            .strip_prefix("duration: ")?
+            .split_once(" ms  plan:\n")?;
+        let explain = explain.strip_prefix("Query Text: ")?;
+        // The text of the statement can span lines, the plan starts at its root node, which is
+        // the first line with costs.
+        let plan_start = explain
+            .match_indices('\n')
+            .map(|(newline, _)| newline + 1)
+            .find(|&start| {
+                explain[start..]
+                    .lines()
+                    .next()
+                    .is_some_and(|line| line.contains("  (cost="))
+            })?;
+        Some(SlowQueryPlan {
+            user: user.to_string(),
+            database: database.to_string(),
+            duration: Duration::from_secs_f64(millis.parse::<f64>().ok()? / 1000.0),
+            query: explain[..plan_start - 1].to_string(),
+            plan: explain[plan_start..].trim_end().to_string(),
+        })
+    }
+}
+
+/// Plans in the CSV log of the instance in `data_directory`, in the order they were logged,
+/// leaving out those of the superuser setting up the instance.
+pub(crate) fn read_plans(data_directory: &Path) -> std::io::Result<Vec<SlowQueryPlan>> {
+    let log = std::fs::read_to_string(data_directory.join(audit::CSV_LOG_FILE))?;
+    Ok(parse_plans(&log))
+}
+
+fn parse_plans(log: &str) -> Vec<SlowQueryPlan> {
+    audit::parse_csv(log)
+        .iter()
+        .filter(|record| record.get(USER_COLUMN).map(String::as_str) != Some(SUPERUSER))
+        .filter_map(|record| {
+            SlowQueryPlan::parse(
+                record.get(USER_COLUMN)?,
+                record.get(DATABASE_COLUMN)?,
+                record.get(MESSAGE_COLUMN)?,
+            )
+        })
+        .collect()
+}
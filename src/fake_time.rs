@@ -0,0 +1,33 @@
+use crate::sql::{quote_ident, quote_literal};
+
+/// Setting holding the fake time of a database, empty when the real time is used.
+const FAKE_TIME_SETTING: &str = "tmp_postgrust.fake_time";
+
+/// Create `app_now()`, which returns the fake time of the database if one is set and `now()`
+/// otherwise. It lives in `public` so `now()` in `pg_catalog` keeps working for the server.
+pub(crate) const INSTALL_SQL: &str = "
+CREATE FUNCTION public.app_now() RETURNS timestamptz
+LANGUAGE sql STABLE
+AS $$
+    SELECT COALESCE(
+        NULLIF(current_setting('tmp_postgrust.fake_time', true), '')::timestamptz,
+        now()
+    )
+$$;
+";
+
+/// Statements setting the fake time of `dbname` for new sessions, or resetting it to the real
+/// time. The timestamp is validated first so a typo fails here rather than in `app_now()`.
+pub(crate) fn set_fake_time_sql(dbname: &str, timestamp: Option<&str>) -> String {
+    match timestamp {
+        Some(timestamp) => format!(
+            "SELECT {timestamp}::timestamptz; ALTER DATABASE {dbname} SET {FAKE_TIME_SETTING} = {timestamp};",
+            timestamp = quote_literal(timestamp),
+            dbname = quote_ident(dbname),
+        ),
+        None => format!(
+            "ALTER DATABASE {} RESET {FAKE_TIME_SETTING};",
+            quote_ident(dbname)
+        ),
+    }
+}
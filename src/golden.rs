@@ -0,0 +1,134 @@
+use std::path::Path;
+
+/// Environment variable that makes golden data assertions write the current data to their
+/// file instead of comparing against it, for accepting intended changes.
+pub const UPDATE_GOLDEN_ENV: &str = "TMP_POSTGRUST_UPDATE_GOLDEN";
+
+/// Lines of context shown around changed lines of a diff.
+const DIFF_CONTEXT: usize = 2;
+
+/// Arguments for `pg_dump` to dump the rows of `tables`, or of every table outside of the
+/// schemas of `tmp-postgrust` when empty.
+pub(crate) fn dump_args(tables: &[&str]) -> Vec<String> {
+    let mut args = vec!["--data-only".to_string()];
+    if tables.is_empty() {
+        args.push("--exclude-schema=tmp_postgrust_*".to_string());
+    }
+    args.extend(tables.iter().map(|table| format!("--table={table}")));
+    args
+}
+
+/// Keep only the `COPY` blocks of a data dump, ordered by table with the rows of each table
+/// sorted, so the result does not depend on the physical order of rows or the version of
+/// `pg_dump`.
+pub(crate) fn normalize_dump(dump: &str) -> String {
+    let mut blocks = Vec::new();
+    let mut lines = dump.lines();
+    while let Some(line) = lines.next() {
+        if !line.starts_with("COPY ") {
+            continue;
+        }
+        let mut rows: Vec<&str> = lines.by_ref().take_while(|row| *row != "\\.").collect();
+        rows.sort_unstable();
+        blocks.push((line, rows));
+    }
+    blocks.sort_unstable();
+
+    let mut normalized = String::new();
+    for (header, rows) in blocks {
+        if !normalized.is_empty() {
+            normalized.push('\n');
+        }
+        normalized.push_str(header);
+        normalized.push('\n');
+        for row in rows {
+            normalized.push_str(row);
+            normalized.push('\n');
+        }
+        normalized.push_str("\\.\n");
+    }
+    normalized
+}
+
+/// Compare `actual` with the contents of the golden file `path`, or write it there when
+/// [`UPDATE_GOLDEN_ENV`] is set. Returns a description of the mismatch.
+pub(crate) fn compare(path: &Path, actual: &str) -> Result<(), String> {
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        return std::fs::write(path, actual)
+            .map_err(|err| format!("failed to write golden file {}: {}", path.display(), err));
+    }
+    let expected = std::fs::read_to_string(path).map_err(|err| {
+        format!(
+            "failed to read golden file {}: {}, set {} to create it",
+            path.display(),
+            err,
+            UPDATE_GOLDEN_ENV
+        )
+    })?;
+    if expected == actual {
+        return Ok(());
+    }
+    Err(format!(
+        "data does not match golden file {}, set {} to update it\n{}",
+        path.display(),
+        UPDATE_GOLDEN_ENV,
+        line_diff(&expected, actual)
+    ))
+}
+
+/// Lines removed from `expected` prefixed with `-` and lines added in `actual` prefixed with
+/// `+`, with a few unchanged lines of context around them.
+pub(crate) fn line_diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    // Length of the longest common subsequence of the remaining lines of each side.
+    let mut lcs = vec![vec![0_usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push((' ', expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(('-', expected[i]));
+            i += 1;
+        } else {
+            lines.push(('+', actual[j]));
+            j += 1;
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|&n| lines[n].0 != ' ').collect();
+    let mut diff = String::new();
+    let mut shown_until = 0;
+    for (n, (marker, line)) in lines.iter().enumerate() {
+        let near_change = changed
+            .iter()
+            .any(|&change| n + DIFF_CONTEXT >= change && n <= change + DIFF_CONTEXT);
+        if !near_change {
+            continue;
+        }
+        if n > shown_until {
+            diff.push_str("...\n");
+        }
+        diff.push(*marker);
+        diff.push_str(line);
+        diff.push('\n');
+        shown_until = n + 1;
+    }
+    if shown_until < lines.len() {
+        diff.push_str("...\n");
+    }
+    diff
+}
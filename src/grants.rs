@@ -0,0 +1,290 @@
+use std::fmt::{self, Write as _};
+
+use crate::sql::{quote_ident, quote_literal};
+
+/// Privilege granted by a [`Grant`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Privilege {
+    /// `SELECT` on tables and sequences.
+    Select,
+    /// `INSERT` on tables.
+    Insert,
+    /// `UPDATE` on tables and sequences.
+    Update,
+    /// `DELETE` on tables.
+    Delete,
+    /// `TRUNCATE` on tables.
+    Truncate,
+    /// `REFERENCES` on tables.
+    References,
+    /// `TRIGGER` on tables.
+    Trigger,
+    /// `USAGE` on schemas and sequences.
+    Usage,
+    /// `CREATE` on schemas and databases.
+    Create,
+    /// `CONNECT` on databases.
+    Connect,
+    /// `TEMPORARY` on databases.
+    Temporary,
+    /// `EXECUTE` on functions.
+    Execute,
+    /// Every privilege of the kind of object.
+    All,
+}
+
+impl Privilege {
+    fn keyword(self) -> &'static str {
+        match self {
+            Privilege::Select => "SELECT",
+            Privilege::Insert => "INSERT",
+            Privilege::Update => "UPDATE",
+            Privilege::Delete => "DELETE",
+            Privilege::Truncate => "TRUNCATE",
+            Privilege::References => "REFERENCES",
+            Privilege::Trigger => "TRIGGER",
+            Privilege::Usage => "USAGE",
+            Privilege::Create => "CREATE",
+            Privilege::Connect => "CONNECT",
+            Privilege::Temporary => "TEMPORARY",
+            Privilege::Execute => "EXECUTE",
+            Privilege::All => "ALL",
+        }
+    }
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.keyword())
+    }
+}
+
+/// Objects a [`Grant`] applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Database(String),
+    Schema(String),
+    Table(String),
+    Sequence(String),
+    AllTablesInSchema(String),
+    AllSequencesInSchema(String),
+    AllFunctionsInSchema(String),
+    FutureTablesInSchema(String),
+    FutureSequencesInSchema(String),
+}
+
+impl Target {
+    /// Privileges postgresql accepts for the kind of object.
+    fn allowed(&self) -> &'static [Privilege] {
+        use Privilege::{
+            All, Connect, Create, Delete, Execute, Insert, References, Select, Temporary, Trigger,
+            Truncate, Update, Usage,
+        };
+        match self {
+            Target::Database(_) => &[Connect, Create, Temporary, All],
+            Target::Schema(_) => &[Usage, Create, All],
+            Target::Table(_) | Target::AllTablesInSchema(_) | Target::FutureTablesInSchema(_) => &[
+                Select, Insert, Update, Delete, Truncate, References, Trigger, All,
+            ],
+            Target::Sequence(_)
+            | Target::AllSequencesInSchema(_)
+            | Target::FutureSequencesInSchema(_) => &[Usage, Select, Update, All],
+            Target::AllFunctionsInSchema(_) => &[Execute, All],
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Database(name) => write!(f, "DATABASE {}", quote_ident(name)),
+            Target::Schema(name) => write!(f, "SCHEMA {}", quote_ident(name)),
+            // Names of tables and sequences may be qualified with their schema.
+            Target::Table(name) => write!(f, "TABLE {name}"),
+            Target::Sequence(name) => write!(f, "SEQUENCE {name}"),
+            Target::AllTablesInSchema(schema) => {
+                write!(f, "ALL TABLES IN SCHEMA {}", quote_ident(schema))
+            }
+            Target::AllSequencesInSchema(schema) => {
+                write!(f, "ALL SEQUENCES IN SCHEMA {}", quote_ident(schema))
+            }
+            Target::AllFunctionsInSchema(schema) => {
+                write!(f, "ALL FUNCTIONS IN SCHEMA {}", quote_ident(schema))
+            }
+            Target::FutureTablesInSchema(_) => f.write_str("TABLES"),
+            Target::FutureSequencesInSchema(_) => f.write_str("SEQUENCES"),
+        }
+    }
+}
+
+/// Privileges on a database object granted to roles in every instance of a factory, added
+/// with [`with_grants`](crate::builder::TmpPostgrustFactoryBuilder::with_grants), e.g.
+/// `Grant::on_schema("public").to("app").privileges(&[Privilege::Usage])`.
+///
+/// Grants are applied by the superuser when an instance is set up, in the order they were
+/// added. Grantees that do not exist yet are created as roles that can log in without a
+/// password, except `PUBLIC`, which stands for every role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    target: Target,
+    grantees: Vec<String>,
+    privileges: Vec<Privilege>,
+    with_grant_option: bool,
+}
+
+impl Grant {
+    fn on(target: Target) -> Self {
+        Grant {
+            target,
+            grantees: Vec::new(),
+            privileges: Vec::new(),
+            with_grant_option: false,
+        }
+    }
+
+    /// Grant privileges on the database `name`.
+    #[must_use]
+    pub fn on_database(name: impl Into<String>) -> Self {
+        Grant::on(Target::Database(name.into()))
+    }
+
+    /// Grant privileges on the schema `name`.
+    #[must_use]
+    pub fn on_schema(name: impl Into<String>) -> Self {
+        Grant::on(Target::Schema(name.into()))
+    }
+
+    /// Grant privileges on the table `name`, an SQL name such as `public.orders`, which has
+    /// to exist in the template of the instances.
+    #[must_use]
+    pub fn on_table(name: impl Into<String>) -> Self {
+        Grant::on(Target::Table(name.into()))
+    }
+
+    /// Grant privileges on the sequence `name`, an SQL name such as `public.orders_id_seq`,
+    /// which has to exist in the template of the instances.
+    #[must_use]
+    pub fn on_sequence(name: impl Into<String>) -> Self {
+        Grant::on(Target::Sequence(name.into()))
+    }
+
+    /// Grant privileges on the tables in `schema` when the instance is set up.
+    #[must_use]
+    pub fn on_all_tables_in_schema(schema: impl Into<String>) -> Self {
+        Grant::on(Target::AllTablesInSchema(schema.into()))
+    }
+
+    /// Grant privileges on the sequences in `schema` when the instance is set up.
+    #[must_use]
+    pub fn on_all_sequences_in_schema(schema: impl Into<String>) -> Self {
+        Grant::on(Target::AllSequencesInSchema(schema.into()))
+    }
+
+    /// Grant privileges on the functions in `schema` when the instance is set up.
+    #[must_use]
+    pub fn on_all_functions_in_schema(schema: impl Into<String>) -> Self {
+        Grant::on(Target::AllFunctionsInSchema(schema.into()))
+    }
+
+    /// Grant privileges on the tables the database user creates in `schema` later, e.g. in
+    /// migrations run by the test, through default privileges.
+    #[must_use]
+    pub fn on_future_tables_in_schema(schema: impl Into<String>) -> Self {
+        Grant::on(Target::FutureTablesInSchema(schema.into()))
+    }
+
+    /// Grant privileges on the sequences the database user creates in `schema` later, through
+    /// default privileges.
+    #[must_use]
+    pub fn on_future_sequences_in_schema(schema: impl Into<String>) -> Self {
+        Grant::on(Target::FutureSequencesInSchema(schema.into()))
+    }
+
+    /// Grant the privileges to `role` too.
+    #[must_use]
+    pub fn to(mut self, role: impl Into<String>) -> Self {
+        self.grantees.push(role.into());
+        self
+    }
+
+    /// Grant `privileges` too.
+    #[must_use]
+    pub fn privileges(mut self, privileges: &[Privilege]) -> Self {
+        self.privileges.extend_from_slice(privileges);
+        self
+    }
+
+    /// Let the grantees grant the privileges to others.
+    #[must_use]
+    pub fn with_grant_option(mut self, with_grant_option: bool) -> Self {
+        self.with_grant_option = with_grant_option;
+        self
+    }
+
+    /// Check that the grant names grantees and privileges the object accepts.
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.grantees.is_empty() {
+            return Err(format!("grant on {} has no grantee", self.target));
+        }
+        if self.privileges.is_empty() {
+            return Err(format!("grant on {} has no privileges", self.target));
+        }
+        let allowed = self.target.allowed();
+        match self
+            .privileges
+            .iter()
+            .find(|privilege| !allowed.contains(privilege))
+        {
+            Some(privilege) => Err(format!(
+                "privilege {} cannot be granted on {}",
+                privilege, self.target
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Statements creating the missing grantees and granting the privileges, with future
+    /// objects being those created by `owner`.
+    pub(crate) fn sql(&self, owner: &str) -> String {
+        let mut sql = String::new();
+        let mut grantees = Vec::new();
+        for grantee in &self.grantees {
+            if grantee.eq_ignore_ascii_case("public") {
+                grantees.push("PUBLIC".to_string());
+                continue;
+            }
+            let _ = writeln!(
+                sql,
+                "DO $$ BEGIN IF NOT EXISTS (SELECT FROM pg_roles WHERE rolname = {}) THEN \
+                 CREATE ROLE {} LOGIN; END IF; END $$;",
+                quote_literal(grantee),
+                quote_ident(grantee)
+            );
+            grantees.push(quote_ident(grantee));
+        }
+        let privileges: Vec<&str> = self.privileges.iter().map(|p| p.keyword()).collect();
+        match &self.target {
+            Target::FutureTablesInSchema(schema) | Target::FutureSequencesInSchema(schema) => {
+                let _ = write!(
+                    sql,
+                    "ALTER DEFAULT PRIVILEGES FOR ROLE {} IN SCHEMA {} ",
+                    quote_ident(owner),
+                    quote_ident(schema)
+                );
+            }
+            _ => {}
+        }
+        let _ = write!(
+            sql,
+            "GRANT {} ON {} TO {}",
+            privileges.join(", "),
+            self.target,
+            grantees.join(", ")
+        );
+        if self.with_grant_option {
+            sql.push_str(" WITH GRANT OPTION");
+        }
+        sql.push(';');
+        sql
+    }
+}
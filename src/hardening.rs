@@ -0,0 +1,69 @@
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+
+/// Name of the `pg_ident.conf` map of the operating system user to the roles of an instance.
+const IDENT_MAP: &str = "tmp_postgrust";
+
+/// Name of the operating system user running the process.
+pub(crate) fn current_os_user() -> io::Result<String> {
+    let output = Command::new("id").arg("-un").output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Only allow `os_user` to connect, over the unix socket with peer authentication, as one of
+/// `roles`.
+pub(crate) fn write_auth_files(
+    data_directory: &Path,
+    os_user: &str,
+    roles: &[&str],
+) -> TmpPostgrustResult<()> {
+    let pg_hba = format!("local all all peer map={IDENT_MAP}\n");
+    let mut pg_ident = String::new();
+    for role in roles {
+        pg_ident.push_str(IDENT_MAP);
+        pg_ident.push(' ');
+        pg_ident.push_str(os_user);
+        pg_ident.push(' ');
+        pg_ident.push_str(role);
+        pg_ident.push('\n');
+    }
+    std::fs::write(data_directory.join("pg_hba.conf"), pg_hba)
+        .and_then(|()| std::fs::write(data_directory.join("pg_ident.conf"), pg_ident))
+        .map_err(TmpPostgrustError::CreateConfigFailed)
+}
+
+/// Check that only the owner of the unix socket of a started server can connect to it.
+#[cfg(unix)]
+pub(crate) fn verify_socket(socket_path: &Path) -> TmpPostgrustResult<()> {
+    let mode = std::fs::metadata(socket_path)
+        .map_err(|err| TmpPostgrustError::SocketHardeningFailed(err.to_string()))?
+        .permissions()
+        .mode();
+    // Neither the group nor others have any permission.
+    if mode & 0o777 == mode & 0o700 {
+        Ok(())
+    } else {
+        Err(TmpPostgrustError::SocketHardeningFailed(format!(
+            "socket {} is accessible to other users (mode {:o})",
+            socket_path.display(),
+            mode & 0o777
+        )))
+    }
+}
+
+/// Without unix permissions the socket cannot be verified, so hardening always fails.
+#[cfg(not(unix))]
+pub(crate) fn verify_socket(socket_path: &Path) -> TmpPostgrustResult<()> {
+    Err(TmpPostgrustError::SocketHardeningFailed(format!(
+        "permissions of socket {} cannot be verified on this platform",
+        socket_path.display()
+    )))
+}
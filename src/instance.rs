@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::sql::{quote_ident, quote_literal};
+use crate::{DATABASE_NAME, DATABASE_USER};
+
+/// Database and role set up for the application in a new instance, passed to
+/// [`new_instance_with`](crate::TmpPostgrustFactory::new_instance_with). By default the
+/// database `demo` is owned by the superuser `demo_user`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct InstanceOptions {
+    pub(crate) dbname: String,
+    pub(crate) user: String,
+    pub(crate) superuser: bool,
+    pub(crate) password: Option<String>,
+}
+
+impl Default for InstanceOptions {
+    fn default() -> Self {
+        InstanceOptions {
+            dbname: DATABASE_NAME.to_string(),
+            user: DATABASE_USER.to_string(),
+            superuser: true,
+            password: None,
+        }
+    }
+}
+
+/// Never prints the password.
+impl fmt::Debug for InstanceOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstanceOptions")
+            .field("dbname", &self.dbname)
+            .field("user", &self.user)
+            .field("superuser", &self.superuser)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl InstanceOptions {
+    /// Options of the default database and role.
+    #[must_use]
+    pub fn new() -> Self {
+        InstanceOptions::default()
+    }
+
+    /// Create the database of the application as `dbname`.
+    #[must_use]
+    pub fn with_dbname(mut self, dbname: impl Into<String>) -> Self {
+        self.dbname = dbname.into();
+        self
+    }
+
+    /// Create the role owning the database and used by the connection string of the instance
+    /// as `user`.
+    #[must_use]
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = user.into();
+        self
+    }
+
+    /// Whether the role owning the database is a superuser. Without superuser privileges
+    /// tests notice statements that fail for the application role in production, while the
+    /// instance is still set up as the superuser, whose connection string is available from
+    /// `superuser_connection_string` on the guard. The role is explicitly granted all
+    /// privileges on the database and its `public` schema.
+    #[must_use]
+    pub fn with_superuser(mut self, superuser: bool) -> Self {
+        self.superuser = superuser;
+        self
+    }
+
+    /// Give the role owning the database `password`, which connection details of the instance
+    /// include, for clusters [requiring passwords](crate::builder::AuthMethod). By default the
+    /// role of such clusters gets a random password.
+    #[must_use]
+    pub fn with_password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Name of the database of the application.
+    #[must_use]
+    pub fn dbname(&self) -> &str {
+        &self.dbname
+    }
+
+    /// Role owning the database of the application.
+    #[must_use]
+    pub fn user(&self) -> &str {
+        &self.user
+    }
+
+    /// Whether the role owning the database is a superuser.
+    #[must_use]
+    pub fn superuser(&self) -> bool {
+        self.superuser
+    }
+
+    /// Statement setting the password of the role to `password`.
+    pub(crate) fn password_sql(&self, password: &str) -> String {
+        format!(
+            "ALTER ROLE {} PASSWORD {};",
+            quote_ident(&self.user),
+            quote_literal(password)
+        )
+    }
+
+    /// Statements granting the role the privileges it needs as the owner of the database when
+    /// it is not a superuser.
+    pub(crate) fn grant_sql(&self) -> String {
+        let user = quote_ident(&self.user);
+        format!(
+            "GRANT ALL ON DATABASE {} TO {user};\nGRANT ALL ON SCHEMA public TO {user};",
+            quote_ident(&self.dbname)
+        )
+    }
+}
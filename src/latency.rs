@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::dirs::InstanceDir;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+
+/// Size of the chunks read from a socket, each of which is delayed as one packet.
+const PACKET_SIZE: usize = 8192;
+
+/// Unix socket in front of an instance that delays every packet sent in either direction, for
+/// testing clients on a slow network when they only connect through the socket directory.
+///
+/// Created with `latency_shim` on a guard. Connect to the
+/// [connection string](Self::connection_string) of the shim instead of the one of the
+/// instance. Every packet is delayed on its own, so latency is added without limiting
+/// throughput. Open connections are closed when the shim is dropped.
+pub struct LatencyShim {
+    socket_dir: InstanceDir,
+    socket_path: PathBuf,
+    connection_string: String,
+    pub(crate) connections: Arc<Connections>,
+    shutdown: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl LatencyShim {
+    /// Forward connections to the socket `upstream` through `.s.PGSQL.{port}` in a new socket
+    /// directory in `temp_root`, delaying every packet by `delay`.
+    pub(crate) fn spawn(
+        temp_root: &Path,
+        upstream: PathBuf,
+        port: u32,
+        delay: Duration,
+        connection_string: impl FnOnce(&Path) -> String,
+    ) -> TmpPostgrustResult<LatencyShim> {
+        let socket_dir = InstanceDir::temporary(temp_root, "tmp-postgrust-shim")
+            .map_err(TmpPostgrustError::LatencyShimFailed)?;
+        let socket_path = crate::socket_path(socket_dir.path(), port);
+        let listener =
+            UnixListener::bind(&socket_path).map_err(TmpPostgrustError::LatencyShimFailed)?;
+        debug!(
+            "delaying packets to {} by {:?} on {}",
+            upstream.display(),
+            delay,
+            socket_path.display()
+        );
+
+        let connections = Arc::new(Connections::default());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let acceptor = {
+            let connections = Arc::clone(&connections);
+            let shutdown = Arc::clone(&shutdown);
+            std::thread::spawn(move || {
+                accept(&listener, &upstream, delay, &connections, &shutdown);
+            })
+        };
+        Ok(LatencyShim {
+            connection_string: connection_string(socket_dir.path()),
+            socket_dir,
+            socket_path,
+            connections,
+            shutdown,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Connection string for connecting to the instance through the shim.
+    #[must_use]
+    pub fn connection_string(&self) -> &str {
+        &self.connection_string
+    }
+
+    /// Socket directory of the shim, to be used as the host of connections.
+    #[must_use]
+    pub fn socket_dir(&self) -> &Path {
+        self.socket_dir.path()
+    }
+}
+
+impl Drop for LatencyShim {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // Wake up the acceptor blocked waiting for a connection.
+        let _ = UnixStream::connect(&self.socket_path);
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        for stream in self.connections.take().into_iter().flatten() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Both streams of every open connection, shut down when the shim is dropped.
+#[derive(Default)]
+pub(crate) struct Connections {
+    open: Mutex<HashMap<u64, [UnixStream; 2]>>,
+    next_id: AtomicU64,
+}
+
+impl Connections {
+    /// Keep the streams of a new connection until the returned handle and its clones are
+    /// dropped, once forwarding finished in both directions.
+    fn open(self: &Arc<Self>, streams: [UnixStream; 2]) -> OpenConnection {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.lock().insert(id, streams);
+        OpenConnection {
+            id,
+            connections: Arc::clone(self),
+        }
+    }
+
+    fn take(&self) -> Vec<[UnixStream; 2]> {
+        self.lock().drain().map(|(_, streams)| streams).collect()
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, HashMap<u64, [UnixStream; 2]>> {
+        self.open.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Connection forwarded by the shim, whose streams are released when it is dropped.
+struct OpenConnection {
+    id: u64,
+    connections: Arc<Connections>,
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.connections.lock().remove(&self.id);
+    }
+}
+
+fn accept(
+    listener: &UnixListener,
+    upstream: &Path,
+    delay: Duration,
+    connections: &Arc<Connections>,
+    shutdown: &AtomicBool,
+) {
+    for client in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        let forwarded = client.and_then(|client| {
+            let server = UnixStream::connect(upstream)?;
+            let connection = Arc::new(connections.open([client.try_clone()?, server.try_clone()?]));
+            forward(
+                client.try_clone()?,
+                server.try_clone()?,
+                delay,
+                Arc::clone(&connection),
+            )?;
+            forward(server, client, delay, connection)
+        });
+        if let Err(err) = forwarded {
+            warn!("failed to forward connection: {}", err);
+        }
+    }
+}
+
+/// Copy everything read from `from` to `to` in background threads, each packet `delay` after
+/// it was read. `connection` is released once everything was written.
+fn forward(
+    from: UnixStream,
+    to: UnixStream,
+    delay: Duration,
+    connection: Arc<OpenConnection>,
+) -> std::io::Result<()> {
+    let (sender, receiver) = mpsc::channel::<(Instant, Vec<u8>)>();
+    std::thread::Builder::new()
+        .name("latency-shim-read".to_string())
+        .spawn(move || read_packets(from, &sender, delay))?;
+    std::thread::Builder::new()
+        .name("latency-shim-write".to_string())
+        .spawn(move || {
+            write_packets(to, &receiver);
+            drop(connection);
+        })?;
+    Ok(())
+}
+
+fn read_packets(mut from: UnixStream, sender: &mpsc::Sender<(Instant, Vec<u8>)>, delay: Duration) {
+    let mut buf = [0; PACKET_SIZE];
+    loop {
+        match from.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(read) => {
+                if sender
+                    .send((Instant::now() + delay, buf[..read].to_vec()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Write packets once they are due, closing the writing half of `to` when the other side
+/// closed the connection.
+fn write_packets(mut to: UnixStream, receiver: &mpsc::Receiver<(Instant, Vec<u8>)>) {
+    for (due, packet) in receiver {
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        if to.write_all(&packet).is_err() {
+            break;
+        }
+    }
+    let _ = to.shutdown(Shutdown::Write);
+}
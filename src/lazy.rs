@@ -0,0 +1,70 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::{watch, OnceCell};
+
+use crate::builder::TmpPostgrustFactoryBuilder;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::TmpPostgrustFactory;
+
+/// Factory that is built on a background thread, created by
+/// [`spawn_lazy`](TmpPostgrustFactory::spawn_lazy), so running `initdb` overlaps with the
+/// remaining setup of the test harness. Instances are created through the factory returned
+/// by [`ready`](Self::ready).
+#[derive(Debug)]
+pub struct LazyFactory {
+    built: Arc<Built>,
+    // Never sent on, the building thread drops the sender once the outcome is stored.
+    done: watch::Receiver<()>,
+}
+
+#[derive(Debug, Default)]
+struct Built {
+    factory: OnceCell<TmpPostgrustFactory>,
+    error: Mutex<Option<TmpPostgrustError>>,
+}
+
+impl LazyFactory {
+    pub(crate) fn spawn(builder: TmpPostgrustFactoryBuilder) -> Self {
+        let (send, done) = watch::channel(());
+        let built = Arc::new(Built::default());
+        {
+            let built = Arc::clone(&built);
+            std::thread::spawn(move || {
+                match builder.build() {
+                    Ok(factory) => {
+                        let _ = built.factory.set(factory);
+                    }
+                    Err(err) => {
+                        *built.error.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+                    }
+                }
+                drop(send);
+            });
+        }
+        LazyFactory { built, done }
+    }
+
+    /// Wait until the factory is built. The first caller receives the error if building
+    /// failed, later callers [`LazyFactoryFailed`](TmpPostgrustError::LazyFactoryFailed).
+    ///
+    /// Waiting is cancel-safe: a caller that gives up, e.g. because of a timeout, does not
+    /// affect later callers.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the error of building the factory, as described above.
+    pub async fn ready(&self) -> TmpPostgrustResult<&TmpPostgrustFactory> {
+        // Only fails once the sender is dropped, as nothing is ever sent.
+        let _ = self.done.clone().changed().await;
+        if let Some(factory) = self.built.factory.get() {
+            return Ok(factory);
+        }
+        Err(self
+            .built
+            .error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .unwrap_or(TmpPostgrustError::LazyFactoryFailed))
+    }
+}
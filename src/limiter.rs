@@ -0,0 +1,245 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+
+/// Number of instances of a factory that may run at the same time unless configured otherwise.
+pub(crate) const DEFAULT_MAX_CONCURRENT_INSTANCES: usize = 8;
+
+/// What to do when a new instance is requested while the limit of running instances is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InstanceLimitBehavior {
+    /// Wait until an instance stops, serving waiting requests by
+    /// [priority](InstancePriority) and then in the order they were made.
+    #[default]
+    Queue,
+    /// Wait in line like [`Queue`](Self::Queue), but give up after the given time with
+    /// [`InstanceLimitTimeout`](TmpPostgrustError::InstanceLimitTimeout).
+    Timeout(Duration),
+    /// Fail immediately with
+    /// [`InstanceLimitReached`](TmpPostgrustError::InstanceLimitReached).
+    FailFast,
+}
+
+/// Priority of a request for an instance while the limit of running instances is reached.
+/// Waiting requests with a higher priority are served first, so quick smoke tests are not
+/// starved behind heavyweight migration tests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum InstancePriority {
+    /// Served after every other waiting request.
+    Low,
+    /// Priority of requests that do not specify one.
+    #[default]
+    Normal,
+    /// Served before every other waiting request.
+    High,
+}
+
+/// Position in the queue of waiting requests, highest priority first and then oldest first.
+type Ticket = (Reverse<InstancePriority>, u64);
+
+#[derive(Debug)]
+struct LimiterState {
+    available: usize,
+    next_ticket: u64,
+    waiting: BTreeSet<Ticket>,
+}
+
+/// Fair counting semaphore limiting the number of running instances, serving waiting
+/// requests by priority.
+#[derive(Debug)]
+pub(crate) struct InstanceLimiter {
+    limit: usize,
+    state: Mutex<LimiterState>,
+    changed: Condvar,
+    #[cfg(feature = "tokio-process")]
+    changed_async: tokio::sync::Notify,
+}
+
+impl InstanceLimiter {
+    pub(crate) const fn new(limit: usize) -> Self {
+        InstanceLimiter {
+            limit,
+            state: Mutex::new(LimiterState {
+                available: limit,
+                next_ticket: 0,
+                waiting: BTreeSet::new(),
+            }),
+            changed: Condvar::new(),
+            #[cfg(feature = "tokio-process")]
+            changed_async: tokio::sync::Notify::const_new(),
+        }
+    }
+
+    /// Number of instances that may run at the same time.
+    pub(crate) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Wake up every waiting request, e.g. so it notices that it was cancelled.
+    pub(crate) fn notify(&self) {
+        self.changed.notify_all();
+        #[cfg(feature = "tokio-process")]
+        self.changed_async.notify_waiters();
+    }
+
+    /// Take a permit right away if one is available and nobody is waiting, otherwise queue
+    /// up unless `behavior` is to fail fast.
+    fn enqueue(
+        self: &Arc<Self>,
+        behavior: InstanceLimitBehavior,
+        priority: InstancePriority,
+    ) -> TmpPostgrustResult<Result<InstancePermit, QueuedRequest>> {
+        let mut state = self.state.lock().unwrap();
+        if state.available > 0 && state.waiting.is_empty() {
+            state.available -= 1;
+            return Ok(Ok(InstancePermit {
+                limiter: Arc::clone(self),
+            }));
+        }
+        if behavior == InstanceLimitBehavior::FailFast {
+            return Err(TmpPostgrustError::InstanceLimitReached);
+        }
+        let ticket = (Reverse(priority), state.next_ticket);
+        state.next_ticket += 1;
+        state.waiting.insert(ticket);
+        debug!("waiting for a running instance to stop");
+        Ok(Err(QueuedRequest {
+            limiter: Arc::clone(self),
+            ticket,
+        }))
+    }
+
+    /// Claim a running instance, waiting according to `behavior` while the limit is reached.
+    pub(crate) fn acquire(
+        self: &Arc<Self>,
+        behavior: InstanceLimitBehavior,
+        priority: InstancePriority,
+    ) -> TmpPostgrustResult<InstancePermit> {
+        self.acquire_unless(behavior, priority, &|| false)
+            .map(|permit| permit.expect("the request is never cancelled"))
+    }
+
+    /// Claim a running instance like [`acquire`](Self::acquire), giving up with `None` once
+    /// `cancelled` returns true, which is checked whenever [`notify`](Self::notify) wakes
+    /// the request up.
+    pub(crate) fn acquire_unless(
+        self: &Arc<Self>,
+        behavior: InstanceLimitBehavior,
+        priority: InstancePriority,
+        cancelled: &dyn Fn() -> bool,
+    ) -> TmpPostgrustResult<Option<InstancePermit>> {
+        let request = match self.enqueue(behavior, priority)? {
+            Ok(permit) => return Ok(Some(permit)),
+            Err(request) => request,
+        };
+        let deadline = match behavior {
+            InstanceLimitBehavior::Timeout(timeout) => Some((Instant::now() + timeout, timeout)),
+            _ => None,
+        };
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if request.try_take(&mut state) {
+                drop(state);
+                return Ok(Some(request.into_permit()));
+            }
+            if cancelled() {
+                return Ok(None);
+            }
+            state = match deadline {
+                None => self.changed.wait(state).unwrap(),
+                Some((deadline, timeout)) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Err(TmpPostgrustError::InstanceLimitTimeout(timeout));
+                    }
+                    self.changed.wait_timeout(state, remaining).unwrap().0
+                }
+            };
+        }
+    }
+
+    /// Claim a running instance, waiting according to `behavior` while the limit is reached.
+    #[cfg(feature = "tokio-process")]
+    pub(crate) async fn acquire_async(
+        self: &Arc<Self>,
+        behavior: InstanceLimitBehavior,
+        priority: InstancePriority,
+    ) -> TmpPostgrustResult<InstancePermit> {
+        let request = match self.enqueue(behavior, priority)? {
+            Ok(permit) => return Ok(permit),
+            Err(request) => request,
+        };
+        let wait = async {
+            loop {
+                // Registered before checking, so a permit released in between is not missed.
+                let changed = self.changed_async.notified();
+                if request.try_take(&mut self.state.lock().unwrap()) {
+                    return;
+                }
+                changed.await;
+            }
+        };
+        match behavior {
+            InstanceLimitBehavior::Timeout(timeout) => tokio::time::timeout(timeout, wait)
+                .await
+                .map_err(|_| TmpPostgrustError::InstanceLimitTimeout(timeout))?,
+            _ => wait.await,
+        }
+        Ok(request.into_permit())
+    }
+}
+
+/// Request waiting in the queue of a limiter, which leaves the queue when dropped.
+struct QueuedRequest {
+    limiter: Arc<InstanceLimiter>,
+    ticket: Ticket,
+}
+
+impl QueuedRequest {
+    /// Take a permit if one is available and this request is first in line.
+    fn try_take(&self, state: &mut MutexGuard<'_, LimiterState>) -> bool {
+        if state.available == 0 || state.waiting.first() != Some(&self.ticket) {
+            return false;
+        }
+        state.waiting.remove(&self.ticket);
+        state.available -= 1;
+        true
+    }
+
+    fn into_permit(self) -> InstancePermit {
+        let limiter = Arc::clone(&self.limiter);
+        drop(self);
+        InstancePermit { limiter }
+    }
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        self.limiter
+            .state
+            .lock()
+            .unwrap()
+            .waiting
+            .remove(&self.ticket);
+        // The next in line may be able to proceed now.
+        self.limiter.notify();
+    }
+}
+
+/// Counts a running instance against the limit until dropped.
+#[derive(Debug)]
+pub(crate) struct InstancePermit {
+    limiter: Arc<InstanceLimiter>,
+}
+
+impl Drop for InstancePermit {
+    fn drop(&mut self) {
+        self.limiter.state.lock().unwrap().available += 1;
+        self.limiter.notify();
+    }
+}
@@ -0,0 +1,135 @@
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tempdir::TempDir;
+use tracing::{info, warn};
+
+use crate::builder::Verbosity;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::synchronous::exec_process;
+
+/// Directories searched for `mkfs.ext4`, which are often not on the `PATH` of regular users.
+const SBIN_DIRS: [&str; 2] = ["/usr/sbin", "/sbin"];
+
+/// Size limited ext4 filesystem in an image file, mounted through a loop device and
+/// unmounted and deleted when dropped unless it has been kept.
+///
+/// Mounting requires root, which postgresql refuses to run as, so `mount` and `umount` are run
+/// with `sudo -n`.
+#[derive(Debug)]
+pub(crate) struct LoopbackFs {
+    dir: PathBuf,
+    mount_point: PathBuf,
+    mounted: bool,
+    verbosity: Verbosity,
+    remove_on_drop: AtomicBool,
+}
+
+impl LoopbackFs {
+    /// Create a filesystem of `size` bytes owned by the current user, mounted in a new uniquely
+    /// named directory in `root`.
+    pub(crate) fn create(
+        root: &Path,
+        prefix: &str,
+        size: u64,
+        verbosity: Verbosity,
+    ) -> TmpPostgrustResult<Self> {
+        let dir = TempDir::new_in(root, prefix)
+            .map_err(TmpPostgrustError::CreateLoopbackFsFailed)?
+            .into_path();
+        let owner = dir
+            .metadata()
+            .map_err(TmpPostgrustError::CreateLoopbackFsFailed)?;
+        // Removes the directory if any of the following steps fail.
+        let mut loopback = LoopbackFs {
+            mount_point: dir.join("mnt"),
+            dir,
+            mounted: false,
+            verbosity,
+            remove_on_drop: AtomicBool::new(true),
+        };
+
+        let image = loopback.dir.join("disk.img");
+        File::create(&image)
+            .and_then(|file| file.set_len(size))
+            .map_err(TmpPostgrustError::CreateLoopbackFsFailed)?;
+        exec_process(
+            Command::new(find_sbin_command("mkfs.ext4"))
+                .args(["-q", "-F", "-m", "0", "-E"])
+                .arg(format!("root_owner={}:{}", owner.uid(), owner.gid()))
+                .arg(&image),
+            verbosity,
+            TmpPostgrustError::LoopbackFsFailed,
+        )?;
+        std::fs::create_dir(&loopback.mount_point)
+            .map_err(TmpPostgrustError::CreateLoopbackFsFailed)?;
+        exec_process(
+            sudo("mount")
+                .args(["-o", "loop"])
+                .arg(&image)
+                .arg(&loopback.mount_point),
+            verbosity,
+            TmpPostgrustError::LoopbackFsFailed,
+        )?;
+        loopback.mounted = true;
+        info!(
+            "mounted {} byte filesystem on {:?}",
+            size, loopback.mount_point
+        );
+        Ok(loopback)
+    }
+
+    /// Root directory of the mounted filesystem.
+    pub(crate) fn mount_point(&self) -> &Path {
+        &self.mount_point
+    }
+
+    /// Leave the filesystem mounted and its image in place when dropped.
+    pub(crate) fn keep(&self) {
+        if self.remove_on_drop.swap(false, Ordering::SeqCst) {
+            info!("keeping filesystem mounted on {:?}", self.mount_point);
+        }
+    }
+}
+
+impl Drop for LoopbackFs {
+    fn drop(&mut self) {
+        if !self.remove_on_drop.load(Ordering::SeqCst) {
+            return;
+        }
+        if self.mounted {
+            let unmounted = exec_process(
+                sudo("umount").arg(&self.mount_point),
+                self.verbosity,
+                TmpPostgrustError::LoopbackFsFailed,
+            );
+            if let Err(err) = unmounted {
+                warn!("failed to unmount {:?}: {}", self.mount_point, err);
+                return;
+            }
+        }
+        if let Err(err) = std::fs::remove_dir_all(&self.dir) {
+            warn!("failed to remove directory {:?}: {}", self.dir, err);
+        }
+    }
+}
+
+/// Run `program` as root without prompting for a password.
+fn sudo(program: &str) -> Command {
+    let mut command = Command::new("sudo");
+    command.args(["-n", program]);
+    command
+}
+
+/// Path of a system administration command, falling back to the name to look it up on the
+/// `PATH`.
+fn find_sbin_command(name: &str) -> PathBuf {
+    SBIN_DIRS
+        .iter()
+        .map(|dir| Path::new(dir).join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| PathBuf::from(name))
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::search::find_postgresql_command;
+
+/// Binaries run by factories and guards. Missing client tools are left out of the manifest.
+const BINARIES: [&str; 6] = [
+    "postgres",
+    "initdb",
+    "createdb",
+    "createuser",
+    "psql",
+    "pg_dump",
+];
+
+/// Postgresql binary recorded in a [`BinaryManifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryInfo {
+    /// Name of the binary, e.g. `initdb`.
+    pub name: String,
+    /// Location the binary was found at.
+    pub path: PathBuf,
+    /// Output of `<binary> --version`.
+    pub version: String,
+    /// 64 bit FNV-1a hash of the binary in hex. Detects changed builds, but is not
+    /// cryptographically secure.
+    pub checksum: String,
+}
+
+/// Exact postgresql binaries used by a factory, so CI can require that later runs use the
+/// same build and notice silent upgrades of the environment.
+///
+/// The text format has one line per binary with the name, version, checksum and path
+/// separated by tabs, and is written with `to_string` and read with `parse`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BinaryManifest {
+    /// Binaries in the order they were looked up.
+    pub binaries: Vec<BinaryInfo>,
+}
+
+impl BinaryManifest {
+    /// Record the binaries that are currently resolved.
+    pub(crate) fn collect() -> TmpPostgrustResult<BinaryManifest> {
+        let mut binaries = Vec::new();
+        for name in BINARIES {
+            let Ok(path) = find_postgresql_command("bin", name) else {
+                continue;
+            };
+            let output = Command::new(&path)
+                .arg("--version")
+                .output()
+                .map_err(|source| TmpPostgrustError::ExecSubprocessFailed {
+                    source,
+                    command: format!("{} --version", path.display()),
+                })?;
+            let checksum =
+                checksum(&path).map_err(|e| TmpPostgrustError::ManifestFailed(e.to_string()))?;
+            binaries.push(BinaryInfo {
+                name: name.to_string(),
+                version: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+                checksum,
+                path,
+            });
+        }
+        Ok(BinaryManifest { binaries })
+    }
+
+    /// Describe every binary of `required` that is missing or differs in version or checksum.
+    /// Paths may differ as long as the binaries are identical.
+    #[must_use]
+    pub fn mismatches(&self, required: &BinaryManifest) -> Vec<String> {
+        required
+            .binaries
+            .iter()
+            .filter_map(|required| {
+                let Some(found) = self.binaries.iter().find(|b| b.name == required.name) else {
+                    return Some(format!("{} is missing", required.name));
+                };
+                if found.version != required.version || found.checksum != required.checksum {
+                    return Some(format!(
+                        "{} at {} is {} ({}), expected {} ({})",
+                        found.name,
+                        found.path.display(),
+                        found.version,
+                        found.checksum,
+                        required.version,
+                        required.checksum
+                    ));
+                }
+                None
+            })
+            .collect()
+    }
+
+    /// Fail with [`TmpPostgrustError::ManifestMismatch`] unless the binaries match `required`.
+    pub(crate) fn require(&self, required: &BinaryManifest) -> TmpPostgrustResult<()> {
+        let mismatches = self.mismatches(required);
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(TmpPostgrustError::ManifestMismatch(mismatches.join("; ")))
+        }
+    }
+}
+
+impl fmt::Display for BinaryManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for binary in &self.binaries {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}",
+                binary.name,
+                binary.version,
+                binary.checksum,
+                binary.path.display()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BinaryManifest {
+    type Err = TmpPostgrustError;
+
+    fn from_str(manifest: &str) -> Result<Self, Self::Err> {
+        let binaries = manifest
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| match line.splitn(4, '\t').collect::<Vec<_>>()[..] {
+                [name, version, checksum, path] => Ok(BinaryInfo {
+                    name: name.to_string(),
+                    path: PathBuf::from(path),
+                    version: version.to_string(),
+                    checksum: checksum.to_string(),
+                }),
+                _ => Err(TmpPostgrustError::ManifestFailed(format!(
+                    "invalid manifest line: {line:?}"
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(BinaryManifest { binaries })
+    }
+}
+
+/// 64 bit FNV-1a hash of the file at `path` in hex.
+fn checksum(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut buffer = [0; 8 * 1024];
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for byte in &buffer[..read] {
+            hash = (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    Ok(format!("{hash:016x}"))
+}
@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::sql::{split_records, unaligned_query_args};
+
+/// Reports the server version followed by every setting that differs from its built-in
+/// default, e.g. because it was set in the configuration file.
+const SERVER_QUERY: &str = "
+SELECT 'version', current_setting('server_version'), NULL
+UNION ALL
+SELECT 'setting', name, setting
+FROM pg_settings
+WHERE source NOT IN ('default', 'override')
+ORDER BY 1, 2;
+";
+
+/// Details of a running instance for tooling outside of the test process, e.g. shell scripts
+/// or test processes written in other languages.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstanceMetadata {
+    /// Label of the instance.
+    pub label: String,
+    /// Connection string for the database user.
+    pub connection_string: String,
+    /// Directory containing the unix socket of the server.
+    pub host: PathBuf,
+    /// Port the server listens on.
+    pub port: u32,
+    /// Database user.
+    pub user: String,
+    /// Database created for the user.
+    pub dbname: String,
+    /// Data directory of the server.
+    pub data_directory: PathBuf,
+    /// Version of the server, e.g. `15.4`.
+    pub server_version: String,
+    /// Settings that differ from their built-in defaults.
+    pub settings: BTreeMap<String, String>,
+}
+
+/// Arguments for `psql` to print the server query in a parseable form.
+pub(crate) fn server_query_args() -> [&'static str; 9] {
+    unaligned_query_args(SERVER_QUERY)
+}
+
+/// Fill in the server version and settings of `metadata` from the output of the server query.
+pub(crate) fn parse_server(output: &str, metadata: &mut InstanceMetadata) {
+    for fields in split_records(output) {
+        match fields.as_slice() {
+            ["version", version, ..] => metadata.server_version = (*version).to_string(),
+            ["setting", name, setting, ..] => {
+                metadata
+                    .settings
+                    .insert((*name).to_string(), (*setting).to_string());
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use tracing::info;
+
+use crate::builder::DynamicSharedMemoryType;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+
+/// `/dev/shm` smaller than this cannot hold the dynamic shared memory segments of parallel
+/// queries. Docker limits it to 64MB unless told otherwise.
+pub(crate) const MIN_SHM_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Quirks of the system that factories work around.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub(crate) struct Platform {
+    /// `cp` is provided by `BusyBox`, which lacks clone support and some GNU flags.
+    pub(crate) busybox_cp: bool,
+    /// The C library is musl, e.g. on Alpine, whose locales only support the C collation.
+    pub(crate) musl: bool,
+    /// Running inside a container.
+    pub(crate) container: bool,
+    /// `/dev/shm` is too small for POSIX dynamic shared memory.
+    pub(crate) small_shm: bool,
+    /// The temporary directory lives on overlayfs, which cannot clone files.
+    pub(crate) overlay_tmp: bool,
+    /// No locale besides `C` and `POSIX` is installed.
+    pub(crate) missing_locales: bool,
+    /// The system temporary directory is mounted `noexec`.
+    pub(crate) noexec_tmp: bool,
+    /// Directory temporary directories are created in, somewhere else than the system
+    /// temporary directory if that is mounted `noexec`. `None` if no alternative was found.
+    pub(crate) temp_root: Option<PathBuf>,
+}
+
+impl Platform {
+    /// Detect the quirks of the system the factory runs on.
+    pub(crate) fn detect() -> Platform {
+        let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+        let system_tmp = std::env::temp_dir();
+        let noexec_tmp = is_noexec(&mounts, &system_tmp);
+        let temp_root = if noexec_tmp {
+            exec_temp_root(&mounts)
+        } else {
+            Some(system_tmp)
+        };
+        let platform = Platform {
+            busybox_cp: is_busybox_cp(),
+            musl: cfg!(target_env = "musl") || Path::new("/etc/alpine-release").exists(),
+            container: is_container(),
+            small_shm: shm_size(&mounts).is_some_and(|size| size < MIN_SHM_BYTES),
+            overlay_tmp: temp_root
+                .as_ref()
+                .is_some_and(|root| mount_type(&mounts, root) == Some("overlay")),
+            missing_locales: missing_locales(),
+            noexec_tmp,
+            temp_root,
+        };
+        let workarounds = platform.workarounds();
+        if !workarounds.is_empty() {
+            info!(
+                container = platform.container,
+                "applied platform workarounds: {}",
+                workarounds.join(", ")
+            );
+        }
+        platform
+    }
+
+    /// Extra `initdb` arguments, selecting the C locale where other locales are unreliable.
+    pub(crate) fn initdb_args(&self) -> Vec<&'static str> {
+        if self.musl || self.missing_locales {
+            vec!["--no-locale", "--encoding=UTF8"]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Directory to create temporary directories in, failing with an explanation when the
+    /// system temporary directory is mounted `noexec` and no alternative was found.
+    pub(crate) fn temp_root(&self) -> TmpPostgrustResult<PathBuf> {
+        match &self.temp_root {
+            Some(temp_root) => Ok(temp_root.clone()),
+            None if self.noexec_tmp => Err(TmpPostgrustError::NoexecTempDir(std::env::temp_dir())),
+            None => Ok(std::env::temp_dir()),
+        }
+    }
+
+    /// `dynamic_shared_memory_type` to use instead of the server default.
+    pub(crate) fn dynamic_shared_memory_type(&self) -> Option<DynamicSharedMemoryType> {
+        if self.small_shm {
+            Some(DynamicSharedMemoryType::Mmap)
+        } else {
+            None
+        }
+    }
+
+    /// Human readable descriptions of the workarounds applied on this platform.
+    pub(crate) fn workarounds(&self) -> Vec<&'static str> {
+        let mut workarounds = Vec::new();
+        if self.busybox_cp {
+            workarounds.push("native copy instead of BusyBox cp");
+        } else if self.overlay_tmp {
+            workarounds.push("plain cp on overlayfs");
+        }
+        if self.musl {
+            workarounds.push("no locale on musl");
+        } else if self.missing_locales {
+            workarounds.push("no locale as none are installed");
+        }
+        if self.small_shm {
+            workarounds.push("mmap dynamic shared memory as /dev/shm is small");
+        }
+        if self.noexec_tmp {
+            workarounds.push("temporary directories outside of the noexec system temp directory");
+        }
+        workarounds
+    }
+}
+
+fn is_busybox_cp() -> bool {
+    // `BusyBox` prints its name in the usage text, to stdout or stderr depending on the version.
+    Command::new("cp")
+        .arg("--help")
+        .output()
+        .is_ok_and(|output| {
+            String::from_utf8_lossy(&output.stdout).contains("BusyBox")
+                || String::from_utf8_lossy(&output.stderr).contains("BusyBox")
+        })
+}
+
+fn is_container() -> bool {
+    Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+        || fs::read_to_string("/proc/1/cgroup").is_ok_and(|cgroup| {
+            ["docker", "kubepods", "containerd", "lxc"]
+                .iter()
+                .any(|runtime| cgroup.contains(runtime))
+        })
+}
+
+/// Size of the tmpfs mounted at `/dev/shm`, from its `size=` mount option.
+pub(crate) fn shm_size(mounts: &str) -> Option<u64> {
+    let options = mounts.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.get(1) == Some(&"/dev/shm")).then(|| fields.get(3).copied())?
+    })?;
+    let size = options
+        .split(',')
+        .find_map(|option| option.strip_prefix("size="))?;
+    let (digits, unit) = size.split_at(
+        size.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(size.len()),
+    );
+    let multiplier = match unit {
+        "" => 1,
+        "k" => 1024,
+        "m" => 1024 * 1024,
+        "g" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok().map(|size| size * multiplier)
+}
+
+/// Filesystem type and mount options of the mount point holding `path`.
+fn mount_of<'a>(mounts: &'a str, path: &Path) -> Option<(&'a str, &'a str)> {
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?;
+            let fs_type = fields.next()?;
+            let options = fields.next().unwrap_or_default();
+            path.starts_with(mount_point)
+                .then_some((mount_point.len(), (fs_type, options)))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, mount)| mount)
+}
+
+/// Filesystem type of the mount point holding `path`.
+pub(crate) fn mount_type<'a>(mounts: &'a str, path: &Path) -> Option<&'a str> {
+    mount_of(mounts, path).map(|(fs_type, _)| fs_type)
+}
+
+/// True if `path` is on a filesystem mounted `noexec`, where extensions and tools placed in
+/// temporary directories cannot be run.
+pub(crate) fn is_noexec(mounts: &str, path: &Path) -> bool {
+    mount_of(mounts, path).is_some_and(|(_, options)| options.split(',').any(|o| o == "noexec"))
+}
+
+/// Writable directory outside of a `noexec` mount for temporary directories: inside the
+/// cargo target directory, or in the cache directory of the user.
+fn exec_temp_root(mounts: &str) -> Option<PathBuf> {
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .or_else(|| Some(std::env::current_dir().ok()?.join("target")));
+    let user_cache = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache"));
+    IntoIterator::into_iter([target_dir, user_cache])
+        .flatten()
+        .map(|dir| dir.join("tmp-postgrust"))
+        .find(|dir| fs::create_dir_all(dir).is_ok() && !is_noexec(mounts, dir))
+}
+
+fn missing_locales() -> bool {
+    // Without the `locale` tool there is no way to tell, leave it to `initdb`.
+    let Ok(output) = Command::new("locale").arg("-a").output() else {
+        return false;
+    };
+    output.status.success()
+        && String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .all(|locale| matches!(locale, "C" | "POSIX" | "C.UTF-8" | "C.utf8"))
+}
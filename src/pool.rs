@@ -0,0 +1,237 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use tracing::{debug, error};
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::synchronous::ProcessGuard;
+use crate::TmpPostgrustFactory;
+
+/// Label of the instances started by a pool.
+const POOL_LABEL: &str = "pool";
+
+/// How long the provisioner waits before retrying after failing to start an instance.
+const PROVISION_RETRY: Duration = Duration::from_secs(1);
+
+/// Instances of a factory started ahead of time in a background thread, so
+/// [`acquire`](Self::acquire) hands out a running instance in milliseconds instead of waiting
+/// for the server to start.
+///
+/// The pool has a fixed number of slots. Each slot holds either a standby instance or one that
+/// was acquired; when the guard of an acquired instance is dropped its server is stopped and
+/// a fresh standby takes its place in the background. Remaining standbys are stopped when the
+/// pool is dropped.
+///
+/// Standbys count against the [limit of running
+/// instances](crate::TmpPostgrustFactoryBuilder::with_max_concurrent_instances) of the
+/// factory, so the pool cannot have more slots than the limit allows.
+pub struct FactoryPool {
+    state: Arc<PoolState>,
+    provisioner: Option<JoinHandle<()>>,
+}
+
+impl FactoryPool {
+    /// Keep `size` instances of `factory` running, starting them in the background.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PoolTooLarge`](TmpPostgrustError::PoolTooLarge) if `size` exceeds the limit
+    /// of running instances of `factory`.
+    pub fn new(factory: TmpPostgrustFactory, size: usize) -> TmpPostgrustResult<Self> {
+        let limit = factory.inner.instance_limiter.limit();
+        if size > limit {
+            return Err(TmpPostgrustError::PoolTooLarge { size, limit });
+        }
+        let state = Arc::new(PoolState {
+            factory,
+            size,
+            slots: Mutex::default(),
+            changed: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+        let provisioner = {
+            let state = Arc::clone(&state);
+            std::thread::spawn(move || state.provision())
+        };
+        Ok(FactoryPool {
+            state,
+            provisioner: Some(provisioner),
+        })
+    }
+
+    /// Factory the instances are started from.
+    #[must_use]
+    pub fn factory(&self) -> &TmpPostgrustFactory {
+        &self.state.factory
+    }
+
+    /// Number of slots of the pool.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.state.size
+    }
+
+    /// Number of standby instances that can be acquired right away.
+    #[must_use]
+    pub fn ready(&self) -> usize {
+        self.state.slots().standby.len()
+    }
+
+    /// Take a standby instance out of the pool, waiting for one to be started if every slot
+    /// is acquired or still starting.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the error of the last attempt to start a standby if it failed while waiting.
+    /// The pool keeps retrying in the background, so a later call may succeed.
+    pub fn acquire(&self) -> TmpPostgrustResult<PooledInstance> {
+        let mut slots = self.state.slots();
+        loop {
+            if let Some(guard) = slots.standby.pop_front() {
+                slots.acquired += 1;
+                return Ok(PooledInstance {
+                    guard: Some(guard),
+                    state: Arc::clone(&self.state),
+                });
+            }
+            if let Some(err) = slots.failure.take() {
+                return Err(err);
+            }
+            slots = self
+                .state
+                .changed
+                .wait(slots)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+impl fmt::Debug for FactoryPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slots = self.state.slots();
+        f.debug_struct("FactoryPool")
+            .field("size", &self.state.size)
+            .field("standby", &slots.standby.len())
+            .field("acquired", &slots.acquired)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for FactoryPool {
+    fn drop(&mut self) {
+        self.state.shutdown.store(true, Ordering::SeqCst);
+        // Stop the standbys first, as the provisioner may wait for the permits they hold.
+        let standby = std::mem::take(&mut self.state.slots().standby);
+        drop(standby);
+        self.state.changed.notify_all();
+        // Wake the provisioner up if it waits for a permit held by an acquired instance.
+        self.state.factory.inner.instance_limiter.notify();
+        if let Some(provisioner) = self.provisioner.take() {
+            let _ = provisioner.join();
+        }
+        // A standby the provisioner finished starting while the pool was being dropped.
+        self.state.slots().standby.clear();
+    }
+}
+
+#[derive(Default)]
+struct Slots {
+    standby: VecDeque<ProcessGuard>,
+    acquired: usize,
+    // Error of the last failed attempt to start a standby, handed to a waiting `acquire`.
+    failure: Option<TmpPostgrustError>,
+}
+
+struct PoolState {
+    factory: TmpPostgrustFactory,
+    size: usize,
+    slots: Mutex<Slots>,
+    changed: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl PoolState {
+    /// Slots of the pool, which stay consistent even if a thread panicked while holding them.
+    fn slots(&self) -> MutexGuard<'_, Slots> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Keep every slot that is not acquired filled with a standby instance.
+    fn provision(&self) {
+        loop {
+            {
+                let mut slots = self.slots();
+                while slots.standby.len() + slots.acquired >= self.size && !self.is_shutdown() {
+                    slots = self
+                        .changed
+                        .wait(slots)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+            if self.is_shutdown() {
+                return;
+            }
+            match self
+                .factory
+                .new_instance_unless(POOL_LABEL, &|| self.is_shutdown())
+            {
+                Ok(Some(instance)) => {
+                    debug!("started standby instance on port {}", instance.auth.port);
+                    let mut slots = self.slots();
+                    slots.standby.push_back(instance);
+                    slots.failure = None;
+                    drop(slots);
+                    self.changed.notify_all();
+                }
+                Ok(None) => return,
+                Err(err) => {
+                    error!("failed to start standby instance: {}", err);
+                    self.slots().failure = Some(err);
+                    self.changed.notify_all();
+                    std::thread::sleep(PROVISION_RETRY);
+                }
+            }
+        }
+    }
+}
+
+/// Instance acquired from a [`FactoryPool`], dereferencing to its
+/// [`ProcessGuard`]. Dropping it stops the server and frees its slot for a fresh standby.
+pub struct PooledInstance {
+    guard: Option<ProcessGuard>,
+    state: Arc<PoolState>,
+}
+
+impl Deref for PooledInstance {
+    type Target = ProcessGuard;
+
+    fn deref(&self) -> &ProcessGuard {
+        self.guard.as_ref().expect("guard is only taken on drop")
+    }
+}
+
+impl fmt::Debug for PooledInstance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledInstance")
+            .field("connection_string", &self.connection_string)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for PooledInstance {
+    fn drop(&mut self) {
+        // Stop the server first, so its replacement does not wait for a free instance permit.
+        drop(self.guard.take());
+        self.state.slots().acquired -= 1;
+        self.state.changed.notify_all();
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::dirs::InstanceDir;
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::instance::InstanceOptions;
+use crate::limiter::InstancePermit;
+use crate::workspace::WorkspaceSlot;
+use crate::TmpPostgrustFactory;
+
+/// Data directory of an instance that has been created but not started yet, returned by
+/// [`prepare_instance`](TmpPostgrustFactory::prepare_instance). Its configuration files can be
+/// inspected and modified before [`start`](Self::start) boots the server, e.g. to test
+/// configuration tooling or to debug a server that fails to start.
+///
+/// Dropping it without starting removes the data directory.
+pub struct PreparedInstance<'a> {
+    pub(crate) factory: &'a TmpPostgrustFactory,
+    pub(crate) label: String,
+    pub(crate) socket_dir: Arc<InstanceDir>,
+    pub(crate) port: u32,
+    pub(crate) data_directory: InstanceDir,
+    pub(crate) started: Instant,
+    pub(crate) instance_permit: InstancePermit,
+    pub(crate) workspace_slot: Option<WorkspaceSlot>,
+    pub(crate) options: InstanceOptions,
+}
+
+impl PreparedInstance<'_> {
+    /// Label of the instance.
+    #[must_use]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Port the server will listen on.
+    #[must_use]
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    /// Data directory of the instance.
+    #[must_use]
+    pub fn data_directory(&self) -> &Path {
+        self.data_directory.path()
+    }
+
+    /// Contents of `postgresql.conf` as rendered by the factory.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReadConfigFailed`](TmpPostgrustError::ReadConfigFailed) if the file cannot be
+    /// read.
+    pub fn postgresql_conf(&self) -> TmpPostgrustResult<String> {
+        self.read_config("postgresql.conf")
+    }
+
+    /// Replace the contents of `postgresql.conf`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CreateConfigFailed`](TmpPostgrustError::CreateConfigFailed) if the file cannot
+    /// be written.
+    pub fn set_postgresql_conf(&self, contents: &str) -> TmpPostgrustResult<()> {
+        self.write_config("postgresql.conf", contents)
+    }
+
+    /// Contents of `pg_hba.conf` as created by `initdb` or the factory.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReadConfigFailed`](TmpPostgrustError::ReadConfigFailed) if the file cannot be
+    /// read.
+    pub fn pg_hba_conf(&self) -> TmpPostgrustResult<String> {
+        self.read_config("pg_hba.conf")
+    }
+
+    /// Replace the contents of `pg_hba.conf`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CreateConfigFailed`](TmpPostgrustError::CreateConfigFailed) if the file cannot
+    /// be written.
+    pub fn set_pg_hba_conf(&self, contents: &str) -> TmpPostgrustResult<()> {
+        self.write_config("pg_hba.conf", contents)
+    }
+
+    fn read_config(&self, name: &str) -> TmpPostgrustResult<String> {
+        std::fs::read_to_string(self.data_directory.path().join(name))
+            .map_err(TmpPostgrustError::ReadConfigFailed)
+    }
+
+    fn write_config(&self, name: &str, contents: &str) -> TmpPostgrustResult<()> {
+        std::fs::write(self.data_directory.path().join(name), contents)
+            .map_err(TmpPostgrustError::CreateConfigFailed)
+    }
+
+    /// Boot the server and set up its database.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`new_labeled_instance`](crate::TmpPostgrustFactory::new_labeled_instance).
+    pub fn start(self) -> TmpPostgrustResult<crate::synchronous::ProcessGuard> {
+        self.factory.start_prepared(self)
+    }
+
+    /// Boot the server and set up its database.
+    ///
+    /// # Errors
+    ///
+    /// Fails like
+    /// [`new_labeled_instance_async`](crate::TmpPostgrustFactory::new_labeled_instance_async).
+    #[cfg(feature = "tokio-process")]
+    pub async fn start_async(self) -> TmpPostgrustResult<crate::asynchronous::ProcessGuard> {
+        self.factory.start_prepared_async(self).await
+    }
+}
@@ -0,0 +1,149 @@
+use std::convert::TryFrom;
+use std::fs;
+
+use tracing::info;
+
+use crate::builder::{DynamicSharedMemoryType, TmpPostgrustFactoryBuilder};
+use crate::platform::{shm_size, MIN_SHM_BYTES};
+use crate::workers::BackgroundWorkerExtension;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Memory assumed when it cannot be determined, a typical small CI runner.
+const DEFAULT_MEMORY_MB: u64 = 4096;
+
+/// Least memory given to a single instance when deriving how many may run at once.
+const MIN_INSTANCE_MEMORY_MB: u64 = 192;
+
+/// Ready-made configurations of factories, applied with
+/// [`with_preset`](TmpPostgrustFactoryBuilder::with_preset).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Tune the number of concurrent instances, `shared_buffers`, `max_connections` and the
+    /// dynamic shared memory type to the cores, memory and `/dev/shm` of the machine, so the
+    /// same configuration works on laptops and on small CI runners. Half of the memory is
+    /// shared among the instances, each getting a quarter of its share as `shared_buffers`.
+    Ci,
+    /// Preload `TimescaleDB` and create the extension in the database of every instance,
+    /// failing to start instances with the error of the server when the library is missing
+    /// and when the created extension does not match the installed version.
+    Timescale,
+    /// Preload Citus and create the extension in the database of every instance, to start
+    /// local clusters with
+    /// [`new_citus_cluster`](crate::TmpPostgrustFactory::new_citus_cluster).
+    Citus,
+    /// Preload pgaudit logging every class of statements to the
+    /// [CSV log](TmpPostgrustFactoryBuilder::with_csv_log), to test audit trail requirements
+    /// with [`audit_events`](crate::synchronous::ProcessGuard::audit_events).
+    Pgaudit,
+}
+
+impl Preset {
+    pub(crate) fn apply(self, builder: TmpPostgrustFactoryBuilder) -> TmpPostgrustFactoryBuilder {
+        match self {
+            Preset::Ci => {
+                let resources = RunnerResources::detect();
+                let tuning = Tuning::for_runner(&resources);
+                info!(
+                    cores = resources.cores,
+                    memory_bytes = resources.memory_bytes,
+                    shm_bytes = resources.shm_bytes,
+                    "tuned for CI: {} concurrent instances, shared_buffers {}MB, \
+                     max_connections {}",
+                    tuning.max_concurrent_instances,
+                    tuning.shared_buffers_mb,
+                    tuning.max_connections
+                );
+                let builder = builder
+                    .with_max_concurrent_instances(tuning.max_concurrent_instances)
+                    .with_shared_buffers(tuning.shared_buffers_mb)
+                    .with_max_connections(tuning.max_connections);
+                match tuning.dynamic_shared_memory_type {
+                    Some(shm_type) => builder.with_dynamic_shared_memory_type(shm_type),
+                    None => builder,
+                }
+            }
+            Preset::Timescale => {
+                builder.with_background_worker_extension(BackgroundWorkerExtension::timescaledb())
+            }
+            Preset::Citus => {
+                builder.with_background_worker_extension(BackgroundWorkerExtension::citus())
+            }
+            Preset::Pgaudit => builder
+                .with_background_worker_extension(BackgroundWorkerExtension::pgaudit())
+                .with_csv_log(true),
+        }
+    }
+}
+
+/// Resources of the machine running the tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RunnerResources {
+    pub(crate) cores: usize,
+    pub(crate) memory_bytes: Option<u64>,
+    pub(crate) shm_bytes: Option<u64>,
+}
+
+impl RunnerResources {
+    fn detect() -> Self {
+        let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+        RunnerResources {
+            cores: std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+            memory_bytes: memory_bytes(),
+            shm_bytes: shm_size(&mounts),
+        }
+    }
+}
+
+/// Physical memory, limited by the memory limit of the cgroup of the process.
+fn memory_bytes() -> Option<u64> {
+    let total = fs::read_to_string("/proc/meminfo")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?
+        * 1024;
+    // cgroup v2 and v1, "max" or a huge number when unlimited.
+    let limit = [
+        "/sys/fs/cgroup/memory.max",
+        "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+    ]
+    .iter()
+    .find_map(|path| fs::read_to_string(path).ok()?.trim().parse::<u64>().ok());
+    Some(limit.map_or(total, |limit| limit.min(total)))
+}
+
+/// Settings derived from the resources of the machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Tuning {
+    pub(crate) max_concurrent_instances: usize,
+    pub(crate) shared_buffers_mb: u32,
+    pub(crate) max_connections: u32,
+    pub(crate) dynamic_shared_memory_type: Option<DynamicSharedMemoryType>,
+}
+
+impl Tuning {
+    pub(crate) fn for_runner(resources: &RunnerResources) -> Self {
+        let memory_mb = resources
+            .memory_bytes
+            .map_or(DEFAULT_MEMORY_MB, |bytes| bytes / MIB);
+        let budget_mb = memory_mb / 2;
+        let instances = (resources.cores as u64)
+            .min(budget_mb / MIN_INSTANCE_MEMORY_MB)
+            .clamp(1, 32);
+        let instance_mb = budget_mb / instances;
+        Tuning {
+            max_concurrent_instances: usize::try_from(instances).unwrap_or(1),
+            shared_buffers_mb: u32::try_from((instance_mb / 4).clamp(12, 256)).unwrap_or(12),
+            max_connections: u32::try_from((instance_mb / 4).clamp(20, 100)).unwrap_or(20),
+            dynamic_shared_memory_type: resources
+                .shm_bytes
+                .is_some_and(|size| size < MIN_SHM_BYTES)
+                .then_some(DynamicSharedMemoryType::Mmap),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::builder::Verbosity;
+use crate::errors::TmpPostgrustError;
+
+/// How often `pg_isready` is run while a server is starting, which takes a few tens of
+/// milliseconds.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Arguments for `pg_isready` to check whether the server listening on `port` with its socket
+/// in `socket_dir` accepts connections. The check does not authenticate, so it works for every
+/// `pg_hba.conf`.
+pub(crate) fn pg_isready_args(socket_dir: &Path, port: u32) -> Vec<OsString> {
+    vec![
+        "-q".into(),
+        "-h".into(),
+        socket_dir.into(),
+        "-p".into(),
+        port.to_string().into(),
+        "-d".into(),
+        "postgres".into(),
+        "-t".into(),
+        "1".into(),
+    ]
+}
+
+/// Whether `pg_isready` exited with the status telling that the server accepts connections,
+/// unlike those for a server that is still starting, does not respond or was not contacted.
+pub(crate) fn is_ready(status: ExitStatus) -> bool {
+    status.success()
+}
+
+/// Message of a fatal error logged by the server, e.g. when a preloaded library is missing.
+fn fatal_error(line: &str) -> Option<String> {
+    line.split_once("FATAL:")
+        .map(|(_, message)| message.trim().to_string())
+}
+
+/// Whether a server failed to start because another server or process took its port, which
+/// happens in TCP mode when the port is taken between allocating and binding it.
+pub(crate) fn port_taken(err: &TmpPostgrustError) -> bool {
+    match err {
+        TmpPostgrustError::ServerStartFailed(fatal) => {
+            fatal.contains("could not create any TCP/IP sockets")
+                || (fatal.starts_with("lock file \"") && fatal.ends_with("\" already exists"))
+        }
+        _ => false,
+    }
+}
+
+/// Error for a server that exited while starting, with the last fatal error of what it wrote
+/// to `stderr`.
+pub(crate) fn startup_failure(stderr: &[String], verbosity: Verbosity) -> TmpPostgrustError {
+    let mut fatal = None;
+    for line in stderr {
+        if verbosity >= Verbosity::Verbose {
+            debug!("Postgresql: {}", line);
+        }
+        fatal = fatal_error(line).or(fatal);
+    }
+    TmpPostgrustError::ServerStartFailed(
+        fatal.unwrap_or_else(|| "server exited without logging a fatal error".to_string()),
+    )
+}
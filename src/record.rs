@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+
+use crate::audit::{self, DATABASE_COLUMN, DETAIL_COLUMN, MESSAGE_COLUMN, USER_COLUMN};
+use crate::auth::SUPERUSER;
+
+/// Settings logging every statement, including the parameters of prepared statements, and
+/// how long it took.
+pub(crate) const STATEMENT_LOG_CONFIG: &str = "log_statement = 'all'
+log_parameter_max_length = -1
+log_duration = on
+";
+
+/// Statement sent to an instance, as listed by
+/// [`recorded_statements`](crate::synchronous::ProcessGuard::recorded_statements).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedStatement {
+    /// Role of the session that sent the statement.
+    pub user: String,
+    /// Database the statement was sent to.
+    pub database: String,
+    /// Text of the statement, with `$1`, `$2`, ... placeholders for the parameters of prepared
+    /// statements.
+    pub statement: String,
+    /// Parameters of a prepared statement as SQL literals, e.g. `'42'` or `NULL`.
+    pub parameters: Vec<String>,
+}
+
+impl RecordedStatement {
+    pub(crate) fn parse(user: &str, database: &str, message: &str, detail: &str) -> Option<Self> {
+        let (statement, parameters) = if let Some(statement) = message.strip_prefix("statement: ") {
+            (statement, Vec::new())
+        } else {
+            // Prepared statements are logged as `execute <name>: ...` when they are executed.
+            let (_, statement) = message.strip_prefix("execute ")?.split_once(": ")?;
+            (statement, parse_parameters(detail))
+        };
+        Some(RecordedStatement {
+            user: user.to_string(),
+            database: database.to_string(),
+            statement: statement.to_string(),
+            parameters,
+        })
+    }
+
+    /// Text of the statement with the parameters filled in, so it can be run on its own.
+    #[must_use]
+    pub fn sql(&self) -> String {
+        if self.parameters.is_empty() {
+            return self.statement.clone();
+        }
+        let mut sql = String::with_capacity(self.statement.len());
+        let mut chars = self.statement.chars().peekable();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                ('\'' | '"', None) => quote = Some(c),
+                (c, Some(open)) if c == open => quote = None,
+                ('$', None) if chars.peek().is_some_and(char::is_ascii_digit) => {
+                    let mut number = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        number.push(digit);
+                    }
+                    let parameter = number
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| self.parameters.get(n.checked_sub(1)?));
+                    if let Some(parameter) = parameter {
+                        sql.push_str(parameter);
+                    } else {
+                        sql.push('$');
+                        sql.push_str(&number);
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            sql.push(c);
+        }
+        sql
+    }
+}
+
+/// Parse a detail such as `parameters: $1 = '42', $2 = NULL` into the values of the
+/// parameters.
+fn parse_parameters(detail: &str) -> Vec<String> {
+    let Some(mut rest) = detail.strip_prefix("parameters: ") else {
+        return Vec::new();
+    };
+    let mut parameters = Vec::new();
+    while let Some((_, value)) = rest.split_once(" = ") {
+        let end = if value.starts_with('\'') {
+            quoted_literal_len(value)
+        } else {
+            value.find(", $").unwrap_or(value.len())
+        };
+        parameters.push(value[..end].to_string());
+        rest = &value[end..];
+    }
+    parameters
+}
+
+/// Length of the quoted SQL literal at the start of `value`, in which quotes are doubled.
+fn quoted_literal_len(value: &str) -> usize {
+    let mut chars = value.char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '\'' {
+            if chars.peek().map(|&(_, c)| c) == Some('\'') {
+                chars.next();
+            } else {
+                return i + 1;
+            }
+        }
+    }
+    value.len()
+}
+
+/// Statements in the CSV log of the instance in `data_directory`, in the order they were
+/// logged, leaving out those of the superuser setting up the instance.
+pub(crate) fn read_statements(data_directory: &Path) -> std::io::Result<Vec<RecordedStatement>> {
+    let log = std::fs::read_to_string(data_directory.join(audit::CSV_LOG_FILE))?;
+    Ok(parse_statements(&log))
+}
+
+fn parse_statements(log: &str) -> Vec<RecordedStatement> {
+    audit::parse_csv(log)
+        .iter()
+        .filter(|record| record.get(USER_COLUMN).map(String::as_str) != Some(SUPERUSER))
+        .filter_map(|record| {
+            RecordedStatement::parse(
+                record.get(USER_COLUMN)?,
+                record.get(DATABASE_COLUMN)?,
+                record.get(MESSAGE_COLUMN)?,
+                record.get(DETAIL_COLUMN).map_or("", String::as_str),
+            )
+        })
+        .collect()
+}
+
+/// Script for `psql` running `statements` one after another.
+pub(crate) fn replay_script(statements: &[RecordedStatement]) -> String {
+    let mut script = String::new();
+    for statement in statements {
+        let sql = statement.sql();
+        let _ = writeln!(script, "-- {}", statement.user);
+        script.push_str(sql.trim_end().trim_end_matches(';'));
+        script.push_str(";\n\n");
+    }
+    script
+}
+
+/// Number of times each distinct query was sent to an instance, as returned by
+/// [`query_fingerprints`](crate::synchronous::ProcessGuard::query_fingerprints), e.g. for
+/// asserting that an endpoint issues a fixed number of queries instead of one per row.
+///
+/// Queries are identified by their fingerprint, the statement with literals and parameters
+/// replaced by `?`, whitespace collapsed and everything outside of quoted identifiers in lower
+/// case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryFingerprints {
+    counts: BTreeMap<String, usize>,
+}
+
+impl QueryFingerprints {
+    pub(crate) fn from_statements(statements: &[RecordedStatement]) -> Self {
+        let mut counts = BTreeMap::new();
+        for statement in statements {
+            *counts.entry(fingerprint(&statement.statement)).or_default() += 1;
+        }
+        QueryFingerprints { counts }
+    }
+
+    /// Number of times a query with the same fingerprint as `statement` was sent.
+    #[must_use]
+    pub fn count(&self, statement: &str) -> usize {
+        self.count_exact(&fingerprint(statement))
+    }
+
+    fn count_exact(&self, fingerprint: &str) -> usize {
+        self.counts.get(fingerprint).copied().unwrap_or_default()
+    }
+
+    /// Number of queries sent in total.
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Fingerprints with the number of times they were sent, ordered by fingerprint.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.counts
+            .iter()
+            .map(|(fingerprint, count)| (fingerprint.as_str(), *count))
+    }
+
+    /// Queries sent since `earlier` was taken, for counting the queries of a single request.
+    #[must_use]
+    pub fn since(&self, earlier: &QueryFingerprints) -> QueryFingerprints {
+        let counts = self
+            .counts
+            .iter()
+            .filter_map(|(fingerprint, &count)| {
+                let count = count.saturating_sub(earlier.count_exact(fingerprint));
+                (count > 0).then(|| (fingerprint.clone(), count))
+            })
+            .collect();
+        QueryFingerprints { counts }
+    }
+}
+
+impl fmt::Display for QueryFingerprints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (fingerprint, count) in self.iter() {
+            writeln!(f, "{count:>6} {fingerprint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Normalize `statement` so statements differing only in literals, parameters, whitespace or
+/// the case of keywords and unquoted identifiers are counted together.
+fn fingerprint(statement: &str) -> String {
+    let mut fingerprint = String::with_capacity(statement.len());
+    let mut chars = statement.chars().peekable();
+    while let Some(c) = chars.next() {
+        let in_word = fingerprint
+            .chars()
+            .next_back()
+            .is_some_and(|last| last.is_alphanumeric() || last == '_');
+        match c {
+            '\'' => {
+                // Quotes in literals are doubled, which reads as two adjacent literals.
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                fingerprint.push('?');
+            }
+            '"' => {
+                fingerprint.push(c);
+                for c in chars.by_ref() {
+                    fingerprint.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+                fingerprint.push('?');
+            }
+            c if c.is_ascii_digit() && !in_word => {
+                while chars.next_if(|c| c.is_ascii_digit() || *c == '.').is_some() {}
+                fingerprint.push('?');
+            }
+            c if c.is_whitespace() => {
+                if !fingerprint.is_empty() && !fingerprint.ends_with(' ') {
+                    fingerprint.push(' ');
+                }
+            }
+            c => fingerprint.extend(c.to_lowercase()),
+        }
+    }
+    fingerprint
+        .trim_end()
+        .trim_end_matches(';')
+        .trim_end()
+        .to_string()
+}
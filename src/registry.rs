@@ -0,0 +1,85 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use crate::usage::{self, ResourceUsage};
+
+/// Tracks the server processes started by a factory so they can be stopped together.
+#[derive(Debug, Default)]
+pub(crate) struct InstanceRegistry {
+    instances: Mutex<HashMap<u32, String>>,
+    usage: Mutex<BTreeMap<String, ResourceUsage>>,
+}
+
+impl InstanceRegistry {
+    /// Record a running server with the label of its instance, which stays registered until
+    /// the returned entry is dropped.
+    pub(crate) fn register(self: &Arc<Self>, pid: u32, label: &str) -> RegistryEntry {
+        self.instances
+            .lock()
+            .unwrap()
+            .insert(pid, label.to_string());
+        RegistryEntry {
+            registry: Arc::clone(self),
+            pid,
+        }
+    }
+
+    /// Number of registered servers.
+    pub(crate) fn len(&self) -> usize {
+        self.instances.lock().unwrap().len()
+    }
+
+    /// Resource usage of stopped servers, summed by label.
+    pub(crate) fn usage_by_label(&self) -> BTreeMap<String, ResourceUsage> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    /// Process ids and labels of all registered servers.
+    #[cfg(feature = "unix-signals")]
+    pub(crate) fn instances(&self) -> Vec<(u32, String)> {
+        self.instances
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(pid, label)| (*pid, label.clone()))
+            .collect()
+    }
+}
+
+/// Registration of a running server, removed from the registry when dropped.
+#[derive(Debug)]
+pub(crate) struct RegistryEntry {
+    registry: Arc<InstanceRegistry>,
+    pid: u32,
+}
+
+impl RegistryEntry {
+    /// Sample the resource usage of the server right before it is stopped and add it to the
+    /// totals of its label.
+    pub(crate) fn record_usage(&self) -> ResourceUsage {
+        let usage = usage::sample(self.pid);
+        let label = self
+            .registry
+            .instances
+            .lock()
+            .unwrap()
+            .get(&self.pid)
+            .cloned();
+        if let Some(label) = label {
+            *self
+                .registry
+                .usage
+                .lock()
+                .unwrap()
+                .entry(label)
+                .or_default() += usage;
+        }
+        usage
+    }
+}
+
+impl Drop for RegistryEntry {
+    fn drop(&mut self) {
+        self.registry.instances.lock().unwrap().remove(&self.pid);
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use tracing::{info, instrument};
+
+use crate::detach::{self, AttachedInstance, DetachedInstance};
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::{instance_id, temp_dir_prefix};
+
+/// Directory holding the state files of reusable instances when the factory has no
+/// workspace.
+const DEFAULT_REUSE_DIR: &str = "tmp-postgrust-reuse";
+
+/// State file of the reusable instance for `key` in `dir`, stable between runs.
+pub(crate) fn state_file(dir: Option<&Path>, key: &str) -> PathBuf {
+    let dir = dir.map_or_else(
+        || std::env::temp_dir().join(DEFAULT_REUSE_DIR),
+        Path::to_path_buf,
+    );
+    dir.join(format!(
+        "{}-{:08x}.state",
+        temp_dir_prefix("instance", key),
+        instance_id(key)
+    ))
+}
+
+/// Wait until no other process uses the reusable instance of `state_file`. The lock is held
+/// until the returned file is dropped.
+pub(crate) fn lock(state_file: &Path) -> TmpPostgrustResult<File> {
+    if let Some(dir) = state_file.parent() {
+        std::fs::create_dir_all(dir).map_err(TmpPostgrustError::StateFileFailed)?;
+    }
+    let mut lock_file = state_file.as_os_str().to_owned();
+    lock_file.push(".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_file)
+        .map_err(TmpPostgrustError::StateFileFailed)?;
+    file.lock().map_err(TmpPostgrustError::StateFileFailed)?;
+    Ok(file)
+}
+
+/// Adopt the server left running for `key` by a previous run, if it still runs.
+#[instrument]
+pub(crate) fn attach(key: &str, state_file: &Path) -> Option<AttachedInstance> {
+    if !state_file.exists() {
+        return None;
+    }
+    match detach::attach(state_file) {
+        Ok(attached) => Some(attached),
+        Err(err) => {
+            info!("not reusing instance {}: {}", key, err);
+            let _ = std::fs::remove_file(state_file);
+            None
+        }
+    }
+}
+
+/// Instance kept running between test runs for a caller-provided key, returned by
+/// [`reuse_instance`](crate::TmpPostgrustFactory::reuse_instance).
+///
+/// Dropping it leaves the server running for the next run with the same key, while
+/// [`discard`](Self::discard) stops it.
+#[derive(Debug)]
+pub struct ReusedInstance {
+    attached: Option<AttachedInstance>,
+    reused: bool,
+    // Keeps other processes from using the instance at the same time.
+    _lock: File,
+}
+
+impl ReusedInstance {
+    pub(crate) fn new(attached: AttachedInstance, reused: bool, lock: File) -> Self {
+        ReusedInstance {
+            attached: Some(attached),
+            reused,
+            _lock: lock,
+        }
+    }
+
+    fn attached(&self) -> &AttachedInstance {
+        self.attached.as_ref().unwrap()
+    }
+
+    /// True when the server was left running by a previous run, false when it was started
+    /// for this one.
+    #[must_use]
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Details of the instance.
+    #[must_use]
+    pub fn instance(&self) -> &DetachedInstance {
+        self.attached().instance()
+    }
+
+    /// Connection string for the database user.
+    #[must_use]
+    pub fn connection_string(&self) -> &str {
+        self.attached().connection_string()
+    }
+
+    /// Stop the server and remove its data directory, so the next run with the same key
+    /// starts a fresh instance.
+    pub fn discard(mut self) {
+        drop(self.attached.take());
+    }
+}
+
+impl Drop for ReusedInstance {
+    fn drop(&mut self) {
+        if let Some(attached) = self.attached.take() {
+            let detached = attached.detach();
+            info!(
+                "keeping instance {} (pid {}) running for the next run",
+                detached.label, detached.pid
+            );
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::sql::quote_ident;
+
+/// Role of a test identity, created with
+/// [`create_role`](crate::synchronous::ProcessGuard::create_role) to exercise row level
+/// security policies as different users.
+///
+/// The role can log in without a password, is neither superuser nor owner of any table and
+/// is granted read and write access to the tables and sequences of the `public` schema,
+/// including ones created afterwards, so policies are the only thing restricting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRole {
+    name: String,
+    bypass_rls: bool,
+    row_security: Option<bool>,
+}
+
+impl TestRole {
+    /// Role called `name`, subject to row level security.
+    #[must_use]
+    pub fn new(name: impl Into<String>) -> Self {
+        TestRole {
+            name: name.into(),
+            bypass_rls: false,
+            row_security: None,
+        }
+    }
+
+    /// Let the role bypass every row level security policy, like administrative or
+    /// replication users often do.
+    #[must_use]
+    pub fn with_bypass_rls(mut self, bypass_rls: bool) -> Self {
+        self.bypass_rls = bypass_rls;
+        self
+    }
+
+    /// Set `row_security` in the sessions of the role. When off, queries the policies would
+    /// filter fail instead, like they do for `pg_dump`.
+    #[must_use]
+    pub fn with_row_security(mut self, row_security: bool) -> Self {
+        self.row_security = Some(row_security);
+        self
+    }
+
+    /// Name of the role.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Statements creating the role, run as `grantor` which creates the tables.
+    pub(crate) fn create_sql(&self, grantor: &str) -> String {
+        let role = quote_ident(&self.name);
+        let mut sql = "CREATE ROLE ".to_string() + &role + " LOGIN NOSUPERUSER ";
+        sql.push_str(if self.bypass_rls {
+            "BYPASSRLS;\n"
+        } else {
+            "NOBYPASSRLS;\n"
+        });
+        if let Some(row_security) = self.row_security {
+            sql.push_str("ALTER ROLE ");
+            sql.push_str(&role);
+            sql.push_str(if row_security {
+                " SET row_security = on;\n"
+            } else {
+                " SET row_security = off;\n"
+            });
+        }
+        for grant in [
+            "GRANT USAGE ON SCHEMA public TO ",
+            "GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public TO ",
+            "GRANT USAGE, SELECT ON ALL SEQUENCES IN SCHEMA public TO ",
+        ] {
+            sql.push_str(grant);
+            sql.push_str(&role);
+            sql.push_str(";\n");
+        }
+        for grant in [
+            " IN SCHEMA public GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO ",
+            " IN SCHEMA public GRANT USAGE, SELECT ON SEQUENCES TO ",
+        ] {
+            sql.push_str("ALTER DEFAULT PRIVILEGES FOR ROLE ");
+            sql.push_str(&quote_ident(grantor));
+            sql.push_str(grant);
+            sql.push_str(&role);
+            sql.push_str(";\n");
+        }
+        sql
+    }
+}
+
+/// Statements enabling row level security on `table`, optionally enforcing it for the owner
+/// of the table as well.
+pub(crate) fn enable_sql(table: &str, force: bool) -> String {
+    let mut sql = "ALTER TABLE ".to_string() + table + " ENABLE ROW LEVEL SECURITY;";
+    if force {
+        sql.push_str("\nALTER TABLE ");
+        sql.push_str(table);
+        sql.push_str(" FORCE ROW LEVEL SECURITY;");
+    }
+    sql
+}
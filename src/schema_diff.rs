@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::sql::{split_records, unaligned_query_args};
+
+/// Lists every user-defined table, column, index and constraint with a definition that
+/// changes whenever the object changes in a meaningful way.
+const CATALOG_QUERY: &str = "
+WITH rel AS (
+    SELECT c.oid, c.relname, n.nspname
+    FROM pg_class c
+    JOIN pg_namespace n ON n.oid = c.relnamespace
+    WHERE n.nspname NOT IN ('pg_catalog', 'information_schema')
+      AND n.nspname NOT LIKE 'pg_toast%'
+)
+SELECT 'table', rel.nspname || '.' || rel.relname, c.relkind::text
+FROM rel JOIN pg_class c ON c.oid = rel.oid
+WHERE c.relkind IN ('r', 'p', 'v', 'm', 'f')
+UNION ALL
+SELECT 'column', rel.nspname || '.' || rel.relname || '.' || a.attname,
+       format_type(a.atttypid, a.atttypmod)
+       || CASE WHEN a.attnotnull THEN ' NOT NULL' ELSE '' END
+       || COALESCE(' DEFAULT ' || pg_get_expr(d.adbin, d.adrelid), '')
+FROM rel
+JOIN pg_attribute a ON a.attrelid = rel.oid
+LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+JOIN pg_class c ON c.oid = rel.oid
+WHERE a.attnum > 0 AND NOT a.attisdropped AND c.relkind IN ('r', 'p', 'v', 'm', 'f')
+UNION ALL
+SELECT 'index', rel.nspname || '.' || rel.relname, pg_get_indexdef(rel.oid)
+FROM rel JOIN pg_index i ON i.indexrelid = rel.oid
+UNION ALL
+SELECT 'constraint', rel.nspname || '.' || rel.relname || '.' || con.conname,
+       pg_get_constraintdef(con.oid)
+FROM rel JOIN pg_constraint con ON con.conrelid = rel.oid
+ORDER BY 1, 2;
+";
+
+/// Kind of schema object compared by [`diff_schemas`](crate::diff_schemas).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SchemaObjectKind {
+    /// Table, view, materialized view or foreign table.
+    Table,
+    /// Column of a table, including its type, nullability and default.
+    Column,
+    /// Index, compared by its `CREATE INDEX` definition.
+    Index,
+    /// Table constraint, compared by its definition.
+    Constraint,
+}
+
+impl SchemaObjectKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "table" => Some(SchemaObjectKind::Table),
+            "column" => Some(SchemaObjectKind::Column),
+            "index" => Some(SchemaObjectKind::Index),
+            "constraint" => Some(SchemaObjectKind::Constraint),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SchemaObjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            SchemaObjectKind::Table => "table",
+            SchemaObjectKind::Column => "column",
+            SchemaObjectKind::Index => "index",
+            SchemaObjectKind::Constraint => "constraint",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Schema object that only exists in one of the compared databases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObject {
+    /// Kind of object.
+    pub kind: SchemaObjectKind,
+    /// Schema qualified name of the object.
+    pub name: String,
+    /// Definition of the object as reported by the catalog.
+    pub definition: String,
+}
+
+/// Schema object that exists in both databases with differing definitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaObjectChange {
+    /// Kind of object.
+    pub kind: SchemaObjectKind,
+    /// Schema qualified name of the object.
+    pub name: String,
+    /// Definition in the left database.
+    pub left: String,
+    /// Definition in the right database.
+    pub right: String,
+}
+
+/// Structural differences between the schemas of two databases.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SchemaDiff {
+    /// Objects only present in the left database.
+    pub only_in_left: Vec<SchemaObject>,
+    /// Objects only present in the right database.
+    pub only_in_right: Vec<SchemaObject>,
+    /// Objects present in both databases with different definitions.
+    pub changed: Vec<SchemaObjectChange>,
+}
+
+impl SchemaDiff {
+    /// True when both schemas are structurally identical.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.only_in_left.is_empty() && self.only_in_right.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for object in &self.only_in_left {
+            writeln!(
+                f,
+                "- {} {}: {}",
+                object.kind, object.name, object.definition
+            )?;
+        }
+        for object in &self.only_in_right {
+            writeln!(
+                f,
+                "+ {} {}: {}",
+                object.kind, object.name, object.definition
+            )?;
+        }
+        for change in &self.changed {
+            writeln!(
+                f,
+                "~ {} {}: {} => {}",
+                change.kind, change.name, change.left, change.right
+            )?;
+        }
+        Ok(())
+    }
+}
+
+type SchemaObjects = BTreeMap<(SchemaObjectKind, String), String>;
+
+/// Arguments for `psql` to print the catalog query in a parseable form.
+pub(crate) fn catalog_query_args() -> [&'static str; 9] {
+    unaligned_query_args(CATALOG_QUERY)
+}
+
+fn parse_catalog(output: &str) -> SchemaObjects {
+    split_records(output)
+        .filter_map(|fields| {
+            let kind = SchemaObjectKind::parse(fields.first()?)?;
+            let name = (*fields.get(1)?).to_string();
+            let definition = fields.get(2).copied().unwrap_or_default().to_string();
+            Some(((kind, name), definition))
+        })
+        .collect()
+}
+
+/// Compare the outputs of the catalog query run against two databases.
+pub(crate) fn diff_catalogs(left: &str, right: &str) -> SchemaDiff {
+    let left = parse_catalog(left);
+    let mut right = parse_catalog(right);
+    let mut diff = SchemaDiff::default();
+
+    for ((kind, name), left_definition) in left {
+        match right.remove(&(kind, name.clone())) {
+            None => diff.only_in_left.push(SchemaObject {
+                kind,
+                name,
+                definition: left_definition,
+            }),
+            Some(right_definition) if right_definition != left_definition => {
+                diff.changed.push(SchemaObjectChange {
+                    kind,
+                    name,
+                    left: left_definition,
+                    right: right_definition,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    diff.only_in_right = right
+        .into_iter()
+        .map(|((kind, name), definition)| SchemaObject {
+            kind,
+            name,
+            definition,
+        })
+        .collect();
+
+    diff
+}
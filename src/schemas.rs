@@ -0,0 +1,69 @@
+use std::ffi::OsString;
+use std::path::Path;
+
+use crate::sql::quote_ident;
+
+/// Statement making `schema` the first schema searched in the sessions `role` opens to
+/// `dbname`, followed by `public`.
+fn search_path_sql(role: &str, dbname: &str, schema: &str) -> String {
+    format!(
+        "ALTER ROLE {} IN DATABASE {} SET search_path = {}, public;",
+        quote_ident(role),
+        quote_ident(dbname),
+        quote_ident(schema)
+    )
+}
+
+/// Statements granting `role` full access to `schema` and the tables and sequences in it.
+fn grant_sql(schema: &str, role: &str) -> String {
+    let schema = quote_ident(schema);
+    let role = quote_ident(role);
+    format!(
+        "GRANT USAGE ON SCHEMA {schema} TO {role};
+GRANT ALL ON ALL TABLES IN SCHEMA {schema} TO {role};
+GRANT ALL ON ALL SEQUENCES IN SCHEMA {schema} TO {role};"
+    )
+}
+
+/// Statements creating the tenant `name`, a role that can log in and a schema of the same
+/// name, which is first on the search path of the role in `dbname` and in which the role can
+/// create objects. Other tenants have no access to the schema.
+pub(crate) fn create_tenant_sql(name: &str, dbname: &str) -> String {
+    let ident = quote_ident(name);
+    format!(
+        "CREATE ROLE {ident} LOGIN NOSUPERUSER;
+CREATE SCHEMA {ident};
+GRANT USAGE, CREATE ON SCHEMA {ident} TO {ident};
+{}",
+        search_path_sql(name, dbname, name)
+    )
+}
+
+/// Arguments for `psql` to create `schema` from the DDL file `ddl` in one transaction, with
+/// objects of unqualified names created in the schema, and put it on the search path of
+/// `role` in `dbname`.
+pub(crate) fn schema_double_args(
+    schema: &str,
+    ddl: &Path,
+    role: &str,
+    dbname: &str,
+) -> Vec<OsString> {
+    let create = format!(
+        "CREATE SCHEMA {schema}; SET search_path = {schema};",
+        schema = quote_ident(schema)
+    );
+    let setup = grant_sql(schema, role) + "\n" + &search_path_sql(role, dbname, schema);
+    vec![
+        "-X".into(),
+        "-q".into(),
+        "-v".into(),
+        "ON_ERROR_STOP=1".into(),
+        "--single-transaction".into(),
+        "-c".into(),
+        create.into(),
+        "-f".into(),
+        ddl.into(),
+        "-c".into(),
+        setup.into(),
+    ]
+}
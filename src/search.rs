@@ -1,7 +1,9 @@
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 
 use glob::glob;
-use which::which;
+use which::which_in;
 
 /// Addtional file system locations to search for binaries
 /// if `initdb` and `postgres` are not in the $PATH.
@@ -13,20 +15,91 @@ const SEARCH_PATHS: [&'static str; 5] = [
     "/opt/local/lib/postgresql*",
 ];
 
+/// Homebrew kegs on Apple silicon and Intel, and Postgres.app. On macOS the $PATH often only
+/// contains the client tools of the `libpq` keg, so the server has to be found here.
+#[cfg(target_os = "macos")]
+const MACOS_SEARCH_PATHS: [&str; 5] = [
+    "/opt/homebrew/opt/postgresql@*",
+    "/opt/homebrew/opt/postgresql",
+    "/usr/local/opt/postgresql@*",
+    "/usr/local/opt/postgresql",
+    "/Applications/Postgres.app/Contents/Versions/*",
+];
+
+/// Default Nix profiles, searched after the profiles listed in `$NIX_PROFILES`.
+const NIX_PROFILES: [&str; 2] = ["/nix/var/nix/profiles/default", "/run/current-system/sw"];
+
+/// `$PATH` when the last factory was built, so binaries of a Nix or direnv shell active at that
+/// point are used even if the environment of the process changes afterwards.
+static PATH_SNAPSHOT: RwLock<Option<OsString>> = RwLock::new(None);
+
+/// Remember the current `$PATH` for resolving binaries.
+pub(crate) fn snapshot_path() {
+    *PATH_SNAPSHOT.write().unwrap() = std::env::var_os("PATH");
+}
+
+fn search_paths() -> Vec<String> {
+    let mut search_paths = Vec::new();
+    // Nix profiles, most specific first, while `$NIX_PROFILES` lists the least specific first.
+    if let Some(nix_profiles) = std::env::var_os("NIX_PROFILES") {
+        let nix_profiles = nix_profiles.to_string_lossy().into_owned();
+        search_paths.extend(nix_profiles.split_whitespace().rev().map(str::to_string));
+    } else if let Some(home) = std::env::var_os("HOME") {
+        search_paths.push(home.to_string_lossy().into_owned() + "/.nix-profile");
+    }
+    search_paths.extend(NIX_PROFILES.iter().map(ToString::to_string));
+    search_paths.extend(SEARCH_PATHS.iter().map(ToString::to_string));
+    #[cfg(target_os = "macos")]
+    search_paths.extend(MACOS_SEARCH_PATHS.iter().map(ToString::to_string));
+    search_paths
+}
+
+/// Binary `name` in a directory configured with `$PG_BIN`, or `dir` of the installation in
+/// `$PGHOME`.
+fn find_configured_command(dir: &str, name: &str) -> Option<PathBuf> {
+    let configured = std::env::var_os("PG_BIN")
+        .map(|pg_bin| PathBuf::from(pg_bin).join(name))
+        .or_else(|| {
+            std::env::var_os("PGHOME").map(|home| PathBuf::from(home).join(dir).join(name))
+        });
+    configured.filter(|path| path.is_file())
+}
+
+/// Numbers in `path`, so that paths of versioned installs sort by version, e.g.
+/// `postgresql@16` after `postgresql@9.6`.
+pub(crate) fn version_key(path: &Path) -> Vec<u32> {
+    path.to_string_lossy()
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|number| number.parse().ok())
+        .collect()
+}
+
 pub(crate) fn find_postgresql_command(dir: &str, name: &str) -> Result<PathBuf, ()> {
+    // Explicitly configured installations take precedence.
+    if let Some(path) = find_configured_command(dir, name) {
+        return Ok(path);
+    }
+
     // Use binaries from $PATH if available.
-    if let Ok(path) = which(name) {
+    let path_snapshot = PATH_SNAPSHOT
+        .read()
+        .unwrap()
+        .clone()
+        .or_else(|| std::env::var_os("PATH"));
+    let cwd = std::env::current_dir().unwrap_or_default();
+    if let Ok(path) = which_in(name, path_snapshot, cwd) {
         return Ok(path);
     };
 
-    // Check common install locations for the first available postgresql.
-    for path in SEARCH_PATHS {
-        for entry in
-            glob(&(path.to_string() + "/" + dir + "/" + name)).expect("Failed to read glob pattern")
-        {
-            if let Ok(path) = entry {
-                return Ok(path);
-            }
+    // Check Nix profiles and common install locations for the newest available postgresql.
+    for path in search_paths() {
+        let mut candidates: Vec<PathBuf> = glob(&(path + "/" + dir + "/" + name))
+            .expect("Failed to read glob pattern")
+            .filter_map(Result::ok)
+            .collect();
+        candidates.sort_by_key(|candidate| version_key(candidate));
+        if let Some(path) = candidates.pop() {
+            return Ok(path);
         }
     }
     Err(())
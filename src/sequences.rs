@@ -0,0 +1,57 @@
+use crate::sql::unaligned_query_args;
+
+/// Setting holding the value sequences created in an instance start at.
+const SEQUENCE_START_SETTING: &str = "tmp_postgrust.sequence_start";
+
+/// Create an event trigger making every sequence created afterwards, including those of
+/// `serial` and identity columns, start at [`SEQUENCE_START_SETTING`]. The trigger function runs
+/// as the superuser, so sequences of any role are changed.
+pub(crate) const INSTALL_SQL: &str = "
+CREATE SCHEMA tmp_postgrust_sequences;
+CREATE FUNCTION tmp_postgrust_sequences.set_start() RETURNS event_trigger
+LANGUAGE plpgsql SECURITY DEFINER SET search_path = pg_catalog
+AS $$
+DECLARE
+    command record;
+BEGIN
+    FOR command IN SELECT objid FROM pg_event_trigger_ddl_commands()
+        WHERE object_type = 'sequence' AND command_tag = 'CREATE SEQUENCE'
+    LOOP
+        PERFORM setval(
+            command.objid::regclass,
+            current_setting('tmp_postgrust.sequence_start')::bigint,
+            false
+        );
+    END LOOP;
+END
+$$;
+CREATE EVENT TRIGGER tmp_postgrust_sequence_start ON ddl_command_end
+EXECUTE FUNCTION tmp_postgrust_sequences.set_start();
+";
+
+/// Restarts every sequence outside of the system schemas and those of `tmp-postgrust`, at the
+/// start value of the instance if one is configured and at the start value of the sequence
+/// otherwise, and reports how many were restarted.
+const RESET_QUERY: &str = "
+SELECT count(setval(
+    format('%I.%I', schemaname, sequencename)::regclass,
+    COALESCE(
+        NULLIF(current_setting('tmp_postgrust.sequence_start', true), '')::bigint,
+        start_value
+    ),
+    false
+))
+FROM pg_sequences
+WHERE schemaname NOT IN ('pg_catalog', 'information_schema')
+  AND schemaname NOT LIKE 'tmp\\_postgrust\\_%';
+";
+
+/// Line of `postgresql.conf` making sequences start at `start`.
+pub(crate) fn start_config(start: i64) -> String {
+    format!("{SEQUENCE_START_SETTING} = {start}\n")
+}
+
+/// Arguments for `psql` to restart the sequences and print how many were restarted.
+pub(crate) fn reset_query_args() -> [&'static str; 9] {
+    unaligned_query_args(RESET_QUERY)
+}
@@ -0,0 +1,70 @@
+use std::fmt;
+
+use crate::errors::{ProcessCapture, TmpPostgrustError};
+
+/// Why the server rejected a setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidSettingReason {
+    /// The server does not know a setting of this name.
+    Unknown,
+    /// The value is malformed or outside the valid range of the setting.
+    InvalidValue,
+}
+
+/// Setting of the configuration of instances rejected by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSetting {
+    /// Name of the setting as written in the configuration.
+    pub name: String,
+    /// Why the setting was rejected.
+    pub reason: InvalidSettingReason,
+    /// Message logged by the server.
+    pub message: String,
+}
+
+impl fmt::Display for InvalidSetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Name quoted after `marker` in a log line of the server.
+fn quoted_after<'a>(line: &'a str, marker: &str) -> Option<&'a str> {
+    let (_, rest) = line.split_once(marker)?;
+    rest.split('"').next()
+}
+
+/// Parse the settings rejected in the log output of `postgres -C`.
+pub(crate) fn parse_invalid_settings(stderr: &str) -> Vec<InvalidSetting> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let message = line.split_once(":  ").map_or(line, |(_, message)| message);
+            let (name, reason) = if let Some(name) =
+                quoted_after(message, "unrecognized configuration parameter \"")
+            {
+                (name, InvalidSettingReason::Unknown)
+            } else {
+                (
+                    quoted_after(message, "parameter \"")?,
+                    InvalidSettingReason::InvalidValue,
+                )
+            };
+            Some(InvalidSetting {
+                name: name.to_string(),
+                reason,
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Error for a failed check of the configuration, listing the rejected settings.
+pub(crate) fn check_failed(capture: ProcessCapture) -> TmpPostgrustError {
+    let invalid = parse_invalid_settings(&capture.stderr);
+    if invalid.is_empty() {
+        TmpPostgrustError::CheckSettingsFailed(capture)
+    } else {
+        TmpPostgrustError::InvalidSettings(invalid)
+    }
+}
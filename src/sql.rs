@@ -0,0 +1,38 @@
+/// Separates fields of a row in `psql` unaligned output.
+const FIELD_SEPARATOR: &str = "\x1f";
+/// Separates rows in `psql` unaligned output.
+const RECORD_SEPARATOR: &str = "\x1e";
+
+/// Quote `identifier` for use as an SQL identifier, e.g. a database or role name.
+pub(crate) fn quote_ident(identifier: &str) -> String {
+    "\"".to_string() + &identifier.replace('"', "\"\"") + "\""
+}
+
+/// Quote `value` as an SQL string literal.
+pub(crate) fn quote_literal(value: &str) -> String {
+    "'".to_string() + &value.replace('\'', "''") + "'"
+}
+
+/// Arguments for `psql` to print the rows of `query` in a form parsed by [`split_records`].
+pub(crate) fn unaligned_query_args(query: &str) -> [&str; 9] {
+    [
+        "-X",
+        "-A",
+        "-t",
+        "-F",
+        FIELD_SEPARATOR,
+        "-R",
+        RECORD_SEPARATOR,
+        "-c",
+        query,
+    ]
+}
+
+/// Split the output of a query run with [`unaligned_query_args`] into rows of fields.
+pub(crate) fn split_records(output: &str) -> impl Iterator<Item = Vec<&str>> {
+    output
+        .split(RECORD_SEPARATOR)
+        .map(|record| record.trim_matches('\n'))
+        .filter(|record| !record.is_empty())
+        .map(|record| record.split(FIELD_SEPARATOR).collect())
+}
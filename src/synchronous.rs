@@ -1,47 +1,143 @@
-use std::convert::TryInto;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Write as _;
 use std::io::BufReader;
 use std::io::Lines;
-use std::path::Path;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
 use std::process::Child;
 use std::process::ChildStderr;
 use std::process::ChildStdout;
 use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Output;
 use std::process::Stdio;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use nix::sys::signal;
-use nix::sys::signal::Signal;
-use nix::unistd::Pid;
-use tempdir::TempDir;
-use tracing::{debug, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
+use crate::activity::{self, Backend, ConnectionLeakCheck};
+use crate::artifacts::{self, ArtifactSink};
+use crate::audit::{self, AuditEvent};
+use crate::auth::{AuthContext, SUPERUSER};
+use crate::background::{self, BackgroundActivity};
+use crate::budget::{Budget, BudgetScope};
+use crate::builder::{DatabaseTemplate, Verbosity};
+use crate::checksums::{self, ChecksumReport};
+use crate::connection::{self, ConnectionInfo, TCP_HOST};
+use crate::copy::{copy_native, copy_sources, CopyStrategy};
+use crate::database::{CreatedDatabases, DatabaseGuard, SharedServer};
+use crate::ddl_audit::{self, DdlCommand};
+use crate::detach::DetachedInstance;
+use crate::dirs::{InstanceDir, SocketLink};
 use crate::errors::{ProcessCapture, TmpPostgrustError, TmpPostgrustResult};
+use crate::events::{EventBus, LifecycleEvent};
+use crate::explain::{self, SlowQueryPlan};
+use crate::fake_time;
+use crate::golden;
+#[cfg(unix)]
+use crate::latency::LatencyShim;
+use crate::limiter::InstancePermit;
+use crate::metadata::{self, InstanceMetadata};
+use crate::readiness;
+use crate::record::{self, QueryFingerprints, RecordedStatement};
+use crate::registry::RegistryEntry;
+use crate::rls::{self, TestRole};
+use crate::schemas;
 use crate::search::find_postgresql_command;
+use crate::sequences;
+use crate::settings;
+use crate::sql;
+use crate::terminate::ProcessTerminator;
+use crate::throttle::IoCgroup;
+use crate::usage::ResourceUsage;
+use crate::wait::{self, Backoff};
+use crate::wal::{self, WalLsn};
+use crate::workers;
+use crate::workspace::WorkspaceSlot;
+use crate::{keep_crashed_data_directory, ServerLaunch};
+
+/// Program and arguments of `command` for logs and errors, leaving out its environment, which
+/// carries passwords in `PGPASSWORD`.
+pub(crate) fn command_line(command: &Command) -> String {
+    let mut line = command.get_program().to_string_lossy().into_owned();
+    for arg in command.get_args() {
+        let _ = write!(line, " {}", arg.to_string_lossy());
+    }
+    line
+}
 
 #[instrument(skip(command, fail))]
-fn exec_process(
+pub(crate) fn exec_process(
     command: &mut Command,
+    verbosity: Verbosity,
     fail: impl FnOnce(ProcessCapture) -> TmpPostgrustError,
-) -> TmpPostgrustResult<()> {
-    debug!("running command: {:?}", command);
+) -> TmpPostgrustResult<ProcessCapture> {
+    if verbosity >= Verbosity::Commands {
+        debug!("running command: {}", command_line(command));
+    }
 
     let output = command
         .output()
         .map_err(|err| TmpPostgrustError::ExecSubprocessFailed {
             source: err,
-            command: format!("{:?}", command),
+            command: command_line(command),
         })?;
+    capture_output(&output, verbosity, fail)
+}
 
+/// Run `command` like [`exec_process`] with `input` written to its standard input, for
+/// statements containing passwords, which must not appear in the arguments.
+#[instrument(skip(command, input, fail))]
+pub(crate) fn exec_process_with_input(
+    command: &mut Command,
+    input: &str,
+    verbosity: Verbosity,
+    fail: impl FnOnce(ProcessCapture) -> TmpPostgrustError,
+) -> TmpPostgrustResult<ProcessCapture> {
+    if verbosity >= Verbosity::Commands {
+        debug!("running command: {}", command_line(command));
+    }
+
+    let output = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            // Dropping the handle closes the standard input of the child.
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input.as_bytes())?;
+            }
+            child.wait_with_output()
+        })
+        .map_err(|err| TmpPostgrustError::ExecSubprocessFailed {
+            source: err,
+            command: command_line(command),
+        })?;
+    capture_output(&output, verbosity, fail)
+}
+
+/// Capture the output of a finished tool, failing with `fail` if it did not succeed.
+pub(crate) fn capture_output(
+    output: &Output,
+    verbosity: Verbosity,
+    fail: impl FnOnce(ProcessCapture) -> TmpPostgrustError,
+) -> TmpPostgrustResult<ProcessCapture> {
+    let capture = ProcessCapture {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    };
     if output.status.success() {
-        for line in String::from_utf8(output.stdout).unwrap().lines() {
-            debug!("{}", line);
+        if verbosity >= Verbosity::Verbose {
+            for line in capture.stdout.lines() {
+                debug!("{}", line);
+            }
         }
-        Ok(())
+        Ok(capture)
     } else {
-        Err(fail(ProcessCapture {
-            stdout: String::from_utf8(output.stdout).unwrap(),
-            stderr: String::from_utf8(output.stderr).unwrap(),
-        }))
+        Err(fail(capture))
     }
 }
 
@@ -49,11 +145,13 @@ fn exec_process(
 pub(crate) fn start_postgres_subprocess(
     data_directory: &'_ Path,
     port: u32,
+    launch: &ServerLaunch,
 ) -> TmpPostgrustResult<Child> {
     let postgres_path =
         find_postgresql_command("bin", "postgres").expect("failed to find postgres");
 
-    Command::new(postgres_path)
+    let mut command = launch.command(&postgres_path);
+    command
         .env("PGDATA", data_directory.to_str().unwrap())
         .arg("-p")
         .arg(port.to_string())
@@ -63,95 +161,310 @@ pub(crate) fn start_postgres_subprocess(
         .map_err(TmpPostgrustError::SpawnSubprocessFailed)
 }
 
+/// Server process that is killed when dropped before it is handed to a [`ProcessGuard`], so
+/// a failing startup does not leave it running on its port and directories.
+pub(crate) struct StartingServer(Option<Child>);
+
+impl StartingServer {
+    pub(crate) fn new(postgres_process: Child) -> Self {
+        StartingServer(Some(postgres_process))
+    }
+
+    pub(crate) fn process(&mut self) -> &mut Child {
+        self.0.as_mut().expect("process is only taken once started")
+    }
+
+    /// The process of the started server, to be stopped by its guard from now on.
+    pub(crate) fn started(mut self) -> Child {
+        self.0.take().expect("process is only taken once started")
+    }
+}
+
+impl Drop for StartingServer {
+    fn drop(&mut self) {
+        if let Some(mut postgres_process) = self.0.take() {
+            let _ = postgres_process.kill();
+            let _ = postgres_process.wait();
+        }
+    }
+}
+
+/// Server that accepts connections, with what it writes, before a guard takes it over.
+pub(crate) struct StartedServer {
+    pub(crate) process: StartingServer,
+    pub(crate) registration: RegistryEntry,
+    pub(crate) stdout_reader: Lines<BufReader<ChildStdout>>,
+    pub(crate) stderr_reader: Lines<BufReader<ChildStderr>>,
+    // Port the server listens on, another one than allocated if that was taken
+    pub(crate) port: u32,
+}
+
+/// Wait until `pg_isready` reports that the server listening on `port` accepts connections on
+/// its socket in `socket_dir`, failing with the fatal error the server logged to `stderr` if it
+/// exits first.
+pub(crate) fn wait_until_ready(
+    postgres_process: &mut Child,
+    stderr_reader: &mut Lines<BufReader<ChildStderr>>,
+    socket_dir: &Path,
+    port: u32,
+    timeout: Duration,
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<()> {
+    let pg_isready = find_postgresql_command("bin", "pg_isready")
+        .map_err(|()| TmpPostgrustError::FindBinaryFailed("pg_isready".to_string()))?;
+    let started = Instant::now();
+    loop {
+        let output = Command::new(&pg_isready)
+            .args(readiness::pg_isready_args(socket_dir, port))
+            .output()
+            .map_err(TmpPostgrustError::SpawnSubprocessFailed)?;
+        if readiness::is_ready(output.status) {
+            info!("temporary database system is ready to accept connections");
+            return Ok(());
+        }
+        if postgres_process
+            .try_wait()
+            .map_err(TmpPostgrustError::SpawnSubprocessFailed)?
+            .is_some()
+        {
+            let stderr: Vec<String> = stderr_reader.map_while(Result::ok).collect();
+            return Err(readiness::startup_failure(&stderr, verbosity));
+        }
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(TmpPostgrustError::NotAcceptingConnections(
+                crate::socket_path(socket_dir, port).display().to_string(),
+            ));
+        }
+        std::thread::sleep(readiness::POLL_INTERVAL.min(remaining));
+    }
+}
+
+/// How long a server is given to shut down when its guard is dropped before it is killed.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[instrument]
-pub(crate) fn exec_init_db(data_directory: &'_ Path) -> TmpPostgrustResult<()> {
+pub(crate) fn exec_init_db(
+    data_directory: &'_ Path,
+    extra_args: &[String],
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<()> {
     let initdb_path = find_postgresql_command("bin", "initdb").expect("failed to find initdb");
 
     debug!("Initializing database in: {:?}", data_directory);
     exec_process(
         &mut Command::new(initdb_path)
             .env("PGDATA", data_directory.to_str().unwrap())
-            .arg("--username=postgres"),
+            .arg("-U")
+            .arg(SUPERUSER)
+            .args(extra_args),
+        verbosity,
         TmpPostgrustError::InitDBFailed,
-    )
+    )?;
+    Ok(())
 }
 
+/// Check that the server accepts every setting of `config_file`.
 #[instrument]
-pub(crate) fn exec_copy_dir(src_dir: &'_ Path, dst_dir: &'_ Path) -> TmpPostgrustResult<()> {
-    for read_dir in src_dir
-        .read_dir()
-        .map_err(TmpPostgrustError::CopyCachedInitDBFailedFileNotFound)?
-    {
-        let mut cmd = Command::new("cp");
-        #[cfg(target_os = "macos")]
-        cmd.arg("-R")
+pub(crate) fn exec_check_config(
+    data_directory: &'_ Path,
+    config_file: &'_ Path,
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<()> {
+    let postgres_path =
+        find_postgresql_command("bin", "postgres").expect("failed to find postgres");
+
+    exec_process(
+        Command::new(postgres_path)
+            .arg("-D")
+            .arg(data_directory)
             .arg("-c")
-            .arg(
-                read_dir
-                    .map_err(TmpPostgrustError::CopyCachedInitDBFailedFileNotFound)?
-                    .path(),
-            )
-            .arg(dst_dir);
-        #[cfg(not(target_os = "macos"))]
-        cmd.arg("-R")
-            .arg("--reflink=auto")
-            .arg(
-                read_dir
-                    .map_err(TmpPostgrustError::CopyCachedInitDBFailedFileNotFound)?
-                    .path(),
-            )
-            .arg(dst_dir);
-        exec_process(&mut cmd, TmpPostgrustError::CopyCachedInitDBFailed)?;
-    }
+            .arg(format!("config_file={}", config_file.display()))
+            .arg("-C")
+            .arg("shared_buffers"),
+        verbosity,
+        settings::check_failed,
+    )?;
     Ok(())
 }
 
 #[instrument]
+pub(crate) fn exec_copy_dir(
+    src_dir: &'_ Path,
+    dst_dir: &'_ Path,
+    strategy: CopyStrategy,
+    excludes: &[OsString],
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<()> {
+    let sources = copy_sources(src_dir, excludes)
+        .map_err(TmpPostgrustError::CopyCachedInitDBFailedFileNotFound)?;
+    let Some(cp_args) = strategy.cp_args() else {
+        return copy_native(&sources, dst_dir)
+            .map_err(TmpPostgrustError::CopyCachedInitDBFailedFileNotFound);
+    };
+    // A single `cp` copies every entry, which avoids spawning a process per entry.
+    if sources.is_empty() {
+        return Ok(());
+    }
+    let mut cmd = Command::new("cp");
+    cmd.args(cp_args).args(sources).arg(dst_dir);
+    exec_process(
+        &mut cmd,
+        verbosity,
+        TmpPostgrustError::CopyCachedInitDBFailed,
+    )?;
+    Ok(())
+}
+
+#[instrument(skip(auth))]
 pub(crate) fn exec_create_db(
-    socket: &'_ Path,
-    port: u32,
-    owner: &'_ str,
+    auth: &'_ AuthContext,
     dbname: &'_ str,
+    owner: &'_ str,
+    template: DatabaseTemplate,
+    verbosity: Verbosity,
 ) -> TmpPostgrustResult<()> {
+    let createdb_path =
+        find_postgresql_command("bin", "createdb").expect("failed to find createdb");
+
     exec_process(
-        &mut Command::new("createdb")
-            .arg("-h")
-            .arg(socket)
-            .arg("-p")
-            .arg(port.to_string())
-            .arg("-U")
-            .arg("postgres")
+        &mut Command::new(createdb_path)
+            .args(auth.args())
+            .envs(auth.envs())
             .arg("-O")
             .arg(owner)
+            .arg("-T")
+            .arg(template.name())
             .arg("--echo")
             .arg(dbname),
+        verbosity,
         TmpPostgrustError::CreateDBFailed,
-    )
+    )?;
+    Ok(())
 }
 
-#[instrument]
+#[instrument(skip(auth))]
 pub(crate) fn exec_create_user(
-    socket: &'_ Path,
-    port: u32,
+    auth: &'_ AuthContext,
     username: &'_ str,
+    superuser: bool,
+    verbosity: Verbosity,
 ) -> TmpPostgrustResult<()> {
+    let createuser_path =
+        find_postgresql_command("bin", "createuser").expect("failed to find createuser");
+
     exec_process(
-        &mut Command::new("createuser")
-            .arg("-h")
-            .arg(socket)
-            .arg("-p")
-            .arg(port.to_string())
-            .arg("-U")
-            .arg("postgres")
-            .arg("--superuser")
+        &mut Command::new(createuser_path)
+            .args(auth.args())
+            .envs(auth.envs())
+            .arg(if superuser {
+                "--superuser"
+            } else {
+                "--no-superuser"
+            })
             .arg("--echo")
             .arg(username),
-        TmpPostgrustError::CreateDBFailed,
-    )
+        verbosity,
+        TmpPostgrustError::CreateUserFailed,
+    )?;
+    Ok(())
+}
+
+/// Run `sql` with `psql` against `dbname`, stopping at the first error.
+#[instrument(skip(auth, sql))]
+pub(crate) fn exec_psql(
+    auth: &'_ AuthContext,
+    dbname: &'_ str,
+    sql: &'_ str,
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<()> {
+    let psql_path = find_postgresql_command("bin", "psql")
+        .map_err(|()| TmpPostgrustError::FindBinaryFailed("psql".to_string()))?;
+
+    exec_process(
+        Command::new(psql_path)
+            .args(auth.args())
+            .envs(auth.envs())
+            .args(["-X", "-v", "ON_ERROR_STOP=1", "-d", dbname, "-c", sql]),
+        verbosity,
+        TmpPostgrustError::PgToolFailed,
+    )?;
+    Ok(())
+}
+
+/// Run `sql`, which contains a password, with `psql` against `dbname` through its standard
+/// input, so the statement does not show up in the arguments of the process or in logs.
+#[instrument(skip(auth, sql))]
+pub(crate) fn exec_psql_secret(
+    auth: &'_ AuthContext,
+    dbname: &'_ str,
+    sql: &'_ str,
+    verbosity: Verbosity,
+) -> TmpPostgrustResult<()> {
+    let psql_path = find_postgresql_command("bin", "psql")
+        .map_err(|()| TmpPostgrustError::FindBinaryFailed("psql".to_string()))?;
+
+    exec_process_with_input(
+        Command::new(psql_path)
+            .args(auth.args())
+            .envs(auth.envs())
+            .args(["-X", "-v", "ON_ERROR_STOP=1", "-d", dbname]),
+        sql,
+        verbosity,
+        TmpPostgrustError::PgToolFailed,
+    )?;
+    Ok(())
+}
+
+/// Client connections left open to an instance, described for
+/// [`ConnectionLeakCheck`] or `None` when there are none or the check is off.
+pub(crate) fn find_connection_leaks(
+    auth: &AuthContext,
+    dbname: &str,
+    label: &str,
+    check: ConnectionLeakCheck,
+    verbosity: Verbosity,
+) -> Option<String> {
+    if check == ConnectionLeakCheck::Off {
+        return None;
+    }
+    let output = find_postgresql_command("bin", "psql")
+        .map_err(|()| TmpPostgrustError::FindBinaryFailed("psql".to_string()))
+        .and_then(|psql| {
+            exec_process(
+                Command::new(psql)
+                    .args(activity::activity_query_args())
+                    .envs(auth.libpq_envs(dbname)),
+                verbosity,
+                TmpPostgrustError::PgToolFailed,
+            )
+        });
+    match output {
+        Ok(output) => activity::describe_leaks(label, &activity::parse_activity(&output.stdout)),
+        Err(err) => {
+            warn!("failed to check for leaked connections: {}", err);
+            None
+        }
+    }
+}
+
+/// Report the leaked connections found by [`find_connection_leaks`] once the instance
+/// stopped.
+pub(crate) fn report_connection_leaks(check: ConnectionLeakCheck, leaks: Option<String>) {
+    let Some(leaks) = leaks else {
+        return;
+    };
+    assert!(
+        check != ConnectionLeakCheck::Strict || std::thread::panicking(),
+        "{}",
+        leaks
+    );
+    warn!("{}", leaks);
 }
 
 /// ProcessGuard represents a postgresql process that is running in the background.
 /// once the guard is dropped the process will be killed.
+#[allow(clippy::struct_excessive_bools)]
 pub struct ProcessGuard {
     /// Allows users to read stdout by line for debugging.
     pub stdout_reader: Option<Lines<BufReader<ChildStdout>>>,
@@ -160,24 +473,1144 @@ pub struct ProcessGuard {
     /// Connection string for connecting to the temporary postgresql instance.
     pub connection_string: String,
 
-    // Signal that the postgres process should be killed.
-    pub(crate) postgres_process: Child,
+    // Connection target and credentials of the application user.
+    pub(crate) auth: AuthContext,
+    // Database created for the application user.
+    pub(crate) dbname: String,
+    // Label identifying the test that created the instance.
+    pub(crate) label: String,
+    // Databases created with `create_database` that were not dropped yet.
+    pub(crate) created_databases: Arc<CreatedDatabases>,
+    // How much output of client tools is logged.
+    pub(crate) verbosity: Verbosity,
+    // Check for open client connections when stopping.
+    pub(crate) connection_leak_check: ConnectionLeakCheck,
+    // The server also listens on TCP on the loopback interface.
+    pub(crate) tcp: bool,
+    // Receive the artifacts collected when dropped while panicking.
+    pub(crate) artifact_sinks: Vec<Arc<dyn ArtifactSink>>,
+    // Signal that the postgres process should be killed, none for a database on the shared
+    // server of the factory.
+    pub(crate) postgres_process: Option<Child>,
+    // Leave the server running when dropped.
+    pub(crate) persisted: bool,
+    // The server was already stopped by `stop`.
+    pub(crate) stopped: bool,
+    // Keep the data directory with its core files if the server crashed.
+    pub(crate) keep_on_crash: bool,
+    // Lifecycle events of the factory that created the instance.
+    pub(crate) events: Arc<EventBus>,
+    // Limit the total concurrent instances.
+    pub(crate) _instance_permit: Option<InstancePermit>,
+    // Slot counting against the instance limit of a shared workspace.
+    pub(crate) _workspace_slot: Option<WorkspaceSlot>,
+    // Keep the server listed with its factory while it is running.
+    pub(crate) registration: Option<RegistryEntry>,
     // Prevent the data directory from being dropped while
     // the process is running.
-    pub(crate) _data_directory: TempDir,
+    pub(crate) data_directory: Arc<InstanceDir>,
     // Prevent socket directory from being dropped while
     // the process is running.
-    pub(crate) _socket_dir: Arc<TempDir>,
+    pub(crate) socket_dir: Arc<InstanceDir>,
+    // Short symlink to the socket directory advertised in the connection details.
+    pub(crate) socket_link: Option<SocketLink>,
+    // Directory of the factory to create further temporary directories in.
+    pub(crate) temp_root: PathBuf,
+    // Cgroup limiting the I/O of the server, removed once it stopped.
+    pub(crate) io_cgroup: Option<IoCgroup>,
+    // Database of an instance on the shared server of the factory, dropped instead of
+    // stopping a server.
+    pub(crate) shared_database: Option<DatabaseGuard>,
+}
+
+impl ProcessGuard {
+    /// Instance whose database `dbname` was cloned on the shared `server`.
+    pub(crate) fn on_shared_server(
+        server: &Arc<SharedServer>,
+        dbname: String,
+        label: &str,
+    ) -> Self {
+        let shared = &server.guard;
+        let auth = shared.auth.clone();
+        ProcessGuard {
+            stdout_reader: None,
+            stderr_reader: None,
+            connection_string: crate::instance_connection_string(&auth, &dbname, &auth.host),
+            auth: auth.clone(),
+            dbname: dbname.clone(),
+            label: label.to_string(),
+            created_databases: Arc::default(),
+            verbosity: shared.verbosity,
+            connection_leak_check: shared.connection_leak_check,
+            tcp: shared.tcp,
+            artifact_sinks: shared.artifact_sinks.clone(),
+            postgres_process: None,
+            persisted: false,
+            stopped: false,
+            keep_on_crash: false,
+            events: Arc::clone(&shared.events),
+            _instance_permit: None,
+            _workspace_slot: None,
+            registration: None,
+            data_directory: Arc::clone(&shared.data_directory),
+            socket_dir: Arc::clone(&shared.socket_dir),
+            socket_link: None,
+            temp_root: shared.temp_root.clone(),
+            io_cgroup: None,
+            shared_database: Some(DatabaseGuard::new(
+                auth,
+                dbname,
+                shared.verbosity,
+                Some(Arc::clone(server)),
+                None,
+            )),
+        }
+    }
+
+    /// Label of the instance, by default the name of the thread that created it.
+    #[must_use]
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Run a postgresql client tool such as `psql` or `pg_dump` against this instance.
+    ///
+    /// The binary is resolved the same way as `initdb` and `postgres`, and the host, port, user
+    /// and database are provided through the standard `PG*` environment variables. Returns the
+    /// captured output of the tool, with invalid UTF-8 replaced; have tools with binary output
+    /// such as `pg_dump -Fc` write to a file instead.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`FindBinaryFailed`](TmpPostgrustError::FindBinaryFailed) if `tool` cannot be
+    /// found, with [`ExecSubprocessFailed`](TmpPostgrustError::ExecSubprocessFailed) if it cannot
+    /// be run and with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if it exits
+    /// unsuccessfully.
+    #[instrument(skip(self, args))]
+    pub fn run_pg_tool<I, S>(&self, tool: &str, args: I) -> TmpPostgrustResult<ProcessCapture>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let tool_path = find_postgresql_command("bin", tool)
+            .map_err(|()| TmpPostgrustError::FindBinaryFailed(tool.to_string()))?;
+
+        exec_process(
+            Command::new(tool_path)
+                .args(args)
+                .envs(self.auth.libpq_envs(&self.dbname)),
+            self.verbosity,
+            TmpPostgrustError::PgToolFailed,
+        )
+    }
+
+    /// Connect to the database with `tokio-postgres`, driving the connection on a background
+    /// task.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if the connection cannot be
+    /// established.
+    #[cfg(feature = "client")]
+    pub async fn client(&self) -> TmpPostgrustResult<tokio_postgres::Client> {
+        crate::client::connect(&self.connection_string).await
+    }
+
+    /// Run `sql`, which has to return exactly one row, and return the value of its first column.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if connecting or running `sql`
+    /// fails, or if it does not return exactly one row.
+    #[cfg(feature = "client")]
+    pub async fn query_scalar<T>(&self, sql: &str) -> TmpPostgrustResult<T>
+    where
+        T: for<'a> tokio_postgres::types::FromSql<'a>,
+    {
+        crate::client::query_scalar(&self.connection_string, sql).await
+    }
+
+    /// Run `sql` and panic unless it returns `expected_rows`. Values are compared in the text
+    /// format of postgresql, with `NULL` written as `"NULL"`.
+    #[cfg(feature = "client")]
+    pub async fn assert_query_eq(&self, sql: &str, expected_rows: &[&[&str]]) {
+        crate::client::assert_query_eq(&self.connection_string, sql, expected_rows).await;
+    }
+
+    /// Run `f` inside a transaction that is always rolled back, so tests that never need to
+    /// commit can share one instance without seeing each other's changes. A panic in `f` drops
+    /// the connection, which rolls the transaction back as well.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if connecting or starting or
+    /// rolling back the transaction fails.
+    #[cfg(feature = "client")]
+    pub async fn with_rollback<F, Fut, T>(&self, f: F) -> TmpPostgrustResult<T>
+    where
+        F: FnOnce(crate::client::RollbackScope) -> Fut,
+        Fut: std::future::Future<Output = T>,
+    {
+        crate::client::with_rollback(&self.connection_string, f).await
+    }
+
+    /// Run `f` on a new connection with the setting `name`, e.g. `enable_seqscan`, set to
+    /// `value` for its session, and reset it once `f` finished. Other connections keep their
+    /// settings, so tests tweaking planner settings do not affect each other. A panic in `f`
+    /// drops the connection and with it the setting.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if connecting or setting or
+    /// resetting `name` fails, e.g. for an unknown setting.
+    #[cfg(feature = "client")]
+    pub async fn with_setting<F, Fut, T>(
+        &self,
+        name: &str,
+        value: &str,
+        f: F,
+    ) -> TmpPostgrustResult<T>
+    where
+        F: FnOnce(std::sync::Arc<tokio_postgres::Client>) -> Fut,
+        Fut: std::future::Future<Output = T>,
+    {
+        crate::client::with_setting(&self.connection_string, name, value, f).await
+    }
+
+    /// Run `f` on `n_connections` concurrent connections, for testing locking and contention.
+    /// `f` receives the index of its connection, and the errors it returns are collected with
+    /// that index. At most 64 connections are open at the same time.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if a connection cannot be
+    /// established.
+    #[cfg(feature = "client")]
+    pub async fn stress<F, Fut, E>(
+        &self,
+        n_connections: usize,
+        f: F,
+    ) -> TmpPostgrustResult<Vec<(usize, E)>>
+    where
+        F: Fn(usize, tokio_postgres::Client) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), E>> + Send + 'static,
+        E: Send + 'static,
+    {
+        crate::client::stress(&self.connection_string, n_connections, f).await
+    }
+
+    /// Connect to the database with `tokio-postgres` as `role`, e.g. a
+    /// [`TestRole`](crate::rls::TestRole), to see the rows its row level security policies
+    /// allow.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientFailed`](TmpPostgrustError::ClientFailed) if the connection cannot be
+    /// established, e.g. because `role` cannot log in.
+    #[cfg(feature = "client")]
+    pub async fn connect_as(&self, role: &str) -> TmpPostgrustResult<tokio_postgres::Client> {
+        let info = ConnectionInfo {
+            user: role.to_string(),
+            password: None,
+            ..self.connection_info()
+        };
+        crate::client::connect(&info.connection_string()).await
+    }
+
+    /// Create `role` in the instance. Roles other than the database user cannot connect to
+    /// instances of a factory with
+    /// [`with_socket_hardening`](crate::builder::TmpPostgrustFactoryBuilder::with_socket_hardening).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if the role cannot be created,
+    /// e.g. because it already exists.
+    pub fn create_role(&self, role: &TestRole) -> TmpPostgrustResult<()> {
+        exec_psql(
+            &self.auth.as_superuser(),
+            &self.dbname,
+            &role.create_sql(&self.auth.user),
+            self.verbosity,
+        )
+    }
+
+    /// Enable row level security on `table`, an SQL name such as `public.documents`. With
+    /// `force` the policies apply to the owner of the table too, which is the database user
+    /// for tables created by tests.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if row level security cannot be
+    /// enabled, e.g. because `table` does not exist.
+    pub fn enable_row_level_security(&self, table: &str, force: bool) -> TmpPostgrustResult<()> {
+        exec_psql(
+            &self.auth,
+            &self.dbname,
+            &rls::enable_sql(table, force),
+            self.verbosity,
+        )
+    }
+
+    /// Create `schema` from the DDL file `ddl` as a test double of the schema of an external
+    /// system, e.g. tables replicated from it. Objects with unqualified names in the file are
+    /// created in `schema`, which is put ahead of `public` on the search path of `role` in the
+    /// database and to which `role` is granted full access. The file runs in a single
+    /// transaction, so nothing is created when it fails.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if `ddl` cannot be read or one
+    /// of its statements fails.
+    pub fn create_schema_double(
+        &self,
+        schema: &str,
+        ddl: impl AsRef<Path>,
+        role: &str,
+    ) -> TmpPostgrustResult<()> {
+        self.run_pg_tool(
+            "psql",
+            schemas::schema_double_args(schema, ddl.as_ref(), role, &self.dbname),
+        )?;
+        Ok(())
+    }
+
+    /// Create the empty database `name` owned by the database user on this instance, so one
+    /// server serves several isolated databases. The returned guard has a connection string of
+    /// its own and drops the database when it is dropped. Databases still existing when this
+    /// guard stops, persists or detaches the instance are dropped by it, newest first.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if the database cannot be
+    /// created, e.g. because it already exists.
+    pub fn create_database(&self, name: &str) -> TmpPostgrustResult<DatabaseGuard> {
+        exec_psql(
+            &self.auth.as_superuser(),
+            "postgres",
+            &connection::create_database_sql(&self.auth.user, name),
+            self.verbosity,
+        )?;
+        self.created_databases.track(name);
+        Ok(DatabaseGuard::new(
+            self.auth.clone(),
+            name.to_string(),
+            self.verbosity,
+            None,
+            Some(Arc::clone(&self.created_databases)),
+        ))
+    }
+
+    /// Create the tenant `name` of a multi-tenant-by-schema application: a schema and a role
+    /// of that name, which can create objects in the schema and has it first on its search
+    /// path, ahead of `public`. Connect as the tenant with
+    /// [`connection_string_for_schema`](Self::connection_string_for_schema).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if the schema or the role
+    /// cannot be created, e.g. because one of them already exists.
+    pub fn create_tenant_schema(&self, name: &str) -> TmpPostgrustResult<()> {
+        exec_psql(
+            &self.auth.as_superuser(),
+            &self.dbname,
+            &schemas::create_tenant_sql(name, &self.dbname),
+            self.verbosity,
+        )
+    }
+
+    /// Connection string for connecting as the tenant `name` created with
+    /// [`create_tenant_schema`](Self::create_tenant_schema), whose sessions search its schema
+    /// first.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`RoleNotFound`](TmpPostgrustError::RoleNotFound) unless the tenant was created,
+    /// or if looking it up fails.
+    pub fn connection_string_for_schema(&self, name: &str) -> TmpPostgrustResult<String> {
+        self.connection_string_for(name, &self.dbname, false)
+    }
+
+    /// Server processes of the instance from `pg_stat_activity`, besides the session asking,
+    /// e.g. to count connections or find sessions stuck idle in a transaction.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn activity(&self) -> TmpPostgrustResult<Vec<Backend>> {
+        let output = self.run_pg_tool("psql", activity::activity_query_args())?;
+        Ok(activity::parse_activity(&output.stdout))
+    }
+
+    /// Process ids of the client connections to the instance.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn backend_pids(&self) -> TmpPostgrustResult<Vec<u32>> {
+        Ok(self
+            .activity()?
+            .into_iter()
+            .filter(Backend::is_client)
+            .map(|backend| backend.pid)
+            .collect())
+    }
+
+    /// Terminate the server process `pid` with `pg_terminate_backend`, closing its
+    /// connection. Returns whether a process was signalled.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn terminate_backend(&self, pid: u32) -> TmpPostgrustResult<bool> {
+        let query = activity::terminate_query(pid);
+        let output = self.run_pg_tool("psql", sql::unaligned_query_args(&query))?;
+        Ok(output.stdout.trim() == "t")
+    }
+
+    /// Terminate every session connected to `dbname`, e.g. connections leaked by a pool or
+    /// before dropping the database, and wait until they disconnected. Returns the number of
+    /// terminated sessions.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool), or with
+    /// [`ConditionTimeout`](TmpPostgrustError::ConditionTimeout) if sessions are still connected
+    /// after 10 seconds.
+    pub fn terminate_connections(&self, dbname: &str) -> TmpPostgrustResult<usize> {
+        let query = activity::terminate_database_query(dbname);
+        let output = self.run_pg_tool("psql", sql::unaligned_query_args(&query))?;
+        let terminated = output.stdout.trim().parse().unwrap_or_default();
+        if terminated > 0 {
+            self.wait_until(
+                &activity::no_sessions_predicate(dbname),
+                activity::TERMINATE_TIMEOUT,
+            )?;
+        }
+        Ok(terminated)
+    }
+
+    /// Background writer counters and vacuum activity of user tables, for tests asserting on
+    /// bloat or vacuum behaviour.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn background_activity(&self) -> TmpPostgrustResult<BackgroundActivity> {
+        let output = self.run_pg_tool("psql", background::activity_query_args())?;
+        Ok(background::parse_activity(&output.stdout))
+    }
+
+    /// Current position in the write-ahead log, to be compared with a later position with
+    /// [`wal_bytes_since`](Self::wal_bytes_since).
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool), or with
+    /// [`InvalidWalLsn`](TmpPostgrustError::InvalidWalLsn) if the server reports a position that
+    /// cannot be parsed.
+    pub fn current_wal_lsn(&self) -> TmpPostgrustResult<WalLsn> {
+        let output = self.run_pg_tool("psql", wal::current_lsn_query_args())?;
+        output
+            .stdout
+            .parse()
+            .map_err(TmpPostgrustError::InvalidWalLsn)
+    }
+
+    /// Bytes of write-ahead log generated since `lsn`, e.g. to assert that a read-only code
+    /// path generates none. Writes of every session count, including those of autovacuum and
+    /// checkpoints.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`current_wal_lsn`](Self::current_wal_lsn).
+    pub fn wal_bytes_since(&self, lsn: WalLsn) -> TmpPostgrustResult<u64> {
+        Ok(self.current_wal_lsn()?.bytes_since(lsn))
+    }
+
+    /// Connection details, paths, server version and non-default settings of the instance.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn metadata(&self) -> TmpPostgrustResult<InstanceMetadata> {
+        let output = self.run_pg_tool("psql", metadata::server_query_args())?;
+        let mut metadata = InstanceMetadata {
+            label: self.label.clone(),
+            connection_string: self.connection_string.clone(),
+            host: self.auth.host.clone(),
+            port: self.auth.port,
+            user: self.auth.user.clone(),
+            dbname: self.dbname.clone(),
+            data_directory: self.data_directory.path().to_path_buf(),
+            ..InstanceMetadata::default()
+        };
+        metadata::parse_server(&output.stdout, &mut metadata);
+        Ok(metadata)
+    }
+
+    /// Types of the running server processes besides client backends as listed in
+    /// `pg_stat_activity`, e.g. `pg_cron launcher` for the worker of `pg_cron`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn background_workers(&self) -> TmpPostgrustResult<Vec<String>> {
+        let output = self.run_pg_tool("psql", workers::workers_query_args())?;
+        Ok(workers::parse_workers(&output.stdout))
+    }
+
+    /// Check that the `extensions` created in the database match their installed libraries.
+    pub(crate) fn verify_extension_versions(&self, extensions: &[&str]) -> TmpPostgrustResult<()> {
+        if extensions.is_empty() {
+            return Ok(());
+        }
+        let query = workers::versions_query(extensions);
+        let output = self.run_pg_tool("psql", sql::unaligned_query_args(&query))?;
+        workers::check_versions(extensions, &output.stdout)
+    }
+
+    /// Wait until every one of the `expected` background workers is running.
+    pub(crate) fn wait_for_background_workers(
+        &self,
+        expected: &[String],
+        timeout: Duration,
+    ) -> TmpPostgrustResult<()> {
+        if expected.is_empty() {
+            return Ok(());
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let running = self.background_workers()?;
+            let missing = workers::missing_workers(expected, &running);
+            if missing.is_empty() {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(TmpPostgrustError::BackgroundWorkersMissing(
+                    missing.into_iter().map(str::to_string).collect(),
+                ));
+            }
+            std::thread::sleep(workers::WORKER_POLL_INTERVAL);
+        }
+    }
+
+    /// [`metadata`](Self::metadata) as pretty printed JSON, to be written to a file handed to
+    /// tooling outside of the test process.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`metadata`](Self::metadata), or with
+    /// [`MetadataSerializationFailed`](TmpPostgrustError::MetadataSerializationFailed) if the
+    /// metadata cannot be serialized.
+    #[cfg(feature = "serde")]
+    pub fn metadata_json(&self) -> TmpPostgrustResult<String> {
+        serde_json::to_string_pretty(&self.metadata()?)
+            .map_err(TmpPostgrustError::MetadataSerializationFailed)
+    }
+
+    /// Statements logged by pgaudit in the order they ran. Requires a factory built with
+    /// [`Preset::Pgaudit`](crate::preset::Preset::Pgaudit).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReadCsvLogFailed`](TmpPostgrustError::ReadCsvLogFailed) if the server log
+    /// cannot be read.
+    pub fn audit_events(&self) -> TmpPostgrustResult<Vec<AuditEvent>> {
+        audit::read_events(self.data_directory.path()).map_err(TmpPostgrustError::ReadCsvLogFailed)
+    }
+
+    /// Plans of statements slower than the threshold of
+    /// [`with_auto_explain`](crate::TmpPostgrustFactoryBuilder::with_auto_explain) in the
+    /// order they finished, except those of the superuser setting up the instance.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReadCsvLogFailed`](TmpPostgrustError::ReadCsvLogFailed) if the server log
+    /// cannot be read.
+    pub fn slow_query_plans(&self) -> TmpPostgrustResult<Vec<SlowQueryPlan>> {
+        explain::read_plans(self.data_directory.path()).map_err(TmpPostgrustError::ReadCsvLogFailed)
+    }
+
+    /// Statements sent to the instance in the order they were logged, except those of the
+    /// superuser setting it up. Requires a factory built
+    /// [recording statements](crate::TmpPostgrustFactoryBuilder::with_statement_recording).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ReadCsvLogFailed`](TmpPostgrustError::ReadCsvLogFailed) if the server log
+    /// cannot be read.
+    pub fn recorded_statements(&self) -> TmpPostgrustResult<Vec<RecordedStatement>> {
+        record::read_statements(self.data_directory.path())
+            .map_err(TmpPostgrustError::ReadCsvLogFailed)
+    }
+
+    /// Number of times each distinct query was sent to the instance, from the
+    /// [recorded statements](Self::recorded_statements).
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`recorded_statements`](Self::recorded_statements).
+    pub fn query_fingerprints(&self) -> TmpPostgrustResult<QueryFingerprints> {
+        Ok(QueryFingerprints::from_statements(
+            &self.recorded_statements()?,
+        ))
+    }
+
+    /// Start counting the statements sent to the instance, to be checked against `budget` with
+    /// [`BudgetScope::assert`], e.g. around a single request to catch queries issued once
+    /// per row. Requires a factory built
+    /// [recording statements](crate::TmpPostgrustFactoryBuilder::with_statement_recording).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if the start of the budget
+    /// cannot be marked in the server log.
+    pub fn start_budget(&self, budget: Budget) -> TmpPostgrustResult<BudgetScope> {
+        BudgetScope::start(
+            budget,
+            self.auth.clone(),
+            &self.dbname,
+            self.data_directory.path().to_path_buf(),
+            self.verbosity,
+        )
+    }
+
+    /// Save the [recorded statements](Self::recorded_statements) to `path` as a SQL script,
+    /// with the parameters of prepared statements filled in, which
+    /// [`replay_statements`](Self::replay_statements) runs against another instance. Returns
+    /// the number of statements.
+    ///
+    /// # Errors
+    ///
+    /// Fails like [`recorded_statements`](Self::recorded_statements), or with
+    /// [`WriteRecordingFailed`](TmpPostgrustError::WriteRecordingFailed) if `path` cannot be
+    /// written.
+    pub fn record_statements(&self, path: impl AsRef<Path>) -> TmpPostgrustResult<usize> {
+        let statements = self.recorded_statements()?;
+        std::fs::write(path, record::replay_script(&statements))
+            .map_err(TmpPostgrustError::WriteRecordingFailed)?;
+        Ok(statements.len())
+    }
+
+    /// Run the statements saved to `path` by [`record_statements`](Self::record_statements) one
+    /// after another in a single session as the database user, continuing after statements
+    /// that fail. Returns the output of `psql`, listing the errors on stderr.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot be run or cannot read `path`, see [`run_pg_tool`](Self::run_pg_tool).
+    /// Failing statements only show up in the output.
+    pub fn replay_statements(&self, path: impl AsRef<Path>) -> TmpPostgrustResult<ProcessCapture> {
+        self.run_pg_tool(
+            "psql",
+            [
+                OsStr::new("-Xq"),
+                OsStr::new("-f"),
+                path.as_ref().as_os_str(),
+            ],
+        )
+    }
+
+    /// DDL commands run in the database in the order they ran. Requires a factory built with
+    /// [`with_ddl_audit`](crate::builder::TmpPostgrustFactoryBuilder::with_ddl_audit).
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn ddl_history(&self) -> TmpPostgrustResult<Vec<DdlCommand>> {
+        let output = self.run_pg_tool("psql", ddl_audit::history_query_args())?;
+        Ok(ddl_audit::parse_history(&output.stdout))
+    }
+
+    /// Restart every sequence of the database at the start value configured with
+    /// [`with_sequence_start`](crate::builder::TmpPostgrustFactoryBuilder::with_sequence_start),
+    /// or at its own start value, so ids allocated after this are the same in every run.
+    /// Sequences of system schemas are left alone. Returns the number of restarted sequences.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `psql` cannot run the query, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn reset_sequences(&self) -> TmpPostgrustResult<usize> {
+        let output = self.run_pg_tool("psql", sequences::reset_query_args())?;
+        Ok(output.stdout.trim().parse().unwrap_or_default())
+    }
+
+    /// Make `app_now()` return `timestamp`, e.g. `"2020-02-29 12:00:00+00"`, in sessions
+    /// opened afterwards. Requires a factory built with
+    /// [`with_fake_time`](crate::builder::TmpPostgrustFactoryBuilder::with_fake_time).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if the fake time cannot be set.
+    pub fn set_fake_time(&self, timestamp: &str) -> TmpPostgrustResult<()> {
+        exec_psql(
+            &self.auth,
+            &self.dbname,
+            &fake_time::set_fake_time_sql(&self.dbname, Some(timestamp)),
+            self.verbosity,
+        )
+    }
+
+    /// Make `app_now()` return the real time again in sessions opened afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if the fake time cannot be
+    /// cleared.
+    pub fn clear_fake_time(&self) -> TmpPostgrustResult<()> {
+        exec_psql(
+            &self.auth,
+            &self.dbname,
+            &fake_time::set_fake_time_sql(&self.dbname, None),
+            self.verbosity,
+        )
+    }
+
+    /// Leave the server running and its directories in place when the guard is dropped, so it
+    /// can be inspected with external tools after the test finished. Combined with a named
+    /// instance the connection string stays the same between runs. Only the database is kept
+    /// for an instance on the [shared server](crate::TmpPostgrustFactoryBuilder::with_shared_server)
+    /// of its factory, until the server stops.
+    pub fn persist(&mut self) {
+        info!(
+            "persisting instance {}, connect with: {}",
+            self.label, self.connection_string
+        );
+        self.persisted = true;
+        if let Some(mut database) = self.shared_database.take() {
+            database.keep();
+            return;
+        }
+        self.data_directory.keep();
+        self.socket_dir.keep();
+        if let Some(socket_link) = &self.socket_link {
+            socket_link.keep();
+        }
+        if let Some(io_cgroup) = &self.io_cgroup {
+            io_cgroup.keep();
+        }
+    }
+
+    /// Stop managing the server, leaving it running and writing its process id, connection
+    /// details and paths to `state_file`, so another process, e.g. a test binary using a
+    /// database booted by a fixture preparation binary, can adopt it with
+    /// [`attach`](crate::detach::attach).
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`SharedServerUnsupported`](TmpPostgrustError::SharedServerUnsupported) for
+    /// an instance on the shared server of its factory, or if `state_file` cannot be written.
+    pub fn detach(mut self, state_file: impl AsRef<Path>) -> TmpPostgrustResult<DetachedInstance> {
+        let Some(postgres_process) = &self.postgres_process else {
+            return Err(TmpPostgrustError::SharedServerUnsupported("detach"));
+        };
+        let detached = DetachedInstance {
+            pid: postgres_process.id(),
+            label: self.label.clone(),
+            connection_string: self.connection_string.clone(),
+            host: self.auth.host.clone(),
+            port: self.auth.port,
+            user: self.auth.user.clone(),
+            dbname: self.dbname.clone(),
+            data_directory: self.data_directory.path().to_path_buf(),
+        };
+        detached.write(state_file.as_ref())?;
+        self.drop_created_databases();
+        info!("detached instance {} (pid {})", self.label, detached.pid);
+        self.data_directory.keep();
+        self.socket_dir.keep();
+        if let Some(socket_link) = &self.socket_link {
+            socket_link.keep();
+        }
+        if let Some(io_cgroup) = &self.io_cgroup {
+            io_cgroup.keep();
+        }
+        self.persisted = true;
+        Ok(detached)
+    }
+
+    /// Stop the server and return the resources it used, which are also added to the
+    /// [`stats`](crate::TmpPostgrustFactory::stats) of the factory.
+    pub fn stop(mut self) -> ResourceUsage {
+        self.stopped = true;
+        self.shutdown()
+    }
+
+    /// Stop the server cleanly and check the data checksums of its data directory with
+    /// `pg_checksums`, reporting every corrupted block.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ChecksumsDisabled`](TmpPostgrustError::ChecksumsDisabled) unless the
+    /// cluster was initialized
+    /// [with checksums](crate::TmpPostgrustFactoryBuilder::with_data_checksums), or with
+    /// [`SharedServerUnsupported`](TmpPostgrustError::SharedServerUnsupported) for an instance
+    /// on the shared server of its factory.
+    pub fn verify_checksums(mut self) -> TmpPostgrustResult<ChecksumReport> {
+        if self.shared_database.is_some() {
+            return Err(TmpPostgrustError::SharedServerUnsupported(
+                "verify_checksums",
+            ));
+        }
+        self.stopped = true;
+        self.shutdown();
+        checksums::checksum_report(self.run_pg_tool(
+            "pg_checksums",
+            checksums::check_args(self.data_directory.path()),
+        ))
+    }
+
+    fn shutdown(&mut self) -> ResourceUsage {
+        if let Some(database) = self.shared_database.take() {
+            self.drop_created_databases();
+            let leaks = find_connection_leaks(
+                &self.auth,
+                &self.dbname,
+                &self.label,
+                self.connection_leak_check,
+                self.verbosity,
+            );
+            drop(database);
+            report_connection_leaks(self.connection_leak_check, leaks);
+            return ResourceUsage::default();
+        }
+        let usage = self
+            .registration
+            .as_ref()
+            .map(RegistryEntry::record_usage)
+            .unwrap_or_default();
+        let mut leaks = None;
+        let exit =
+            if let Some(Ok(Some(status))) = self.postgres_process.as_mut().map(Child::try_wait) {
+                error!("postgresql exited early with {}", status);
+                if self.keep_on_crash && !status.success() {
+                    keep_crashed_data_directory(&self.data_directory);
+                }
+                Some(status)
+            } else {
+                self.drop_created_databases();
+                leaks = find_connection_leaks(
+                    &self.auth,
+                    &self.dbname,
+                    &self.label,
+                    self.connection_leak_check,
+                    self.verbosity,
+                );
+                self.stop_process()
+            };
+        self.events.emit(&LifecycleEvent::InstanceStopped {
+            label: self.label.clone(),
+            port: self.auth.port,
+            exit,
+        });
+        report_connection_leaks(self.connection_leak_check, leaks);
+        usage
+    }
+
+    fn drop_created_databases(&self) {
+        self.created_databases
+            .drop_all(&self.auth.as_superuser(), self.verbosity);
+    }
+
+    /// Ask the server to shut down and wait up to [`SHUTDOWN_TIMEOUT`] for it to exit before
+    /// killing it. Failures are logged instead of panicking, as this runs when the guard is
+    /// dropped, possibly while the test is already panicking.
+    fn stop_process(&mut self) -> Option<ExitStatus> {
+        let postgres_process = self.postgres_process.as_mut()?;
+        match postgres_process.terminate() {
+            Ok(()) => {
+                let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+                while Instant::now() < deadline {
+                    match postgres_process.try_wait() {
+                        Ok(Some(status)) => return Some(status),
+                        Ok(None) => std::thread::sleep(readiness::POLL_INTERVAL),
+                        Err(err) => {
+                            error!("failed to wait for postgresql: {}", err);
+                            break;
+                        }
+                    }
+                }
+                warn!(
+                    "postgresql did not shut down within {:?}, killing it",
+                    SHUTDOWN_TIMEOUT
+                );
+            }
+            Err(err) => error!("failed to ask postgresql to shut down: {}", err),
+        }
+        if let Err(err) = postgres_process.kill() {
+            error!("failed to kill postgresql: {}", err);
+        }
+        postgres_process
+            .wait()
+            .map_err(|err| error!("failed to wait for postgresql: {}", err))
+            .ok()
+    }
+
+    /// Start a [`LatencyShim`] in front of the socket of the server that delays every packet by
+    /// `delay` in both directions, for testing clients on a slow network.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`LatencyShimFailed`](TmpPostgrustError::LatencyShimFailed) if the socket of the
+    /// shim cannot be set up.
+    #[cfg(unix)]
+    pub fn latency_shim(&self, delay: Duration) -> TmpPostgrustResult<LatencyShim> {
+        LatencyShim::spawn(
+            &self.temp_root,
+            crate::socket_path(self.socket_dir.path(), self.auth.port),
+            self.auth.port,
+            delay,
+            |host| crate::instance_connection_string(&self.auth, &self.dbname, host),
+        )
+    }
+
+    /// Gather what helps debugging a failed test into `dir` for attaching to the artifacts of
+    /// a CI job: the server log of the [CSV log](crate::TmpPostgrustFactoryBuilder::with_csv_log),
+    /// `postgresql.conf`, `pg_hba.conf`, the most expensive statements of
+    /// `pg_stat_statements` if the extension is installed and a schema dump. Returns the paths
+    /// of the files written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`CollectArtifactsFailed`](TmpPostgrustError::CollectArtifactsFailed) if `dir` or
+    /// one of the files cannot be written, or if dumping the schema fails.
+    pub fn collect_artifacts(&self, dir: impl AsRef<Path>) -> TmpPostgrustResult<Vec<PathBuf>> {
+        artifacts::collect(
+            &self.auth.as_superuser(),
+            &self.dbname,
+            self.data_directory.path(),
+            dir.as_ref(),
+            self.verbosity,
+        )
+    }
+
+    /// Dump the schema of the database with `pg_dump --schema-only`, useful for comparing a
+    /// migrated schema against a committed golden file.
+    ///
+    /// # Errors
+    ///
+    /// Fails if `pg_dump` cannot dump the database, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn schema_sql(&self) -> TmpPostgrustResult<String> {
+        Ok(self.run_pg_tool("pg_dump", ["--schema-only"])?.stdout)
+    }
+
+    /// Rows of `tables`, e.g. `public.items`, or of every table when empty, dumped with
+    /// `pg_dump --data-only` and normalized to a stable order, as compared by
+    /// [`assert_data_matches`](Self::assert_data_matches).
+    ///
+    /// # Errors
+    ///
+    /// Fails if `pg_dump` cannot dump the database, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn data_snapshot(&self, tables: &[&str]) -> TmpPostgrustResult<String> {
+        let output = self.run_pg_tool("pg_dump", golden::dump_args(tables))?;
+        Ok(golden::normalize_dump(&output.stdout))
+    }
+
+    /// Panic with a diff unless the [snapshot](Self::data_snapshot) of `tables` matches the
+    /// golden file `path`. With [`UPDATE_GOLDEN_ENV`](crate::golden::UPDATE_GOLDEN_ENV) set the
+    /// snapshot is written to `path` instead.
+    ///
+    /// # Panics
+    /// When the data does not match or cannot be dumped.
+    pub fn assert_data_matches(&self, path: impl AsRef<Path>, tables: &[&str]) {
+        let snapshot = self.data_snapshot(tables).expect("failed to dump data");
+        if let Err(mismatch) = golden::compare(path.as_ref(), &snapshot) {
+            panic!("{}", mismatch);
+        }
+    }
+
+    /// Poll the SQL boolean expression `predicate`, e.g.
+    /// `EXISTS (SELECT FROM jobs WHERE state = 'done')`, with exponential backoff until it is
+    /// true, for waiting on asynchronous workers writing their results into the database.
+    /// Evaluation errors, e.g. of a table that does not exist yet, count as false.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ConditionTimeout`](TmpPostgrustError::ConditionTimeout) after `timeout`, or
+    /// if `psql` cannot be run, see [`run_pg_tool`](Self::run_pg_tool).
+    pub fn wait_until(&self, predicate: &str, timeout: Duration) -> TmpPostgrustResult<()> {
+        let query = wait::predicate_query(predicate);
+        let started = Instant::now();
+        let mut backoff = Backoff::new();
+        loop {
+            let last_error = match self.run_pg_tool("psql", sql::unaligned_query_args(&query)) {
+                Ok(output) if output.stdout.trim() == "t" => return Ok(()),
+                Ok(_) => None,
+                Err(TmpPostgrustError::PgToolFailed(capture)) => {
+                    Some(capture.stderr.trim().to_string())
+                }
+                Err(err) => return Err(err),
+            };
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(TmpPostgrustError::ConditionTimeout {
+                    predicate: predicate.to_string(),
+                    timeout,
+                    last_error,
+                });
+            }
+            let delay = backoff.next_delay(remaining);
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Connection string for connecting as the superuser that set up the instance, e.g. for
+    /// setup that a [non-superuser](crate::instance::InstanceOptions::with_superuser) database
+    /// user is not allowed to do.
+    #[must_use]
+    pub fn superuser_connection_string(&self) -> String {
+        ConnectionInfo::new(&self.auth.as_superuser(), &self.dbname).connection_string()
+    }
+
+    /// Connection string for connecting as `user` to `dbname`, e.g. to a second database or as
+    /// a role created by the test. With `create_missing` the role and the database are created
+    /// if they do not exist, the database being owned by `user`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`RoleNotFound`](TmpPostgrustError::RoleNotFound) or
+    /// [`DatabaseNotFound`](TmpPostgrustError::DatabaseNotFound) unless both exist or
+    /// `create_missing` is set, or with [`PgToolFailed`](TmpPostgrustError::PgToolFailed) if
+    /// looking them up or creating them fails.
+    pub fn connection_string_for(
+        &self,
+        user: &str,
+        dbname: &str,
+        create_missing: bool,
+    ) -> TmpPostgrustResult<String> {
+        let query = connection::existence_query(user, dbname);
+        let output = self.run_pg_tool("psql", sql::unaligned_query_args(&query))?;
+        let (role_exists, database_exists) = connection::parse_existence(&output.stdout);
+        if !role_exists {
+            if !create_missing {
+                return Err(TmpPostgrustError::RoleNotFound(user.to_string()));
+            }
+            let sql = connection::create_role_sql(user);
+            exec_psql(
+                &self.auth.as_superuser(),
+                &self.dbname,
+                &sql,
+                self.verbosity,
+            )?;
+        }
+        if !database_exists {
+            if !create_missing {
+                return Err(TmpPostgrustError::DatabaseNotFound(dbname.to_string()));
+            }
+            let sql = connection::create_database_sql(user, dbname);
+            exec_psql(
+                &self.auth.as_superuser(),
+                &self.dbname,
+                &sql,
+                self.verbosity,
+            )?;
+        }
+        let info = self.connection_info();
+        let password = if user == info.user {
+            info.password.clone()
+        } else {
+            None
+        };
+        Ok(ConnectionInfo {
+            user: user.to_string(),
+            password,
+            dbname: dbname.to_string(),
+            ..info
+        }
+        .connection_string())
+    }
+
+    /// `jdbc:postgresql://` URL of the instance with the credentials as properties.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`TcpRequired`](TmpPostgrustError::TcpRequired) unless the server listens on
+    /// TCP, as the JDBC driver cannot connect to unix sockets.
+    pub fn jdbc_url(&self) -> TmpPostgrustResult<String> {
+        self.tcp_connection_info()?.jdbc_url()
+    }
+
+    /// Details for connecting over TCP, for tools that cannot use unix sockets.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`TcpRequired`](TmpPostgrustError::TcpRequired) unless the factory was built
+    /// [`with_tcp`](crate::builder::TmpPostgrustFactoryBuilder::with_tcp).
+    pub fn tcp_connection_info(&self) -> TmpPostgrustResult<ConnectionInfo> {
+        if !self.tcp {
+            return Err(TmpPostgrustError::TcpRequired);
+        }
+        Ok(ConnectionInfo {
+            host: PathBuf::from(TCP_HOST),
+            ..self.connection_info()
+        })
+    }
+
+    /// `postgresql://` connection string for connecting over TCP, e.g.
+    /// `postgresql://demo_user@127.0.0.1:41234/demo`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`TcpRequired`](TmpPostgrustError::TcpRequired) unless the server listens on
+    /// TCP.
+    pub fn tcp_connection_string(&self) -> TmpPostgrustResult<String> {
+        Ok(self.tcp_connection_info()?.connection_string())
+    }
+
+    /// Connection string in the libpq keyword/value format, e.g.
+    /// `host=/tmp/socket port=5432 user=demo_user dbname=demo`, for tools that do not accept the
+    /// URL in [`connection_string`](Self::connection_string).
+    #[must_use]
+    pub fn kv_connection_string(&self) -> String {
+        self.connection_info().kv_connection_string()
+    }
+
+    /// Host, port, user, password and database of the instance.
+    #[must_use]
+    pub fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo::new(&self.auth, &self.dbname)
+    }
+
+    /// `PGHOST`, `PGPORT`, `PGUSER` and `PGDATABASE` (plus `PGPASSWORD` when a password is set)
+    /// pointing libpq based tools at this instance, e.g. for `Command::envs`.
+    #[must_use]
+    pub fn env_vars(&self) -> BTreeMap<&'static str, String> {
+        self.auth.libpq_envs(&self.dbname).into_iter().collect()
+    }
+
+    /// [`env_vars`](Self::env_vars) formatted as shell `export` statements.
+    #[must_use]
+    pub fn env_exports(&self) -> String {
+        self.auth.shell_exports(&self.dbname)
+    }
+    /// Create a `Command` for `program` with the [`env_vars`](Self::env_vars) of this instance
+    /// set, for end-to-end tests of applications that read their database from the environment.
+    #[must_use]
+    pub fn command<S: AsRef<OsStr>>(&self, program: S) -> Command {
+        let mut command = Command::new(program);
+        command.envs(self.auth.libpq_envs(&self.dbname));
+        command
+    }
 }
 
 /// Signal that the process needs to end.
 impl Drop for ProcessGuard {
     fn drop(&mut self) {
-        signal::kill(
-            Pid::from_raw(self.postgres_process.id().try_into().unwrap()),
-            Signal::SIGINT,
-        )
-        .unwrap();
-        self.postgres_process.wait().unwrap();
+        if self.persisted {
+            self.drop_created_databases();
+            return;
+        }
+        if self.stopped {
+            return;
+        }
+        artifacts::collect_on_panic(
+            &self.artifact_sinks,
+            &self.auth.as_superuser(),
+            &self.dbname,
+            self.data_directory.path(),
+            &self.label,
+            self.verbosity,
+        );
+        self.shutdown();
     }
 }
@@ -0,0 +1,96 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Stops a postgresql server process, gracefully where the platform allows it.
+pub(crate) trait ProcessTerminator {
+    /// Ask the process to shut down.
+    ///
+    /// With the `unix-signals` feature this sends `SIGINT`, requesting a "fast" shutdown that
+    /// lets postgresql clean up its socket and lock files. Without it the process is killed
+    /// outright using `Child::kill`.
+    fn terminate(&mut self) -> io::Result<()>;
+}
+
+#[cfg(feature = "unix-signals")]
+fn send_sigint(pid: u32) -> io::Result<()> {
+    use std::convert::TryInto;
+
+    use nix::sys::signal::{self, Signal};
+    use nix::unistd::Pid;
+
+    signal::kill(Pid::from_raw(pid.try_into().unwrap()), Signal::SIGINT).map_err(io::Error::from)
+}
+
+impl ProcessTerminator for std::process::Child {
+    #[cfg(feature = "unix-signals")]
+    fn terminate(&mut self) -> io::Result<()> {
+        send_sigint(self.id())
+    }
+
+    #[cfg(not(feature = "unix-signals"))]
+    fn terminate(&mut self) -> io::Result<()> {
+        self.kill()
+    }
+}
+
+#[cfg(feature = "tokio-process")]
+impl ProcessTerminator for tokio::process::Child {
+    #[cfg(feature = "unix-signals")]
+    fn terminate(&mut self) -> io::Result<()> {
+        // The process has already been reaped if it has no id.
+        self.id().map_or(Ok(()), send_sigint)
+    }
+
+    #[cfg(not(feature = "unix-signals"))]
+    fn terminate(&mut self) -> io::Result<()> {
+        self.start_kill()
+    }
+}
+
+/// Whether `pid` is the postmaster that wrote the socket `lock_file`, i.e. the data directory
+/// named in the lock file records `pid` in its `postmaster.pid` and, on Linux, the process runs
+/// the `postgres` binary. A server that was killed leaves its lock file behind, and its pid may
+/// since have been reused by an unrelated process.
+pub(crate) fn is_postmaster(pid: u32, lock_file: &Path) -> bool {
+    let Some(data_directory) = std::fs::read_to_string(lock_file)
+        .ok()
+        .and_then(|contents| contents.lines().nth(1).map(PathBuf::from))
+    else {
+        return false;
+    };
+    let recorded = std::fs::read_to_string(data_directory.join("postmaster.pid"))
+        .ok()
+        .and_then(|contents| contents.lines().next()?.trim().parse::<u32>().ok());
+    recorded == Some(pid) && runs_postgres(pid)
+}
+
+#[cfg(target_os = "linux")]
+fn runs_postgres(pid: u32) -> bool {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    std::fs::read(format!("/proc/{pid}/cmdline")).is_ok_and(|cmdline| {
+        cmdline
+            .split(|&byte| byte == 0)
+            .next()
+            .is_some_and(|program| {
+                Path::new(OsStr::from_bytes(program)).file_name() == Some(OsStr::new("postgres"))
+            })
+    })
+}
+
+/// Without `/proc` the process cannot be inspected, the `postmaster.pid` check has to do.
+#[cfg(not(target_os = "linux"))]
+fn runs_postgres(_pid: u32) -> bool {
+    true
+}
+
+/// Ask the server with the given process id to perform a fast shutdown, ignoring processes
+/// that have already exited.
+#[cfg(feature = "unix-signals")]
+pub(crate) fn terminate_pid(pid: u32) -> io::Result<()> {
+    match send_sigint(pid) {
+        Err(err) if err.raw_os_error() == Some(nix::errno::Errno::ESRCH as i32) => Ok(()),
+        result => result,
+    }
+}
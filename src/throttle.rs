@@ -0,0 +1,232 @@
+use std::fmt::Write as _;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::{info, warn};
+
+/// Directory the cgroups of throttled instances are created in by default.
+pub const DEFAULT_CGROUP_PARENT: &str = "/sys/fs/cgroup/tmp-postgrust";
+
+/// Scheduling class and priority of the disk I/O of a server, set with `ionice`. Levels range
+/// from 0, the highest priority, to 7.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    /// Served before every other process, which requires root.
+    Realtime(u8),
+    /// Served by level among the other processes, the class of every process by default.
+    BestEffort(u8),
+    /// Only served when no other process needs the disk.
+    Idle,
+}
+
+impl IoPriority {
+    fn ionice_args(self) -> Vec<String> {
+        match self {
+            IoPriority::Realtime(level) => vec!["-c1".into(), format!("-n{level}")],
+            IoPriority::BestEffort(level) => vec!["-c2".into(), format!("-n{level}")],
+            IoPriority::Idle => vec!["-c3".into()],
+        }
+    }
+}
+
+/// Limits on the disk I/O of the servers of a factory, for simulating a slow database, e.g. to
+/// test timeout handling and query cancellation, without touching application code.
+///
+/// Bandwidth and operation limits are applied with the `io.max` setting of a cgroup v2 created
+/// for every instance in the [cgroup parent](Self::with_cgroup_parent), which has to be
+/// writable, e.g. delegated by systemd or created by root beforehand. They only apply to data
+/// directories on a block device, which a
+/// [size limited](crate::TmpPostgrustFactoryBuilder::with_data_directory_size) data directory
+/// always is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoThrottle {
+    read_bps: Option<u64>,
+    write_bps: Option<u64>,
+    read_iops: Option<u64>,
+    write_iops: Option<u64>,
+    priority: Option<IoPriority>,
+    cgroup_parent: Option<PathBuf>,
+}
+
+impl IoThrottle {
+    /// Throttle that does not limit anything yet.
+    #[must_use]
+    pub fn new() -> Self {
+        IoThrottle::default()
+    }
+
+    /// Limit reads to `bytes_per_second`.
+    #[must_use]
+    pub fn with_read_bps(mut self, bytes_per_second: u64) -> Self {
+        self.read_bps = Some(bytes_per_second);
+        self
+    }
+
+    /// Limit writes to `bytes_per_second`.
+    #[must_use]
+    pub fn with_write_bps(mut self, bytes_per_second: u64) -> Self {
+        self.write_bps = Some(bytes_per_second);
+        self
+    }
+
+    /// Limit reads to `operations_per_second`.
+    #[must_use]
+    pub fn with_read_iops(mut self, operations_per_second: u64) -> Self {
+        self.read_iops = Some(operations_per_second);
+        self
+    }
+
+    /// Limit writes to `operations_per_second`.
+    #[must_use]
+    pub fn with_write_iops(mut self, operations_per_second: u64) -> Self {
+        self.write_iops = Some(operations_per_second);
+        self
+    }
+
+    /// Run servers with `ionice` at `priority`, which needs neither cgroups nor a block device
+    /// but only slows servers down while other processes use the disk.
+    #[must_use]
+    pub fn with_priority(mut self, priority: IoPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Create the cgroups of instances in `cgroup_parent` instead of
+    /// [`DEFAULT_CGROUP_PARENT`].
+    #[must_use]
+    pub fn with_cgroup_parent(mut self, cgroup_parent: impl Into<PathBuf>) -> Self {
+        self.cgroup_parent = Some(cgroup_parent.into());
+        self
+    }
+
+    /// Arguments for `ionice` to run servers with, if their priority is changed.
+    pub(crate) fn ionice_args(&self) -> Option<Vec<String>> {
+        self.priority.map(IoPriority::ionice_args)
+    }
+
+    /// Value of `io.max` limiting the block device `device`, or `None` without limits.
+    fn io_max(&self, device: &str) -> Option<String> {
+        let limits = [
+            ("rbps", self.read_bps),
+            ("wbps", self.write_bps),
+            ("riops", self.read_iops),
+            ("wiops", self.write_iops),
+        ];
+        let mut io_max = device.to_string();
+        for (key, limit) in limits {
+            if let Some(limit) = limit {
+                let _ = write!(io_max, " {key}={limit}");
+            }
+        }
+        (io_max.len() > device.len()).then_some(io_max)
+    }
+
+    /// Create the cgroup `name` limiting the device of `data_directory`, unless neither
+    /// bandwidth nor operations are limited.
+    pub(crate) fn create_cgroup(
+        &self,
+        data_directory: &Path,
+        name: &str,
+    ) -> io::Result<Option<IoCgroup>> {
+        if self.io_max("").is_none() {
+            return Ok(None);
+        }
+        let io_max = self.io_max(&block_device(data_directory)?).unwrap();
+        let parent = self
+            .cgroup_parent
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CGROUP_PARENT));
+        IoCgroup::create(&parent, name, &io_max).map(Some)
+    }
+}
+
+/// `major:minor` of the whole disk holding `path`, as `io.max` does not accept partitions.
+#[cfg(target_os = "linux")]
+fn block_device(path: &Path) -> io::Result<String> {
+    let dev = path.metadata()?.dev();
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0fff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0x00ff);
+    let sys_dev = PathBuf::from(format!("/sys/dev/block/{major}:{minor}"));
+    if !sys_dev.exists() {
+        return Err(io::Error::other(format!(
+            "{} is not on a block device",
+            path.display()
+        )));
+    }
+    if !sys_dev.join("partition").exists() {
+        return Ok(format!("{major}:{minor}"));
+    }
+    let disk = sys_dev.canonicalize()?.join("../dev");
+    Ok(std::fs::read_to_string(disk)?.trim().to_string())
+}
+
+/// cgroups only exist on Linux, elsewhere I/O cannot be throttled.
+#[cfg(not(target_os = "linux"))]
+fn block_device(_path: &Path) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "I/O throttling requires cgroups, which only exist on Linux",
+    ))
+}
+
+/// Enable the io controller for the children of `cgroup` unless it already is.
+fn enable_io_controller(cgroup: &Path) -> io::Result<()> {
+    let subtree_control = cgroup.join("cgroup.subtree_control");
+    let enabled = std::fs::read_to_string(&subtree_control)?;
+    if enabled
+        .split_whitespace()
+        .any(|controller| controller == "io")
+    {
+        return Ok(());
+    }
+    std::fs::write(subtree_control, "+io")
+}
+
+/// Cgroup limiting the I/O of one instance, removed when dropped unless it has been kept.
+#[derive(Debug)]
+pub(crate) struct IoCgroup {
+    path: PathBuf,
+    remove_on_drop: AtomicBool,
+}
+
+impl IoCgroup {
+    fn create(parent: &Path, name: &str, io_max: &str) -> io::Result<Self> {
+        std::fs::create_dir_all(parent)?;
+        if let Some(grandparent) = parent.parent() {
+            enable_io_controller(grandparent)?;
+        }
+        enable_io_controller(parent)?;
+        let path = parent.join(name);
+        std::fs::create_dir(&path)?;
+        let cgroup = IoCgroup {
+            path,
+            remove_on_drop: AtomicBool::new(true),
+        };
+        std::fs::write(cgroup.path.join("io.max"), io_max)?;
+        info!("limiting I/O of cgroup {:?} to {}", cgroup.path, io_max);
+        Ok(cgroup)
+    }
+
+    /// File a process is moved into the cgroup with by writing its process id.
+    pub(crate) fn procs_path(&self) -> PathBuf {
+        self.path.join("cgroup.procs")
+    }
+
+    /// Leave the cgroup in place when dropped.
+    pub(crate) fn keep(&self) {
+        self.remove_on_drop.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for IoCgroup {
+    fn drop(&mut self) {
+        if self.remove_on_drop.load(Ordering::SeqCst) {
+            if let Err(err) = std::fs::remove_dir(&self.path) {
+                warn!("failed to remove cgroup {:?}: {}", self.path, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// Resources consumed by a postgresql server, including backends it already reaped.
+///
+/// Collected from `/proc`, so every field is zero on platforms other than Linux. Peak memory
+/// only covers the postmaster and backends still running when the sample is taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// User and system cpu time.
+    pub cpu_time: Duration,
+    /// Largest resident set size of a single process in bytes.
+    pub max_rss_bytes: u64,
+    /// Bytes read from storage.
+    pub read_bytes: u64,
+    /// Bytes written to storage.
+    pub write_bytes: u64,
+}
+
+impl AddAssign for ResourceUsage {
+    fn add_assign(&mut self, other: ResourceUsage) {
+        self.cpu_time += other.cpu_time;
+        self.max_rss_bytes = self.max_rss_bytes.max(other.max_rss_bytes);
+        self.read_bytes += other.read_bytes;
+        self.write_bytes += other.write_bytes;
+    }
+}
+
+/// Resource usage of the server `pid` and its backends.
+#[cfg(target_os = "linux")]
+pub(crate) fn sample(pid: u32) -> ResourceUsage {
+    let mut usage = proc_usage(pid, true);
+    for child in child_pids(pid) {
+        usage += proc_usage(child, false);
+    }
+    usage
+}
+
+/// Resource usage of the server `pid` and its backends.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn sample(_pid: u32) -> ResourceUsage {
+    ResourceUsage::default()
+}
+
+/// Clock ticks per second used by `/proc/<pid>/stat`, fixed at 100 for userspace on Linux.
+#[cfg(target_os = "linux")]
+const USER_HZ: u64 = 100;
+
+/// Fields of `/proc/<pid>/stat` following the parenthesized command name, which may itself
+/// contain spaces.
+#[cfg(target_os = "linux")]
+fn stat_fields(pid: u32) -> Option<Vec<String>> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    Some(fields.split_whitespace().map(str::to_string).collect())
+}
+
+#[cfg(target_os = "linux")]
+fn child_pids(pid: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|candidate| {
+            stat_fields(*candidate).is_some_and(|fields| fields[1] == pid.to_string())
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn proc_usage(pid: u32, include_reaped: bool) -> ResourceUsage {
+    let mut usage = ResourceUsage::default();
+    if let Some(fields) = stat_fields(pid) {
+        // The state is field 3 of the stat file, so utime, stime, cutime and cstime
+        // (fields 14 to 17) start at index 11. Reaped children are counted by cutime and
+        // cstime, while their io is already included in the counters of the parent.
+        let times = if include_reaped { 11..15 } else { 11..13 };
+        let ticks: u64 = fields
+            .get(times)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|ticks| ticks.parse::<u64>().ok())
+            .sum();
+        usage.cpu_time = Duration::from_millis(ticks * 1000 / USER_HZ);
+    }
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).unwrap_or_default();
+    if let Some(kilobytes) = proc_value(&status, "VmHWM:") {
+        usage.max_rss_bytes = kilobytes * 1024;
+    }
+    // Reading io counters of a process requires the same permissions as ptrace.
+    let io = std::fs::read_to_string(format!("/proc/{pid}/io")).unwrap_or_default();
+    usage.read_bytes = proc_value(&io, "read_bytes:").unwrap_or_default();
+    usage.write_bytes = proc_value(&io, "write_bytes:").unwrap_or_default();
+    usage
+}
+
+#[cfg(target_os = "linux")]
+fn proc_value(contents: &str, key: &str) -> Option<u64> {
+    contents
+        .lines()
+        .find_map(|line| line.strip_prefix(key))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
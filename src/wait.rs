@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+/// Delay before checking a condition for the second time.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Longest delay between checks of a condition.
+const MAX_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Exponentially growing delays between checks of a condition.
+#[derive(Debug)]
+pub(crate) struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Backoff {
+            next: INITIAL_BACKOFF,
+        }
+    }
+
+    /// Delay before the next check, at most `remaining`.
+    pub(crate) fn next_delay(&mut self, remaining: Duration) -> Duration {
+        let delay = self.next.min(remaining);
+        self.next = (self.next * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+/// Query evaluating `predicate` as a boolean.
+pub(crate) fn predicate_query(predicate: &str) -> String {
+    "SELECT (".to_string() + predicate + ")::boolean"
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::sql::unaligned_query_args;
+
+/// Reports the position up to which write-ahead log has been inserted, which unlike the
+/// written position includes records still in the WAL buffers.
+const CURRENT_LSN_QUERY: &str = "SELECT pg_current_wal_insert_lsn();";
+
+/// Position in the write-ahead log of an instance, as returned by
+/// [`current_wal_lsn`](crate::synchronous::ProcessGuard::current_wal_lsn). Formatted and
+/// parsed as `16/B374D848` like `pg_lsn` values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WalLsn(pub u64);
+
+impl WalLsn {
+    /// Bytes of write-ahead log between `earlier` and this position, zero if `earlier` is
+    /// further ahead.
+    #[must_use]
+    pub fn bytes_since(self, earlier: WalLsn) -> u64 {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl fmt::Display for WalLsn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}/{:X}", self.0 >> 32, self.0 & 0xffff_ffff)
+    }
+}
+
+impl FromStr for WalLsn {
+    type Err = String;
+
+    fn from_str(lsn: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid WAL position `{lsn}`");
+        let (high, low) = lsn.trim().split_once('/').ok_or_else(invalid)?;
+        let high = u64::from_str_radix(high, 16).map_err(|_| invalid())?;
+        let low = u64::from_str_radix(low, 16).map_err(|_| invalid())?;
+        if high > u64::from(u32::MAX) || low > u64::from(u32::MAX) {
+            return Err(invalid());
+        }
+        Ok(WalLsn(high << 32 | low))
+    }
+}
+
+/// Arguments for `psql` to print the current WAL position.
+pub(crate) fn current_lsn_query_args() -> [&'static str; 9] {
+    unaligned_query_args(CURRENT_LSN_QUERY)
+}
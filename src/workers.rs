@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use tracing::info;
+
+use crate::errors::{TmpPostgrustError, TmpPostgrustResult};
+use crate::sql::{quote_literal, split_records, unaligned_query_args};
+use crate::DATABASE_NAME;
+
+/// How often the server is asked whether the expected background workers are running.
+pub(crate) const WORKER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Lists the types of the server processes besides client backends.
+const WORKERS_QUERY: &str = "SELECT DISTINCT backend_type FROM pg_stat_activity \
+                             WHERE backend_type <> 'client backend' ORDER BY 1";
+
+/// Extension running background workers, e.g. `pg_cron`, that is preloaded into every instance
+/// of a factory with
+/// [`with_background_worker_extension`](crate::builder::TmpPostgrustFactoryBuilder::with_background_worker_extension).
+///
+/// Starting an instance waits until the expected workers registered, so tests of scheduled
+/// job logic do not race the workers starting up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackgroundWorkerExtension {
+    pub(crate) library: String,
+    pub(crate) settings: Vec<(String, String)>,
+    pub(crate) workers: Vec<String>,
+    pub(crate) extension: Option<String>,
+}
+
+impl BackgroundWorkerExtension {
+    /// Preload the shared library `library` with `shared_preload_libraries`.
+    #[must_use]
+    pub fn new(library: impl Into<String>) -> Self {
+        BackgroundWorkerExtension {
+            library: library.into(),
+            settings: Vec::new(),
+            workers: Vec::new(),
+            extension: None,
+        }
+    }
+
+    /// `pg_cron`, scheduling jobs in the database of the instances with a `pg_cron launcher`
+    /// worker. The extension is created in the database.
+    #[must_use]
+    pub fn pg_cron() -> Self {
+        BackgroundWorkerExtension::new("pg_cron")
+            .with_setting("cron.database_name", DATABASE_NAME)
+            .with_worker("pg_cron launcher")
+            .with_extension("pg_cron")
+    }
+
+    /// `TimescaleDB`, created in the database of the instances with telemetry disabled.
+    #[must_use]
+    pub fn timescaledb() -> Self {
+        BackgroundWorkerExtension::new("timescaledb")
+            .with_setting("timescaledb.telemetry_level", "off")
+            .with_extension("timescaledb")
+    }
+
+    /// Citus, created in the database of the instances. It is loaded before every other
+    /// library as Citus requires.
+    #[must_use]
+    pub fn citus() -> Self {
+        BackgroundWorkerExtension::new("citus").with_extension("citus")
+    }
+
+    /// pgaudit, created in the database of the instances and logging every class of
+    /// statements.
+    #[must_use]
+    pub fn pgaudit() -> Self {
+        BackgroundWorkerExtension::new("pgaudit")
+            .with_setting("pgaudit.log", "all")
+            .with_extension("pgaudit")
+    }
+
+    /// `auto_explain`, logging the plans of statements running for at least `min_duration`.
+    #[must_use]
+    pub fn auto_explain(min_duration: Duration) -> Self {
+        BackgroundWorkerExtension::new("auto_explain").with_setting(
+            "auto_explain.log_min_duration",
+            format!("{}ms", min_duration.as_millis()),
+        )
+    }
+
+    /// Set `name` to `value` in `postgresql.conf`, e.g. `pg_partman_bgw.interval`.
+    #[must_use]
+    pub fn with_setting(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.settings.push((name.into(), value.into()));
+        self
+    }
+
+    /// Wait for a background worker of type `backend_type`, as listed in
+    /// `pg_stat_activity`, when starting an instance. Only workers connected to shared memory
+    /// and a database are listed there.
+    #[must_use]
+    pub fn with_worker(mut self, backend_type: impl Into<String>) -> Self {
+        self.workers.push(backend_type.into());
+        self
+    }
+
+    /// Create the extension `name` in the database of every instance. Starting an instance
+    /// fails unless the created extension matches the version of the installed library.
+    #[must_use]
+    pub fn with_extension(mut self, name: impl Into<String>) -> Self {
+        self.extension = Some(name.into());
+        self
+    }
+
+    /// Lines of `postgresql.conf` for the settings of the extension.
+    pub(crate) fn config(&self) -> String {
+        let mut config = String::new();
+        for (name, value) in &self.settings {
+            config.push_str(name);
+            config.push_str(" = ");
+            config.push_str(&quote_literal(value));
+            config.push('\n');
+        }
+        config
+    }
+}
+
+/// Arguments for `psql` to list the types of the running server processes.
+pub(crate) fn workers_query_args() -> [&'static str; 9] {
+    unaligned_query_args(WORKERS_QUERY)
+}
+
+/// Parse the types of running server processes.
+pub(crate) fn parse_workers(output: &str) -> Vec<String> {
+    split_records(output)
+        .filter_map(|fields| Some((*fields.first()?).to_string()))
+        .collect()
+}
+
+/// Expected workers missing from `running`.
+pub(crate) fn missing_workers<'a>(expected: &'a [String], running: &[String]) -> Vec<&'a str> {
+    expected
+        .iter()
+        .filter(|worker| !running.contains(worker))
+        .map(String::as_str)
+        .collect()
+}
+
+/// Query listing the created and the installed versions of `extensions`.
+pub(crate) fn versions_query(extensions: &[&str]) -> String {
+    let mut names = String::new();
+    for extension in extensions {
+        if !names.is_empty() {
+            names.push_str(", ");
+        }
+        names.push_str(&quote_literal(extension));
+    }
+    "SELECT name, COALESCE(installed_version, ''), COALESCE(default_version, '') \
+     FROM pg_available_extensions WHERE name IN ("
+        .to_string()
+        + &names
+        + ")"
+}
+
+/// Check that every one of `extensions` was created at the version of its installed library.
+pub(crate) fn check_versions(extensions: &[&str], output: &str) -> TmpPostgrustResult<()> {
+    let rows: Vec<Vec<&str>> = split_records(output).collect();
+    for extension in extensions {
+        let row = rows.iter().find(|fields| fields.first() == Some(extension));
+        let Some(&[_, created, installed]) = row.map(Vec::as_slice) else {
+            return Err(TmpPostgrustError::ExtensionNotAvailable(
+                (*extension).to_string(),
+            ));
+        };
+        if created != installed {
+            return Err(TmpPostgrustError::ExtensionVersionMismatch {
+                name: (*extension).to_string(),
+                created: created.to_string(),
+                installed: installed.to_string(),
+            });
+        }
+        info!("loaded extension {} {}", extension, created);
+    }
+    Ok(())
+}
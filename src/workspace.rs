@@ -0,0 +1,100 @@
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tracing::debug;
+
+/// How often a full workspace checks for a released instance slot.
+const SLOT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Directory shared by the factories of several processes, e.g. every test binary of a
+/// `cargo test` run in a workspace, holding the cached cluster and one lock file per instance
+/// that may run at the same time.
+///
+/// Locks are released by the operating system when a process exits, so a crashed test binary
+/// never holds on to a slot.
+#[derive(Debug)]
+pub(crate) struct Workspace {
+    dir: PathBuf,
+    instance_limit: usize,
+}
+
+impl Workspace {
+    pub(crate) fn new(dir: &Path, instance_limit: usize) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Workspace {
+            dir: dir.to_path_buf(),
+            instance_limit: instance_limit.max(1),
+        })
+    }
+
+    /// Cached cluster shared by all factories using the workspace.
+    pub(crate) fn cache_dir(dir: &Path) -> PathBuf {
+        dir.join("cache")
+    }
+
+    /// State files of the instances kept running between runs.
+    pub(crate) fn reuse_dir(&self) -> PathBuf {
+        self.dir.join("reuse")
+    }
+
+    fn lock_file(&self, name: &str) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(name))
+    }
+
+    /// Wait until no other process is initializing the cached cluster. The lock is held until
+    /// the returned file is dropped.
+    pub(crate) fn lock_cache(&self) -> io::Result<File> {
+        let file = self.lock_file("cache.lock")?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// Claim a free instance slot without waiting.
+    pub(crate) fn try_acquire_slot(&self) -> io::Result<Option<WorkspaceSlot>> {
+        for slot in 0..self.instance_limit {
+            let file = self.lock_file(&format!("slot-{slot}.lock"))?;
+            match file.try_lock() {
+                Ok(()) => {
+                    debug!("acquired workspace slot {}", slot);
+                    return Ok(Some(WorkspaceSlot { _file: file }));
+                }
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(err)) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Claim an instance slot, waiting until one is released.
+    pub(crate) fn acquire_slot(&self) -> io::Result<WorkspaceSlot> {
+        loop {
+            if let Some(slot) = self.try_acquire_slot()? {
+                return Ok(slot);
+            }
+            std::thread::sleep(SLOT_POLL_INTERVAL);
+        }
+    }
+
+    /// Claim an instance slot, waiting until one is released.
+    #[cfg(feature = "tokio-process")]
+    pub(crate) async fn acquire_slot_async(&self) -> io::Result<WorkspaceSlot> {
+        loop {
+            if let Some(slot) = self.try_acquire_slot()? {
+                return Ok(slot);
+            }
+            tokio::time::sleep(SLOT_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Slot of a running instance, released when dropped.
+#[derive(Debug)]
+pub(crate) struct WorkspaceSlot {
+    _file: File,
+}